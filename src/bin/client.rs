@@ -1,19 +1,35 @@
+use std::env;
 use std::error::Error;
 use std::net::UdpSocket;
 
-use dnsrust::packets;
+use dnsrust::{idna, packets};
 
 
-use dnsrust::record::DnsQuestion;
 use dnsrust::record::QueryType;
-use packets::{BytePacketBuffer, DnsPacket};
+use packets::{DnsPacket, VectorPacketBuffer};
 
 
 
 fn main() -> Result<(), Box<dyn Error>> {
-    // Perform an A query for google.com
-    let qname = "yahoo.com";
-    let qtype = QueryType::MX;
+    // The name and type to look up are given on the command line, and
+    // default to the A record of google.com. With `--json` the response is
+    // printed as JSON instead.
+    let (flags, args): (Vec<String>, Vec<String>) = env::args().skip(1).partition(|arg| arg.starts_with("--"));
+    let json = flags.iter().any(|flag| flag == "--json");
+    if let Some(flag) = flags.iter().find(|flag| *flag != "--json") {
+        return Err(format!("Unknown option {}", flag).into());
+    }
+    if json && !cfg!(feature = "serde") {
+        return Err("JSON output needs the serde feature".into());
+    }
+
+    let mut args = args.into_iter();
+    // Names in other scripts are sent in their punycode form
+    let qname = idna::to_ascii(&args.next().unwrap_or_else(|| "google.com".to_string()));
+    let qtype = match args.next() {
+        Some(qtype) => qtype.parse::<QueryType>()?,
+        None => QueryType::A,
+    };
 
     // Using googles public DNS server
     let server = ("8.8.8.8", 53);
@@ -21,43 +37,35 @@ fn main() -> Result<(), Box<dyn Error>> {
     // Bind a UDP socket to an arbitrary port
     let socket = UdpSocket::bind(("0.0.0.0", 43210))?;
 
-    // Build our query packet. It's important that we remember to set the
-    // 'recursion_desired' flag. The packet id is arbitrary.
-    let mut packet = DnsPacket::new();
+    // Build our query packet. This sets the 'recursion_desired' flag for us,
+    // and picks a random id so a spoofed answer can't simply guess it.
+    let mut packet = DnsPacket::query(&qname, qtype);
 
-    packet.header.id = 6666;
-    packet.header.questions = 1;
-    packet.header.recursion_desired = true;
-    packet
-        .questions
-        .push(DnsQuestion::new(qname.to_string(), qtype));
+    // Serialize the packet and send it off to the server using our socket:
+    socket.send_to(&packet.to_bytes()?, server)?;
 
-    // Use our new write method  to write the packet to a buffer...
-    let mut req_buffer = BytePacketBuffer::new();
-    packet.write(&mut req_buffer)?;
-
-    // ... and send it off to the server using our socket:
-    socket.send_to(&req_buffer.buf[0..req_buffer.pos], server)?;
-
-    let mut res_buffer = BytePacketBuffer::new();
-    socket.recv_from(&mut res_buffer.buf)?;
+    let mut res_buffer = VectorPacketBuffer::new();
+    let (len, _) = socket.recv_from(&mut res_buffer.buf)?;
+    res_buffer.buf.truncate(len);
 
     // Now we parse the packet
     let res_packet = DnsPacket::from_buffer(&mut res_buffer)?;
-    println!("{:#?}", res_packet.header);
-
-    for q in res_packet.questions {
-        println!("{:#?}", q);
+    if res_packet.header.id != packet.header.id {
+        return Err("Response id doesn't match the query".into());
     }
-    for rec in res_packet.answers {
-        println!("{:#?}", rec);
-    }
-    for rec in res_packet.authorities {
-        println!("{:#?}", rec);
-    }
-    for rec in res_packet.resources {
-        println!("{:#?}", rec);
+
+    if json {
+        #[cfg(feature = "serde")]
+        println!("{}", serde_json::to_string_pretty(&res_packet)?);
+        return Ok(());
     }
 
+    println!("{}", res_packet);
+
+    // The AD bit is the server vouching for having validated the answer
+    // with DNSSEC
+    let validated = if res_packet.header.authed_data { "" } else { " not" };
+    println!(";; The answer was{} authenticated by the server (AD)", validated);
+
     Ok(())
 }
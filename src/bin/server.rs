@@ -1,50 +1,256 @@
 use std::error::Error;
-use std::net::IpAddr;
-use std::net::Ipv4Addr;
+use std::io;
+use std::net::{AddrParseError, Ipv4Addr, SocketAddrV4};
+use std::net::TcpStream;
 use std::net::UdpSocket;
+use std::time::{Duration, Instant};
 
 
+use dnsrust::cache::Cache;
+use dnsrust::record::DnsClass;
 use dnsrust::record::DnsQuestion;
+use dnsrust::record::DnsRecord;
 use dnsrust::record::QueryType;
-use dnsrust::packets::{BytePacketBuffer, DnsPacket};
-use dnsrust::header::ResultCode;
+use dnsrust::packets::{DnsPacket, PacketBuffer, VectorPacketBuffer};
+use dnsrust::header::{OpCode, ResultCode};
+use dnsrust::error::DnsError;
+use dnsrust::name::Name;
+use dnsrust::observer::{NoopObserver, QueryObserver};
+use dnsrust::zone::Zone;
 
-fn lookup(qname: &str, qtype: QueryType, server: (Ipv4Addr, u16))
+// The EDNS payload size we advertise to upstream servers
+const UDP_PAYLOAD_SIZE: u16 = 1232;
+
+// How long we wait for a name server before giving up on it
+const QUERY_TIMEOUT: Duration = Duration::from_secs(3);
+
+// How often we ask a name server which doesn't answer before moving on to
+// the next one, and how long we wait before asking again the first time
+const RETRY_ATTEMPTS: u32 = 3;
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(100);
+
+// a.root-servers.net through m.root-servers.net, where every walk down the
+// delegation chain starts
+const ROOT_SERVERS: [Ipv4Addr; 13] = [
+    Ipv4Addr::new(198, 41, 0, 4),
+    Ipv4Addr::new(170, 247, 170, 2),
+    Ipv4Addr::new(192, 33, 4, 12),
+    Ipv4Addr::new(199, 7, 91, 13),
+    Ipv4Addr::new(192, 203, 230, 10),
+    Ipv4Addr::new(192, 5, 5, 241),
+    Ipv4Addr::new(192, 112, 36, 4),
+    Ipv4Addr::new(198, 97, 190, 53),
+    Ipv4Addr::new(192, 36, 148, 17),
+    Ipv4Addr::new(192, 58, 128, 30),
+    Ipv4Addr::new(193, 0, 14, 129),
+    Ipv4Addr::new(199, 7, 83, 42),
+    Ipv4Addr::new(202, 12, 27, 33),
+];
+
+// How long the root servers may be assumed to stay where they are, as
+// given in the root hints file
+const ROOT_HINTS_TTL: u32 = 3600000;
+
+/// Whether a lookup failed because the server couldn't be reached or didn't
+/// answer in time, which is worth retrying against another server.
+fn is_network_error(e: &(dyn Error + 'static)) -> bool {
+    e.is::<io::Error>() || matches!(e.downcast_ref::<DnsError>(), Some(DnsError::Io(_)))
+}
+
+// Whether the letters of outgoing query names get a random case, which a
+// spoofed answer would have to guess as well (the "0x20" trick)
+const RANDOMIZE_CASE: bool = true;
+
+/// Flip the case of every letter in a name at random
+fn randomize_case(qname: &str) -> String {
+    qname
+        .chars()
+        .map(|c| if rand::random() { c.to_ascii_uppercase() } else { c.to_ascii_lowercase() })
+        .collect()
+}
+
+/// Whether the question of a response repeats the name we sent exactly,
+/// including the case of each letter. The parsed packet lowercases names,
+/// so the question is read again from the raw response.
+fn echoes_qname(res_buffer: &mut VectorPacketBuffer, qname: &str) -> Result<bool, DnsError> {
+    // The question section follows right after the 12 byte header
+    res_buffer.seek(12)?;
+    let mut echoed = String::new();
+    res_buffer.read_qname_preserving_case(&mut echoed)?;
+
+    Ok(echoed == qname)
+}
+
+/// Whether a packet is a response to the question we asked, rather than a
+/// query or an answer to something else.
+fn answers_question(response: &DnsPacket, qname: &str, qtype: QueryType) -> bool {
+    response.header.response
+        && response.questions.len() == 1
+        && response.questions[0].name == *qname
+        && response.questions[0].qtype == qtype
+}
+
+fn lookup(qname: &str, qtype: QueryType, server: (Ipv4Addr, u16), timeout: Duration)
     -> Result<DnsPacket, Box<dyn Error>> {
 
-        let socket = UdpSocket::bind(("0.0.0.0", 43210))?;
-    
-        let mut packet = DnsPacket::new();
-    
-        packet.header.id = 6666;
-        packet.header.questions = 1;
-        packet.header.recursion_desired = true;
-        packet
-            .questions
-            .push(DnsQuestion::new(qname.to_string(), qtype));
+        // An ephemeral port, so lookups running side by side don't get in
+        // each other's way, and a spoofer has to guess the port as well
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+
+        // Only the server we ask can answer, and if nothing listens there
+        // we hear about it right away instead of waiting for the timeout
+        socket.connect(server)?;
+
+        let sent_qname = if RANDOMIZE_CASE { randomize_case(qname) } else { qname.to_string() };
+        let mut packet = DnsPacket::query(&sent_qname, qtype);
+
+        // Let the upstream server know we can take answers beyond 512 bytes,
+        // using the size recommended to avoid IP fragmentation.
+        packet.set_edns_payload_size(UDP_PAYLOAD_SIZE);
+
+        socket.send(&packet.to_bytes()?)?;
     
-        let mut req_buffer = BytePacketBuffer::new();
+        // Anything which doesn't carry the id of our query isn't the answer
+        // to it, and might well be a spoofing attempt, so keep waiting. The
+        // timeout covers the whole wait, or a steady trickle of junk would
+        // keep us here forever.
+        let deadline = Instant::now() + timeout;
+        let response = loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "No answer before the timeout").into());
+            }
+            socket.set_read_timeout(Some(remaining))?;
+
+            let mut res_buffer = VectorPacketBuffer::with_capacity(UDP_PAYLOAD_SIZE as usize);
+            let len = socket.recv(&mut res_buffer.buf)?;
+            res_buffer.buf.truncate(len);
+
+            let response = match DnsPacket::from_buffer(&mut res_buffer) {
+                Ok(x) => x,
+                Err(e) => {
+                    println!("discarding malformed response: {}", e);
+                    continue;
+                }
+            };
+            if response.header.id != packet.header.id {
+                println!("discarding response with mismatched id {}", response.header.id);
+                continue;
+            }
+            if !answers_question(&response, qname, qtype) {
+                println!("discarding packet which doesn't answer our question");
+                continue;
+            }
+            if RANDOMIZE_CASE && !echoes_qname(&mut res_buffer, &sent_qname)? {
+                println!("discarding response which doesn't echo the case of {}", sent_qname);
+                continue;
+            }
+
+            break response;
+        };
+
+        // A truncated answer is only part of the real one, so the server
+        // wants us to ask again over TCP.
+        if response.header.truncated_message {
+            return lookup_tcp(qname, qtype, server, timeout);
+        }
+
+        Ok(response)
+}
+
+/// Perform a lookup over TCP, where each message is prefixed with its
+/// length as a two byte integer (RFC 1035 section 4.2.2).
+fn lookup_tcp(qname: &str, qtype: QueryType, server: (Ipv4Addr, u16), timeout: Duration)
+    -> Result<DnsPacket, Box<dyn Error>> {
+
+        let mut stream = TcpStream::connect_timeout(&server.into(), timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+
+        let mut packet = DnsPacket::query(qname, qtype);
+
+        let mut req_buffer = VectorPacketBuffer::new();
         packet.write(&mut req_buffer)?;
-        socket.send_to(&req_buffer.buf[0..req_buffer.pos], server)?;
-    
-        let mut res_buffer = BytePacketBuffer::new();
-        socket.recv_from(&mut res_buffer.buf)?;
-    
-        DnsPacket::from_buffer(&mut res_buffer)
+        req_buffer.write_tcp(&mut stream)?;
+
+        let mut res_buffer = VectorPacketBuffer::read_tcp(&mut stream)?;
+        let response = DnsPacket::from_buffer(&mut res_buffer)?;
+        if response.header.id != packet.header.id {
+            return Err(format!("Response id {} doesn't match query id {}", response.header.id, packet.header.id).into());
+        }
+        if !answers_question(&response, qname, qtype) {
+            return Err("Response doesn't answer the question we asked".into());
+        }
+
+        Ok(response)
 }
 
-fn recursive_lookup(qname: &str, qtype: QueryType) -> Result<DnsPacket, Box<dyn Error>> {
-    let mut ns = "198.41.0.4".parse::<Ipv4Addr>().unwrap();
+/// Ask a server the same question up to `attempts` times, each time waiting
+/// up to `timeout` for an answer, and pausing twice as long after each
+/// failure starting out at `base_delay`. A lost packet or a server which is
+/// busy for a moment is no reason to give up on it.
+fn lookup_with_retry(
+    qname: &str,
+    qtype: QueryType,
+    server: (Ipv4Addr, u16),
+    timeout: Duration,
+    attempts: u32,
+    base_delay: Duration,
+) -> Result<DnsPacket, Box<dyn Error>> {
 
+    let mut delay = base_delay;
+    let mut attempt = 1;
     loop {
-        println!("attempting lookup of {:?} {} with ns {}", qtype, qname, ns);
+        match lookup(qname, qtype, server, timeout) {
+            Err(e) if is_network_error(&*e) && attempt < attempts => {
+                println!("attempt {} with ns {} failed: {}", attempt, server.0, e);
+                std::thread::sleep(delay);
+                delay *= 2;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
 
-        let ns_copy = ns;
+/// Ask each of the servers in turn until one of them answers. A server is
+/// only passed over when it can't be reached or doesn't answer within
+/// `timeout`.
+fn lookup_any(qname: &str, qtype: QueryType, servers: &[(Ipv4Addr, u16)], timeout: Duration)
+    -> Result<DnsPacket, Box<dyn Error>> {
+    for server in servers {
+        println!("attempting lookup of {:?} {} with ns {}", qtype, qname, server.0);
 
-        let server = (ns_copy, 53);
-        let response = lookup(qname, qtype, server)?;
+        match lookup_with_retry(qname, qtype, *server, timeout, RETRY_ATTEMPTS, RETRY_BASE_DELAY) {
+            Ok(x) => return Ok(x),
+            Err(e) if is_network_error(&*e) => println!("ns {} failed: {}", server.0, e),
+            Err(e) => return Err(e),
+        }
+    }
+
+    Err(format!("No name server answered for {}", qname).into())
+}
+
+fn recursive_lookup(qname: &str, qtype: QueryType, cache: &Cache) -> Result<DnsPacket, Box<dyn Error>> {
+    // Answers we've seen recently spare us the walk down from the root
+    if let Some(answers) = cache.lookup(qname, qtype) {
+        println!("cache hit for {:?} {}", qtype, qname);
+
+        let mut response = DnsPacket::new();
+        response.header.response = true;
+        response.answers = answers;
+        return Ok(response);
+    }
+
+    // The name servers we can ask at the current step of the delegation.
+    // When one of them doesn't answer in time we move on to the next.
+    let mut servers = ROOT_SERVERS.to_vec();
+
+    loop {
+        let candidates: Vec<(Ipv4Addr, u16)> = servers.iter().map(|ns| (*ns, 53)).collect();
+        let response = lookup_any(qname, qtype, &candidates, QUERY_TIMEOUT)?;
 
         if !response.answers.is_empty() && response.header.rescode == ResultCode::NOERROR {
+            cache.store(qname, qtype, &response.answers);
             return Ok(response);
         }
 
@@ -55,8 +261,11 @@ fn recursive_lookup(qname: &str, qtype: QueryType) -> Result<DnsPacket, Box<dyn
             return Ok(response);
         }
 
-        if let Some(new_ns) = response.get_resolved_ns(qname) {
-            ns = new_ns;
+        cache_referral(&response, cache);
+
+        let resolved = response.get_all_resolved_ns(qname);
+        if !resolved.is_empty() {
+            servers = resolved;
 
             continue;
         }
@@ -66,10 +275,10 @@ fn recursive_lookup(qname: &str, qtype: QueryType) -> Result<DnsPacket, Box<dyn
             None => return Ok(response),
         };
 
-        let recursive_response = recursive_lookup(&new_ns_name, QueryType::A)?;
+        let recursive_response = recursive_lookup(new_ns_name, QueryType::A, cache)?;
 
         if let Some(new_ns) = recursive_response.get_random_a() {
-            ns = new_ns;
+            servers = vec![new_ns];
         } else {
             return Ok(response);
         }
@@ -77,75 +286,1045 @@ fn recursive_lookup(qname: &str, qtype: QueryType) -> Result<DnsPacket, Box<dyn
     }
 }
 
-// Handle a single incoming packet
-fn handle_query(socket: &UdpSocket) -> Result<(), Box<dyn Error>> {
-    let mut req_buffer = BytePacketBuffer::new();
+/// Remember the delegation in a referral, along with the addresses of the
+/// name servers it names, so queries which don't ask for recursion can be
+/// pointed at them later on.
+fn cache_referral(response: &DnsPacket, cache: &Cache) {
+    let delegation: Vec<&DnsRecord> = response.authorities_by_type(QueryType::NS).collect();
 
-    // Te 'recv_from' function will write the data into the buffer,
-    // and return the length of the data read as well as the source address.
-    // We need to keep track of the source in order to send our reply later.
+    // Only the addresses of the servers we're being referred to are taken,
+    // anything else in the additional section is none of our business
+    let glue = response.resources_by_type(QueryType::A).filter(|record| {
+        delegation.iter().any(|ns| match ns {
+            DnsRecord::NS { host, .. } => *record.domain() == **host,
+            _ => false,
+        })
+    });
 
-    let (_, src) = socket.recv_from(&mut req_buffer.buf)?;
-
-    // Parsing the raw bytes into a 'DnsPacket'
-    let mut request = DnsPacket::from_buffer(&mut req_buffer)?;
-
-    // Create the response packet
-    let mut packet = DnsPacket::new();
-    packet.header.id = request.header.id;
-    packet.header.recursion_desired = true;
-    packet.header.recursion_available = true;
-    packet.header.response = true;
-
-    // In the normal case, exactly one question is present
-    if let Some(question) = request.questions.pop() {
-        println!("Received query: {:?}", question);
-        // There's always the possibility that the query will fail, in which
-        // case the 'SERVFAIL' response code is set to indicate as much to the client.
-        if let Ok(result) = recursive_lookup(&question.name, question.qtype) {
-            packet.questions.push(question.clone());
-            packet.header.rescode = result.header.rescode;
-    
-            for rec in result.answers {
-                println!("Answer: {:?}", rec);
-                packet.answers.push(rec);
-            }
-    
-            for rec in result.authorities {
-                println!("Authority: {:?}", rec);
-                packet.authorities.push(rec);
+    for (qtype, records) in [(QueryType::NS, delegation.clone()), (QueryType::A, glue.collect())] {
+        let mut owners: Vec<&Name> = records.iter().map(|record| record.domain()).collect();
+        owners.sort();
+        owners.dedup();
+
+        for owner in owners {
+            let rrset: Vec<DnsRecord> = records
+                .iter()
+                .filter(|record| record.domain() == owner)
+                .map(|record| (*record).clone())
+                .collect();
+            cache.store(&owner.to_string(), qtype, &rrset);
+        }
+    }
+}
+
+/// The answer to a query which doesn't ask for recursion (RFC 1034 section
+/// 4.3.1). That's what we already have in the cache, or otherwise a
+/// referral to the name servers of the closest enclosing zone we know of,
+/// with their addresses as glue. The root servers are the referral of last
+/// resort.
+fn referral(qname: &str, qtype: QueryType, cache: &Cache) -> DnsPacket {
+    let mut response = DnsPacket::new();
+    response.header.response = true;
+
+    if let Some(answers) = cache.lookup(qname, qtype) {
+        response.answers = answers;
+        return response;
+    }
+
+    let mut zone = Name::from(qname);
+    while !zone.is_root() {
+        if let Some(servers) = cache.lookup(&zone.to_string(), QueryType::NS) {
+            for server in &servers {
+                if let DnsRecord::NS { host, .. } = server {
+                    response.resources.extend(cache.lookup(host, QueryType::A).unwrap_or_default());
+                }
             }
-    
-            for rec in result.resources {
-                println!("Resource: {:?}", rec);
-                packet.resources.push(rec);
+            response.authorities = servers;
+            return response;
+        }
+
+        zone = zone.parent().unwrap_or_default();
+    }
+
+    for (letter, addr) in ('a'..='m').zip(ROOT_SERVERS) {
+        let host = format!("{}.root-servers.net", letter);
+        response.authorities.push(DnsRecord::NS {
+            domain: Name::root(),
+            host: host.clone(),
+            class: DnsClass::IN,
+            ttl: ROOT_HINTS_TTL,
+        });
+        response.resources.push(DnsRecord::A {
+            domain: Name::from(host.as_str()),
+            addr,
+            class: DnsClass::IN,
+            ttl: ROOT_HINTS_TTL,
+        });
+    }
+
+    response
+}
+
+// Upper bound on the number of aliases we're willing to chase for one query
+const MAX_CNAME_HOPS: usize = 8;
+
+/// What the server can be told about how to go about answering queries
+#[derive(Clone, Copy, Debug)]
+struct Options {
+    /// The recursive resolver to forward queries to, if any, rather than
+    /// resolving them ourselves
+    forwarder: Option<(Ipv4Addr, u16)>,
+    /// Whether ANY queries get the minimal answer of RFC 8482 instead of
+    /// being passed on, which keeps us from being used to amplify traffic
+    minimal_any: bool,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            forwarder: None,
+            minimal_any: true,
+        }
+    }
+}
+
+/// The RFC 8482 answer to an ANY query: a single synthesized HINFO record,
+/// rather than everything we could find for the name.
+fn minimal_any_response(qname: &str) -> DnsPacket {
+    let mut response = DnsPacket::new();
+    response.header.response = true;
+    response.answers.push(DnsRecord::HINFO {
+        domain: Name::from(qname),
+        cpu: "RFC8482".to_string(),
+        os: "".to_string(),
+        class: DnsClass::IN,
+        ttl: 3600,
+    });
+
+    response
+}
+
+/// Resolve a name and follow any CNAME chain in the answer, re-querying for
+/// the target when the upstream answer stops at an alias. With a forwarder
+/// configured the whole job is left to it instead, and clients which don't
+/// want recursion get a referral.
+fn resolve(
+    qname: &str,
+    qtype: QueryType,
+    recursion_desired: bool,
+    cache: &Cache,
+    zone: &Zone,
+    options: &Options,
+) -> Result<DnsPacket, Box<dyn Error>> {
+
+    // Names in our own zone are answered without asking anyone else
+    if let Some(response) = zone.answer(qname, qtype) {
+        return Ok(response);
+    }
+
+    if qtype == QueryType::ANY && options.minimal_any {
+        return Ok(minimal_any_response(qname));
+    }
+
+    if !recursion_desired {
+        return Ok(referral(qname, qtype, cache));
+    }
+
+    // A recursive resolver upstream follows aliases itself, so its answer is
+    // passed on as it is
+    if let Some(upstream) = options.forwarder {
+        let mut response = lookup_with_retry(qname, qtype, upstream, QUERY_TIMEOUT, RETRY_ATTEMPTS, RETRY_BASE_DELAY)?;
+        response.header.authoritative_answer = false;
+        return Ok(response);
+    }
+
+    let mut response = recursive_lookup(qname, qtype, cache)?;
+
+    // The upstream answer was authoritative for the server we got it from,
+    // but not coming from us
+    response.header.authoritative_answer = false;
+
+    // Someone asking for the alias itself doesn't want us to follow it
+    if qtype == QueryType::CNAME {
+        return Ok(response);
+    }
+
+    let mut name = qname.to_string();
+
+    for _ in 0..MAX_CNAME_HOPS {
+        // Servers which predate DNAME leave deriving the alias to us
+        if response.get_cname(&name).is_none() {
+            if let Some(cname) = response.synthesize_cname(&name) {
+                response.answers.push(cname);
             }
-        } else {
-            packet.header.rescode = ResultCode::SERVFAIL;
         }
+
+        name = match response.get_cname(&name) {
+            Some(x) => x.to_string(),
+            None => return Ok(response),
+        };
+
+        // The upstream server often bundles the records of the target, in
+        // which case there's nothing left to look up for this hop.
+        if response.answers.iter().any(|record| *record.domain() == *name) {
+            continue;
+        }
+
+        let next = recursive_lookup(&name, qtype, cache)?;
+        response.header.rescode = next.header.rescode;
+        response.answers.extend(next.answers);
+        response.authorities = next.authorities;
+        response.resources = next.resources;
+    }
+
+    Err(format!("Limit of {} CNAME hops exceeded", MAX_CNAME_HOPS).into())
+}
+
+/// The largest response the client who sent `request` can take over UDP:
+/// 512 bytes unless it told us otherwise through EDNS, but never more than
+/// we're willing to send ourselves.
+fn max_response_size(request: &DnsPacket) -> usize {
+    match request.get_edns() {
+        Some((payload_size, _)) => payload_size.clamp(512, UDP_PAYLOAD_SIZE) as usize,
+        None => 512,
+    }
+}
+
+/// Answer a question in the CHAOS class, which by convention is used to ask
+/// a server about itself rather than about the internet at large.
+fn chaos_response(question: &DnsQuestion) -> DnsPacket {
+    let mut response = DnsPacket::new();
+    response.header.response = true;
+
+    let name = &question.name;
+    if question.qtype == QueryType::TXT && (*name == "version.bind" || *name == "version.server") {
+        response.answers.push(DnsRecord::TXT {
+            domain: question.name.clone(),
+            strings: vec![format!("dnsrust {}", env!("CARGO_PKG_VERSION")).into_bytes()],
+            class: DnsClass::CH,
+            ttl: 0,
+        });
     } else {
+        response.header.rescode = ResultCode::REFUSED;
+    }
+
+    response
+}
+
+/// The questions of a request we should go and resolve. Those of requests
+/// with an opcode we don't support are answered with 'NOTIMP' instead.
+fn supported_questions(request: &DnsPacket) -> &[DnsQuestion] {
+    match request.header.opcode {
+        OpCode::QUERY => &request.questions,
+        _ => &[],
+    }
+}
+
+/// Assemble the response to a request out of the results of resolving each
+/// of its questions, where `None` marks a question we failed to answer.
+fn build_response(request: &DnsPacket, results: Vec<(DnsQuestion, Option<DnsPacket>)>) -> DnsPacket {
+    let mut packet = DnsPacket::response_to(request);
+
+    // Clients which speak EDNS expect an OPT record in the answer as well,
+    // telling them how large a message we're able to take.
+    if request.get_edns().is_some() {
+        packet.set_edns_payload_size(UDP_PAYLOAD_SIZE);
+    }
+
+    // Requests other than standard queries, like zone updates and
+    // notifications, are nothing a resolver can help with
+    if request.header.opcode != OpCode::QUERY {
+        packet.header.rescode = ResultCode::NOTIMP;
+        return packet;
+    }
+
+    // In the normal case exactly one question is present, but nothing stops
+    // a client from asking several at once.
+    if results.is_empty() {
         packet.header.rescode = ResultCode::FORMERR;
     }
 
-    let mut res_buffer = BytePacketBuffer::new();
-    packet.write(&mut res_buffer)?;
+    // The response is only authoritative when every answer in it came
+    // from our own zone
+    let mut answered = false;
+    let mut authoritative = true;
+    for (_, result) in results {
+        // A failed question only turns into a 'SERVFAIL' for the client if
+        // none of the questions could be answered.
+        let result = match result {
+            Some(x) => x,
+            None => continue,
+        };
+
+        if !answered {
+            packet.header.rescode = result.header.rescode;
+            answered = true;
+        }
+        authoritative &= result.header.authoritative_answer;
+
+        packet.answers.extend(result.answers);
+        packet.authorities.extend(result.authorities);
+
+        // The OPT record only describes the hop between us and the
+        // upstream server, so it's not passed on to the client.
+        for rec in result.resources {
+            if let DnsRecord::OPT { .. } = rec {
+                continue;
+            }
+            packet.resources.push(rec);
+        }
+    }
+
+    if !answered && !packet.questions.is_empty() {
+        packet.header.rescode = ResultCode::SERVFAIL;
+    }
+    packet.header.authoritative_answer = answered && authoritative;
+
+    packet
+}
+
+/// Work out the response to a request received as raw bytes, serialized to
+/// fit within what the client can take. The blocking and the async server
+/// both answer their requests through this.
+fn answer_request(
+    req_buffer: &mut VectorPacketBuffer,
+    cache: &Cache,
+    zone: &Zone,
+    options: &Options,
+    observer: &dyn QueryObserver,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+
+    // Parsing the raw bytes into a 'DnsPacket'. The question is echoed back
+    // to the client, which may want to see it exactly as it was sent.
+    req_buffer.preserve_case = true;
+    let request = DnsPacket::from_buffer(req_buffer)?;
+    observer.on_query(&request);
+
+    // There's always the possibility that a lookup will fail, which we note
+    // down and leave for 'build_response' to sort out.
+    let results = supported_questions(&request)
+        .iter()
+        .map(|question| {
+            println!("Received query: {:?}", question);
+
+            if question.class == DnsClass::CH {
+                return (question.clone(), Some(chaos_response(question)));
+            }
+
+            match resolve(&question.name.to_string(), question.qtype, request.header.recursion_desired, cache, zone, options) {
+                Ok(result) => (question.clone(), Some(result)),
+                Err(e) => {
+                    eprintln!("Lookup of {:?} failed: {}", question, e);
+                    (question.clone(), None)
+                }
+            }
+        })
+        .collect();
+
+    let mut packet = build_response(&request, results);
+    println!("{}", packet);
+
+    let res_bytes = packet.to_bytes_with_limit(max_response_size(&request))?;
+    observer.on_response(&packet);
+
+    Ok(res_bytes)
+}
+
+// Handle a single incoming packet
+#[cfg(not(feature = "async"))]
+fn handle_query(
+    socket: &UdpSocket,
+    cache: &Cache,
+    zone: &Zone,
+    options: &Options,
+    observer: &dyn QueryObserver,
+) -> Result<(), Box<dyn Error>> {
+
+    let mut req_buffer = VectorPacketBuffer::new();
+
+    // Te 'recv_from' function will write the data into the buffer,
+    // and return the length of the data read as well as the source address.
+    // We need to keep track of the source in order to send our reply later.
 
-    let len = res_buffer.pos();
-    let data = res_buffer.get_range(0, len)?;
+    let (len, src) = socket.recv_from(&mut req_buffer.buf)?;
+    req_buffer.buf.truncate(len);
 
-    socket.send_to(data, src)?;
+    let res_bytes = answer_request(&mut req_buffer, cache, zone, options, observer)?;
+    socket.send_to(&res_bytes, src)?;
 
     Ok(())
 }
 
+/// The recursive resolver to forward queries to rather than resolving them
+/// ourselves, given as an address with an optional port in the
+/// `DNSRUST_FORWARDER` environment variable.
+fn load_forwarder() -> Result<Option<(Ipv4Addr, u16)>, AddrParseError> {
+    let value = match std::env::var("DNSRUST_FORWARDER") {
+        Ok(value) if !value.is_empty() => value,
+        _ => return Ok(None),
+    };
+
+    let addr = match value.parse::<SocketAddrV4>() {
+        Ok(addr) => addr,
+        Err(_) => SocketAddrV4::new(value.parse()?, 53),
+    };
+
+    Ok(Some((*addr.ip(), addr.port())))
+}
+
+/// Whether ANY queries get the minimal answer, which can be turned off by
+/// setting the `DNSRUST_MINIMAL_ANY` environment variable to `0` or `false`.
+fn load_minimal_any() -> bool {
+    !matches!(std::env::var("DNSRUST_MINIMAL_ANY").as_deref(), Ok("0") | Ok("false"))
+}
+
+fn load_options() -> Result<Options, AddrParseError> {
+    Ok(Options {
+        forwarder: load_forwarder()?,
+        minimal_any: load_minimal_any(),
+    })
+}
+
+/// The zone we answer for authoritatively, read from the file given as
+/// the first argument. Without one we're a plain recursive resolver.
+fn load_zone() -> Result<Zone, DnsError> {
+    match std::env::args().nth(1) {
+        Some(path) => Zone::load(path),
+        None => Ok(Zone::new()),
+    }
+}
+
+#[cfg(not(feature = "async"))]
 fn main() -> Result<(), Box<dyn Error>> {
 
+    let zone = load_zone()?;
+    let options = load_options()?;
     let socket = UdpSocket::bind(("127.0.0.1", 2053))?;
+    let cache = Cache::new();
 
     loop {
-        match handle_query(&socket) {
+        match handle_query(&socket, &cache, &zone, &options, &NoopObserver) {
             Ok(_) => {},
             Err(e) => eprint!("An error ocurred: {}", e),
         }
     }
-}
\ No newline at end of file
+}
+
+/// With the `async` feature enabled, every incoming query is handled in its
+/// own task, so a slow upstream server no longer holds up other clients.
+/// The resolver itself blocks, so each request is answered on tokio's pool
+/// of blocking threads by the same code as in the blocking server.
+#[cfg(feature = "async")]
+mod concurrent {
+    use std::error::Error;
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+
+    use tokio::net::UdpSocket;
+
+    use dnsrust::cache::Cache;
+    use dnsrust::observer::QueryObserver;
+    use dnsrust::packets::VectorPacketBuffer;
+    use dnsrust::zone::Zone;
+
+    use super::{answer_request, Options};
+
+    // Errors have to be sendable to cross task boundaries
+    pub type AsyncError = Box<dyn Error + Send + Sync>;
+
+    /// Answer a request which has already been received on the socket
+    pub async fn handle_query_async(
+        socket: &UdpSocket,
+        mut req_buffer: VectorPacketBuffer,
+        src: SocketAddr,
+        cache: Cache,
+        zone: Arc<Zone>,
+        options: Options,
+        observer: Arc<dyn QueryObserver + Send + Sync>,
+    ) -> Result<(), AsyncError> {
+
+        // The resolver's errors can't be sent between threads, so only
+        // their message makes it back
+        let res_bytes = tokio::task::spawn_blocking(move || {
+            answer_request(&mut req_buffer, &cache, &zone, &options, &*observer).map_err(|e| e.to_string())
+        })
+        .await??;
+
+        socket.send_to(&res_bytes, src).await?;
+
+        Ok(())
+    }
+
+    /// Receive requests on the socket for good, answering each in a task of
+    /// its own.
+    pub async fn serve(
+        socket: Arc<UdpSocket>,
+        cache: Cache,
+        zone: Arc<Zone>,
+        options: Options,
+        observer: Arc<dyn QueryObserver + Send + Sync>,
+    ) -> Result<(), AsyncError> {
+        loop {
+            let mut req_buffer = VectorPacketBuffer::new();
+            let (len, src) = socket.recv_from(&mut req_buffer.buf).await?;
+            req_buffer.buf.truncate(len);
+
+            let socket = socket.clone();
+            let cache = cache.clone();
+            let zone = zone.clone();
+            let observer = observer.clone();
+            tokio::spawn(async move {
+                let handled = handle_query_async(&socket, req_buffer, src, cache, zone, options, observer);
+                if let Err(e) = handled.await {
+                    eprintln!("An error ocurred: {}", e);
+                }
+            });
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+#[tokio::main]
+async fn main() -> Result<(), concurrent::AsyncError> {
+    use std::sync::Arc;
+
+    let zone = Arc::new(load_zone()?);
+    let options = load_options()?;
+    let socket = Arc::new(tokio::net::UdpSocket::bind(("127.0.0.1", 2053)).await?);
+
+    concurrent::serve(socket, Cache::new(), zone, options, Arc::new(NoopObserver)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answer a request the way the server would, as it arrives on the wire
+    fn exchange(request: &mut DnsPacket, zone: &Zone, options: &Options) -> DnsPacket {
+        let mut req_buffer = VectorPacketBuffer::new();
+        request.write(&mut req_buffer).unwrap();
+        req_buffer.buf.truncate(req_buffer.pos());
+        req_buffer.seek(0).unwrap();
+
+        let res_bytes = answer_request(&mut req_buffer, &Cache::new(), zone, options, &NoopObserver).unwrap();
+        DnsPacket::from_bytes(&res_bytes).unwrap()
+    }
+
+    /// A name server on loopback, which replies to every query it receives
+    /// with the packets `respond` makes of it. Each query gets a thread of
+    /// its own, so a slow reply doesn't hold up the others.
+    fn upstream<F>(respond: F) -> (Ipv4Addr, u16)
+    where
+        F: Fn(&DnsPacket) -> Vec<Vec<u8>> + Send + Sync + 'static,
+    {
+        let socket = UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+        let port = socket.local_addr().unwrap().port();
+        let respond = std::sync::Arc::new(respond);
+
+        std::thread::spawn(move || loop {
+            let mut buffer = VectorPacketBuffer::new();
+            let (len, src) = socket.recv_from(&mut buffer.buf).unwrap();
+            buffer.buf.truncate(len);
+
+            let socket = socket.try_clone().unwrap();
+            let respond = respond.clone();
+            std::thread::spawn(move || {
+                // The question has to be echoed in the case it was asked in
+                buffer.preserve_case = true;
+                let query = DnsPacket::from_buffer(&mut buffer).unwrap();
+                for reply in respond(&query) {
+                    socket.send_to(&reply, src).unwrap();
+                }
+            });
+        });
+
+        (Ipv4Addr::LOCALHOST, port)
+    }
+
+    /// An answer to a query with a single A record
+    fn answer(query: &DnsPacket) -> DnsPacket {
+        let mut response = DnsPacket::response_to(query);
+        response.answers.push(DnsRecord::A {
+            domain: query.questions[0].name.clone(),
+            addr: Ipv4Addr::new(93, 184, 216, 34),
+            class: DnsClass::IN,
+            ttl: 300,
+        });
+
+        response
+    }
+
+    #[test]
+    fn lookup_gives_up_on_a_silent_server() {
+        // Bound, so nothing bounces, but never read from
+        let silent = UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+        let port = silent.local_addr().unwrap().port();
+
+        let start = Instant::now();
+        let result = lookup("example.com", QueryType::A, (Ipv4Addr::LOCALHOST, port), Duration::from_millis(200));
+
+        let e = result.unwrap_err();
+        assert!(is_network_error(&*e), "{}", e);
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn decoys_dont_extend_the_timeout() {
+        let upstream = UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+        let port = upstream.local_addr().unwrap().port();
+
+        // Keep sending answers to some other query at the resolver, for far
+        // longer than it waits
+        std::thread::spawn(move || {
+            let mut buf = [0; 512];
+            let (len, src) = upstream.recv_from(&mut buf).unwrap();
+            let query = DnsPacket::from_bytes(&buf[..len]).unwrap();
+
+            let mut decoy = DnsPacket::response_to(&query);
+            decoy.header.id = query.header.id.wrapping_add(1);
+            let decoy = decoy.to_bytes().unwrap();
+
+            for _ in 0..40 {
+                upstream.send_to(&decoy, src).unwrap();
+                std::thread::sleep(Duration::from_millis(50));
+            }
+        });
+
+        let start = Instant::now();
+        let result = lookup("example.com", QueryType::A, (Ipv4Addr::LOCALHOST, port), Duration::from_millis(300));
+
+        assert!(result.is_err());
+        assert!(start.elapsed() < Duration::from_millis(1500));
+    }
+
+    #[test]
+    fn answer_with_mismatched_id_is_rejected() {
+        let server = upstream(|query| {
+            let mut response = answer(query);
+            response.header.id = query.header.id.wrapping_add(1);
+            vec![response.to_bytes().unwrap()]
+        });
+
+        let result = lookup("example.com", QueryType::A, server, Duration::from_millis(300));
+        assert!(is_network_error(&*result.unwrap_err()));
+    }
+
+    #[test]
+    fn spoofed_answer_doesnt_shut_out_the_real_one() {
+        // A forged reply with the wrong id races ahead of the real one
+        let server = upstream(|query| {
+            let mut spoofed = answer(query);
+            spoofed.header.id = query.header.id ^ 0x8000;
+            if let DnsRecord::A { addr, .. } = &mut spoofed.answers[0] {
+                *addr = Ipv4Addr::new(6, 6, 6, 6);
+            }
+            vec![spoofed.to_bytes().unwrap(), answer(query).to_bytes().unwrap()]
+        });
+
+        let response = lookup("example.com", QueryType::A, server, Duration::from_secs(2)).unwrap();
+        assert_eq!(response.get_random_a(), Some(Ipv4Addr::new(93, 184, 216, 34)));
+    }
+
+    #[test]
+    fn packet_without_qr_is_rejected() {
+        // A query coming back at us is no answer, however well it matches
+        let server = upstream(|query| {
+            let mut echoed = answer(query);
+            echoed.header.response = false;
+            vec![echoed.to_bytes().unwrap()]
+        });
+        let result = lookup("example.com", QueryType::A, server, Duration::from_millis(300));
+        assert!(is_network_error(&*result.unwrap_err()));
+
+        // Neither is an answer to another question
+        let server = upstream(|query| {
+            let mut response = answer(query);
+            response.questions[0].qtype = QueryType::AAAA;
+            vec![response.to_bytes().unwrap()]
+        });
+        let result = lookup("example.com", QueryType::A, server, Duration::from_millis(300));
+        assert!(is_network_error(&*result.unwrap_err()));
+    }
+
+    #[test]
+    fn malformed_packets_are_skipped() {
+        let server = upstream(|query| vec![vec![0xff; 3], answer(query).to_bytes().unwrap()]);
+
+        let response = lookup("example.com", QueryType::A, server, Duration::from_secs(2)).unwrap();
+        assert_eq!(response.get_random_a(), Some(Ipv4Addr::new(93, 184, 216, 34)));
+    }
+
+    #[test]
+    fn case_pattern_survives_the_round_trip() {
+        let sent = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let server = {
+            let sent = sent.clone();
+            upstream(move |query| {
+                sent.lock().unwrap().push(query.questions[0].name.to_string());
+                vec![answer(query).to_bytes().unwrap()]
+            })
+        };
+
+        for _ in 0..8 {
+            lookup("abcdefghijklmnop.example.com", QueryType::A, server, Duration::from_secs(2)).unwrap();
+        }
+
+        let mut sent = sent.lock().unwrap().clone();
+        assert!(sent.iter().all(|name| name.eq_ignore_ascii_case("abcdefghijklmnop.example.com")));
+        sent.sort();
+        sent.dedup();
+        assert!(sent.len() > 1, "{:?}", sent);
+    }
+
+    #[test]
+    fn answer_in_another_case_is_rejected() {
+        let server = upstream(|query| {
+            let mut response = answer(query);
+            response.questions[0].name = Name::from(response.questions[0].name.to_string().to_lowercase().as_str());
+            vec![response.to_bytes().unwrap()]
+        });
+
+        // With this many letters, some are bound to be sent in uppercase
+        let result = lookup("abcdefghijklmnopqrstuvwxyz.example.com", QueryType::A, server, Duration::from_millis(300));
+        assert!(is_network_error(&*result.unwrap_err()));
+    }
+
+    #[test]
+    fn root_servers_are_a_through_m() {
+        assert_eq!(ROOT_SERVERS[0], Ipv4Addr::new(198, 41, 0, 4));
+        assert_eq!(ROOT_SERVERS[12], Ipv4Addr::new(202, 12, 27, 33));
+
+        let mut distinct = ROOT_SERVERS.to_vec();
+        distinct.sort();
+        distinct.dedup();
+        assert_eq!(distinct.len(), 13);
+    }
+
+    #[test]
+    fn next_server_is_tried_when_the_first_is_down() {
+        // Nothing listens on the port of a socket which is gone again
+        let down = UdpSocket::bind(("127.0.0.1", 0)).unwrap().local_addr().unwrap().port();
+        let up = upstream(|query| vec![answer(query).to_bytes().unwrap()]);
+
+        let response = lookup_any("example.com", QueryType::A, &[(Ipv4Addr::LOCALHOST, down), up], QUERY_TIMEOUT).unwrap();
+        assert_eq!(response.get_random_a(), Some(Ipv4Addr::new(93, 184, 216, 34)));
+
+        let e = lookup_any("example.com", QueryType::A, &[(Ipv4Addr::LOCALHOST, down)], QUERY_TIMEOUT).unwrap_err();
+        assert!(e.to_string().starts_with("No name server answered"), "{}", e);
+    }
+
+    #[test]
+    fn every_question_is_answered() {
+        let zone = Zone::parse(
+            "example.com 300 IN A 93.184.216.34
+            example.com 300 IN MX 10 mail.example.com",
+        )
+        .unwrap();
+
+        let mut request = DnsPacket::query("example.com", QueryType::A);
+        request.questions.push(DnsQuestion::new(Name::from("example.com"), QueryType::MX));
+
+        let response = exchange(&mut request, &zone, &Options::default());
+        assert_eq!(response.header.rescode, ResultCode::NOERROR);
+        assert_eq!(response.questions, request.questions);
+        assert_eq!(response.header.answers, 2);
+        assert_eq!(response.answers[0].qtype(), QueryType::A);
+        assert_eq!(response.answers[1].qtype(), QueryType::MX);
+    }
+
+    #[test]
+    fn any_gets_the_minimal_answer() {
+        let mut request = DnsPacket::query("example.com", QueryType::ANY);
+        let response = exchange(&mut request, &Zone::new(), &Options::default());
+
+        assert_eq!(response.questions, request.questions);
+        assert_eq!(response.header.rescode, ResultCode::NOERROR);
+        assert!(matches!(
+            &response.answers[..],
+            [DnsRecord::HINFO { domain, cpu, os, .. }] if domain == "example.com" && cpu == "RFC8482" && os.is_empty()
+        ));
+    }
+
+    #[test]
+    fn any_is_forwarded_unless_minimal() {
+        let options = Options {
+            forwarder: Some(upstream(|query| vec![answer(query).to_bytes().unwrap()])),
+            minimal_any: false,
+        };
+
+        let mut request = DnsPacket::query("example.com", QueryType::ANY);
+        let response = exchange(&mut request, &Zone::new(), &options);
+
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(response.answers[0].qtype(), QueryType::A);
+    }
+
+    #[test]
+    fn aa_is_cleared_on_forwarded_answers() {
+        let options = Options {
+            forwarder: Some(upstream(|query| {
+                let mut response = answer(query);
+                response.header.authoritative_answer = true;
+                vec![response.to_bytes().unwrap()]
+            })),
+            ..Options::default()
+        };
+        let zone = Zone::parse("local.example 300 IN A 192.0.2.1").unwrap();
+
+        let mut request = DnsPacket::query("example.com", QueryType::A);
+        let response = exchange(&mut request, &zone, &options);
+        assert_eq!(response.answers.len(), 1);
+        assert!(!response.header.authoritative_answer);
+
+        // Only answers from our own zone are authoritative
+        let mut request = DnsPacket::query("local.example", QueryType::A);
+        let response = exchange(&mut request, &zone, &options);
+        assert_eq!(response.answers.len(), 1);
+        assert!(response.header.authoritative_answer);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn overlapping_queries_are_answered_concurrently() {
+        use std::sync::Arc;
+
+        // The forwarder takes its time over one of the names
+        let forwarder = upstream(|query| {
+            if query.questions[0].name == "slow.example.com" {
+                std::thread::sleep(Duration::from_secs(1));
+            }
+            vec![answer(query).to_bytes().unwrap()]
+        });
+
+        let socket = Arc::new(tokio::net::UdpSocket::bind(("127.0.0.1", 0)).await.unwrap());
+        let server = socket.local_addr().unwrap();
+        let options = Options {
+            forwarder: Some(forwarder),
+            ..Options::default()
+        };
+        tokio::spawn(concurrent::serve(socket, Cache::new(), Arc::new(Zone::new()), options, Arc::new(NoopObserver)));
+
+        let ask = |qname: &'static str| async move {
+            let client = tokio::net::UdpSocket::bind(("127.0.0.1", 0)).await.unwrap();
+            let mut query = DnsPacket::query(qname, QueryType::A);
+            client.send_to(&query.to_bytes().unwrap(), server).await.unwrap();
+
+            let mut buffer = VectorPacketBuffer::new();
+            let received = tokio::time::timeout(Duration::from_secs(5), client.recv_from(&mut buffer.buf));
+            let (len, _) = received.await.unwrap().unwrap();
+            buffer.buf.truncate(len);
+
+            (DnsPacket::from_buffer(&mut buffer).unwrap(), Instant::now())
+        };
+
+        let start = Instant::now();
+        let slow = tokio::spawn(ask("slow.example.com"));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let fast = tokio::spawn(ask("fast.example.com"));
+
+        let (fast, fast_answered) = fast.await.unwrap();
+        let (slow, slow_answered) = slow.await.unwrap();
+        assert_eq!(*fast.answers[0].domain(), "fast.example.com");
+        assert_eq!(*slow.answers[0].domain(), "slow.example.com");
+
+        // The second query didn't have to wait for the first one
+        assert!(fast_answered < slow_answered);
+        assert!(fast_answered - start < Duration::from_millis(800));
+    }
+
+    #[test]
+    fn question_is_echoed_as_it_was_asked() {
+        let zone = Zone::parse("example.com 300 IN A 93.184.216.34").unwrap();
+
+        let mut req_buffer = VectorPacketBuffer::new();
+        DnsPacket::query("ExAmPlE.CoM", QueryType::A).write(&mut req_buffer).unwrap();
+        req_buffer.buf.truncate(req_buffer.pos());
+        req_buffer.seek(0).unwrap();
+
+        let res_bytes = answer_request(&mut req_buffer, &Cache::new(), &zone, &Options::default(), &NoopObserver).unwrap();
+        let mut res_buffer = VectorPacketBuffer::with_capacity(res_bytes.len());
+        res_buffer.buf.copy_from_slice(&res_bytes);
+        res_buffer.preserve_case = true;
+        let response = DnsPacket::from_buffer(&mut res_buffer).unwrap();
+
+        assert_eq!(response.questions[0].name.to_string(), "ExAmPlE.CoM");
+        assert_eq!(response.header.answers, 1);
+    }
+
+    #[test]
+    fn forwarder_answer_is_relayed() {
+        use std::sync::{Arc, Mutex};
+
+        let asked = Arc::new(Mutex::new(Vec::new()));
+        let seen = asked.clone();
+        let options = Options {
+            forwarder: Some(upstream(move |query| {
+                seen.lock().unwrap().push((query.questions[0].clone(), query.header.recursion_desired));
+
+                // An alias whose target a recursive resolver has filled in
+                let mut response = DnsPacket::response_to(query);
+                response.answers.push(DnsRecord::CNAME {
+                    domain: "www.example.com".into(),
+                    host: "example.com".to_string(),
+                    class: DnsClass::IN,
+                    ttl: 300,
+                });
+                response.answers.push(DnsRecord::A {
+                    domain: "example.com".into(),
+                    addr: Ipv4Addr::new(93, 184, 216, 34),
+                    class: DnsClass::IN,
+                    ttl: 300,
+                });
+                vec![response.to_bytes().unwrap()]
+            })),
+            ..Options::default()
+        };
+
+        let mut request = DnsPacket::query("www.example.com", QueryType::A);
+        let response = exchange(&mut request, &Zone::new(), &options);
+
+        assert_eq!(response.header.id, request.header.id);
+        assert_eq!(response.header.rescode, ResultCode::NOERROR);
+        assert_eq!(response.questions, request.questions);
+        assert_eq!(response.answers.len(), 2);
+        assert_eq!(response.get_random_a(), Some(Ipv4Addr::new(93, 184, 216, 34)));
+
+        // One query went upstream, asking for recursion
+        let asked = asked.lock().unwrap();
+        assert_eq!(asked.len(), 1);
+        assert_eq!(asked[0].0.name, "www.example.com");
+        assert_eq!(asked[0].0.qtype, QueryType::A);
+        assert!(asked[0].1);
+    }
+
+    #[test]
+    fn update_is_not_implemented() {
+        let mut request = DnsPacket::query("example.com", QueryType::A);
+        let mut bytes = request.to_bytes().unwrap();
+
+        // Opcode 5 (UPDATE) in bits 3 to 6 of the third byte
+        bytes[2] = (bytes[2] & 0x87) | (5 << 3);
+        let request = DnsPacket::from_bytes(&bytes).unwrap();
+        assert_eq!(request.header.opcode, OpCode::UPDATE);
+
+        // Even a name we could answer for isn't looked at
+        let zone = Zone::parse("example.com 300 IN A 93.184.216.34").unwrap();
+        let response = exchange(&mut request.clone(), &zone, &Options::default());
+        assert!(response.header.response);
+        assert_eq!(response.header.id, request.header.id);
+        assert_eq!(response.header.opcode, OpCode::UPDATE);
+        assert_eq!(response.header.rescode, ResultCode::NOTIMP);
+        assert!(response.answers.is_empty());
+    }
+
+    #[test]
+    fn observer_sees_each_query_once() {
+        use std::sync::Mutex;
+
+        #[derive(Default)]
+        struct Counting {
+            queries: Mutex<Vec<u16>>,
+            responses: Mutex<Vec<u16>>,
+        }
+
+        impl QueryObserver for Counting {
+            fn on_query(&self, query: &DnsPacket) {
+                self.queries.lock().unwrap().push(query.header.id);
+            }
+
+            fn on_response(&self, response: &DnsPacket) {
+                self.responses.lock().unwrap().push(response.header.id);
+            }
+        }
+
+        let zone = Zone::parse("example.com 300 IN A 93.184.216.34").unwrap();
+        let observer = Counting::default();
+        for id in 1..=3 {
+            let mut request = DnsPacket::query("example.com", QueryType::A);
+            request.header.id = id;
+            let mut req_buffer = VectorPacketBuffer::new();
+            request.write(&mut req_buffer).unwrap();
+            req_buffer.buf.truncate(req_buffer.pos());
+            req_buffer.seek(0).unwrap();
+
+            answer_request(&mut req_buffer, &Cache::new(), &zone, &Options::default(), &observer).unwrap();
+        }
+
+        // A request which can't be parsed is nothing to observe
+        let mut junk = VectorPacketBuffer::from_hex("0004 0100 0001").unwrap();
+        assert!(answer_request(&mut junk, &Cache::new(), &zone, &Options::default(), &observer).is_err());
+
+        assert_eq!(*observer.queries.lock().unwrap(), [1, 2, 3]);
+        assert_eq!(*observer.responses.lock().unwrap(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn lookup_is_retried_until_the_server_answers() {
+        use std::sync::atomic::{AtomicU32, Ordering};
+        use std::sync::Arc;
+
+        // The first two queries go unanswered
+        let received = Arc::new(AtomicU32::new(0));
+        let counter = received.clone();
+        let server = upstream(move |query| {
+            if counter.fetch_add(1, Ordering::SeqCst) < 2 {
+                return vec![];
+            }
+            vec![answer(query).to_bytes().unwrap()]
+        });
+
+        let timeout = Duration::from_millis(100);
+        let start = Instant::now();
+        let response = lookup_with_retry("example.com", QueryType::A, server, timeout, 3, Duration::from_millis(50)).unwrap();
+        assert_eq!(response.get_random_a(), Some(Ipv4Addr::new(93, 184, 216, 34)));
+        assert_eq!(received.load(Ordering::SeqCst), 3);
+
+        // Two timeouts, with a pause of 50ms and then 100ms after them
+        assert!(start.elapsed() >= timeout * 2 + Duration::from_millis(150));
+    }
+
+    #[test]
+    fn query_without_rd_gets_a_referral() {
+        let ask = |cache: &Cache| {
+            let mut request = DnsPacket::query("www.example.com", QueryType::A);
+            request.header.recursion_desired = false;
+            let mut req_buffer = VectorPacketBuffer::new();
+            request.write(&mut req_buffer).unwrap();
+            req_buffer.buf.truncate(req_buffer.pos());
+            req_buffer.seek(0).unwrap();
+
+            let res_bytes = answer_request(&mut req_buffer, cache, &Zone::new(), &Options::default(), &NoopObserver).unwrap();
+            DnsPacket::from_bytes(&res_bytes).unwrap()
+        };
+
+        let cache = Cache::new();
+        let ns = vec![DnsRecord::NS {
+            domain: "example.com".into(),
+            host: "ns1.example.com".to_string(),
+            class: DnsClass::IN,
+            ttl: 3600,
+        }];
+        let glue = vec![DnsRecord::A {
+            domain: "ns1.example.com".into(),
+            addr: Ipv4Addr::new(192, 0, 2, 53),
+            class: DnsClass::IN,
+            ttl: 3600,
+        }];
+        cache.store("example.com", QueryType::NS, &ns);
+        cache.store("ns1.example.com", QueryType::A, &glue);
+
+        let response = ask(&cache);
+        assert!(!response.header.recursion_desired);
+        assert_eq!(response.header.rescode, ResultCode::NOERROR);
+        assert!(response.answers.is_empty());
+        assert_eq!(response.authorities, ns);
+        assert_eq!(response.resources, glue);
+
+        // Knowing nothing of the zone, the root servers are the referral
+        let response = ask(&Cache::new());
+        assert!(response.answers.is_empty());
+        assert_eq!(response.authorities.len(), 13);
+        assert!(response.authorities.iter().all(|record| record.qtype() == QueryType::NS && record.domain().is_root()));
+    }
+}
@@ -0,0 +1,129 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::record::{DnsRecord, QueryType};
+
+/// The cached records of a single answer, each with its absolute expiry
+type CacheEntry = Vec<(DnsRecord, Instant)>;
+
+/// An in-memory cache of answers, keyed on the name and type which was
+/// queried, ignoring the case of the name. Cloning the cache hands out
+/// another reference to the same entries, so it can be shared between
+/// handlers.
+#[derive(Clone, Default)]
+pub struct Cache {
+    entries: Arc<Mutex<HashMap<(String, QueryType), CacheEntry>>>,
+}
+
+impl Cache {
+    pub fn new() -> Cache {
+        Cache::default()
+    }
+
+    /// Returns the cached answer records, as long as every one of them is
+    /// still within its TTL. A partially expired answer is evicted, since
+    /// serving only part of a record set would be misleading.
+    pub fn lookup(&self, qname: &str, qtype: QueryType) -> Option<Vec<DnsRecord>> {
+        self.lookup_at(qname, qtype, Instant::now())
+    }
+
+    /// Look up an answer as it stands at the given point in time. The TTL of
+    /// each record is what remains of it by then, so clients don't hold on
+    /// to the records for longer than we were allowed to.
+    pub fn lookup_at(&self, qname: &str, qtype: QueryType, now: Instant) -> Option<Vec<DnsRecord>> {
+        let mut entries = self.entries.lock().unwrap();
+        let key = (qname.to_lowercase(), qtype);
+
+        let records = entries.get(&key)?;
+
+        // Whole seconds are all a TTL can express, so round up to keep a
+        // record with part of a second left from looking expired already
+        let remaining = |expires: &Instant| {
+            let left = expires.saturating_duration_since(now);
+            (left.as_secs() + u64::from(left.subsec_nanos() > 0)) as u32
+        };
+        if records.iter().any(|(_, expires)| remaining(expires) == 0) {
+            entries.remove(&key);
+            return None;
+        }
+
+        let records = records
+            .iter()
+            .map(|(record, expires)| {
+                let mut record = record.clone();
+                record.set_ttl(remaining(expires));
+                record
+            })
+            .collect();
+
+        Some(records)
+    }
+
+    /// Store the answer records for a query, each expiring after its own TTL.
+    pub fn store(&self, qname: &str, qtype: QueryType, records: &[DnsRecord]) {
+        if records.is_empty() {
+            return;
+        }
+
+        let now = Instant::now();
+        let records = records
+            .iter()
+            .map(|record| {
+                let expires = now + Duration::from_secs(record.ttl() as u64);
+                (record.clone(), expires)
+            })
+            .collect();
+
+        self.entries
+            .lock()
+            .unwrap()
+            .insert((qname.to_lowercase(), qtype), records);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::record::DnsClass;
+
+    fn a_record(ttl: u32) -> DnsRecord {
+        DnsRecord::A {
+            domain: "example.com".into(),
+            addr: Ipv4Addr::new(93, 184, 216, 34),
+            class: DnsClass::IN,
+            ttl,
+        }
+    }
+
+    #[test]
+    fn entry_expires_after_its_ttl() {
+        let cache = Cache::new();
+        cache.store("example.com", QueryType::A, &[a_record(1)]);
+
+        assert_eq!(cache.lookup("Example.com", QueryType::A), Some(vec![a_record(1)]));
+        assert_eq!(cache.lookup("example.com", QueryType::AAAA), None);
+
+        let later = Instant::now() + Duration::from_millis(1001);
+        assert_eq!(cache.lookup_at("example.com", QueryType::A, later), None);
+    }
+
+    #[test]
+    fn served_ttl_counts_down() {
+        let cache = Cache::new();
+        cache.store("example.com", QueryType::A, &[a_record(300)]);
+
+        let now = Instant::now();
+        let served = |elapsed| cache.lookup_at("example.com", QueryType::A, now + elapsed);
+        assert_eq!(served(Duration::from_secs(100)), Some(vec![a_record(200)]));
+        assert_eq!(served(Duration::from_millis(299_500)), Some(vec![a_record(1)]));
+
+        // Once nothing is left the entry is gone, even for an earlier clock
+        assert_eq!(served(Duration::from_secs(300)), None);
+        assert_eq!(served(Duration::ZERO), None);
+    }
+}
@@ -0,0 +1,58 @@
+use std::{error::Error, fmt, io};
+
+/// Everything that can go wrong while reading or writing DNS packets
+#[derive(Debug)]
+pub enum DnsError {
+    /// A read or write went past the end of the buffer
+    EndOfBuffer,
+    /// A compression pointer didn't point back to an earlier part of the
+    /// packet
+    InvalidPointer(usize),
+    /// A label was longer than the 63 characters allowed
+    LabelTooLong,
+    /// A name was longer than the 255 bytes allowed on the wire
+    NameTooLong,
+    /// A name contained an empty label somewhere other than the root
+    EmptyLabel,
+    /// The rdata of a record didn't match the layout of its type
+    InvalidRecord(String),
+    /// A hex dump contained something other than pairs of hex digits
+    InvalidHex(String),
+    /// A line of a zone file couldn't be parsed
+    InvalidZone(usize, String),
+    /// A string didn't name any record type we know of
+    UnknownQueryType(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for DnsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DnsError::EndOfBuffer => write!(f, "End of buffer"),
+            DnsError::InvalidPointer(offset) => write!(f, "Invalid compression pointer to offset {}", offset),
+            DnsError::LabelTooLong => write!(f, "Single label exceeds 63 characters of length."),
+            DnsError::NameTooLong => write!(f, "Name exceeds 255 bytes of length."),
+            DnsError::EmptyLabel => write!(f, "Name contains an empty label."),
+            DnsError::InvalidRecord(msg) => write!(f, "{}", msg),
+            DnsError::InvalidHex(token) => write!(f, "Invalid hex bytes {:?}", token),
+            DnsError::InvalidZone(line, msg) => write!(f, "Zone file line {}: {}", line, msg),
+            DnsError::UnknownQueryType(name) => write!(f, "Unknown record type {:?}", name),
+            DnsError::Io(e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+impl Error for DnsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DnsError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for DnsError {
+    fn from(e: io::Error) -> DnsError {
+        DnsError::Io(e)
+    }
+}
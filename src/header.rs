@@ -1,24 +1,47 @@
-use crate::packets::BytePacketBuffer;
-use std::error::Error;
+use std::fmt;
+
+use crate::{error::DnsError, packets::PacketBuffer};
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ResultCode {
-    NOERROR = 0,
-    FORMERR = 1,
-    SERVFAIL = 2,
-    NXDOMAIN = 3,
-    NOTIMP = 4,
-    REFUSED = 5,
+    UNKNOWN(u16),
+    NOERROR, // 0
+    FORMERR, // 1
+    SERVFAIL, // 2
+    NXDOMAIN, // 3
+    NOTIMP, // 4
+    REFUSED, // 5
+    YXDOMAIN, // 6
+    YXRRSET, // 7
+    NXRRSET, // 8
+    NOTAUTH, // 9
+    NOTZONE, // 10
+    // Extended codes, which need the upper bits carried by EDNS
+    BADVERS, // 16
+    BADCOOKIE, // 23
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OpCode {
+    UNKNOWN(u8),
+    QUERY, // 0
+    IQUERY, // 1
+    STATUS, // 2
+    NOTIFY, // 4
+    UPDATE, // 5
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DnsHeader {
     pub id: u16, // 16 bits
 
     pub recursion_desired: bool, // 1 bit
     pub truncated_message: bool, // 1 bit
     pub authoritative_answer: bool, // 1 bit
-    pub opcode: u8, // 4 bits
+    pub opcode: OpCode, // 4 bits
     pub response: bool, // 1 bit
 
     pub rescode: ResultCode, // 4 bits
@@ -34,18 +57,110 @@ pub struct DnsHeader {
 }
 
 impl ResultCode {
-    pub fn from_num(num: u8) -> ResultCode {
+    pub fn from_num(num: u16) -> ResultCode {
         match num {
+            0 => ResultCode::NOERROR,
             1 => ResultCode::FORMERR,
             2 => ResultCode::SERVFAIL,
             3 => ResultCode::NXDOMAIN,
             4 => ResultCode::NOTIMP,
             5 => ResultCode::REFUSED,
-            0 | _ => ResultCode::NOERROR
+            6 => ResultCode::YXDOMAIN,
+            7 => ResultCode::YXRRSET,
+            8 => ResultCode::NXRRSET,
+            9 => ResultCode::NOTAUTH,
+            10 => ResultCode::NOTZONE,
+            16 => ResultCode::BADVERS,
+            23 => ResultCode::BADCOOKIE,
+            _ => ResultCode::UNKNOWN(num),
+        }
+    }
+
+    pub fn to_num(&self) -> u16 {
+        match *self {
+            ResultCode::UNKNOWN(x) => x,
+            ResultCode::NOERROR => 0,
+            ResultCode::FORMERR => 1,
+            ResultCode::SERVFAIL => 2,
+            ResultCode::NXDOMAIN => 3,
+            ResultCode::NOTIMP => 4,
+            ResultCode::REFUSED => 5,
+            ResultCode::YXDOMAIN => 6,
+            ResultCode::YXRRSET => 7,
+            ResultCode::NXRRSET => 8,
+            ResultCode::NOTAUTH => 9,
+            ResultCode::NOTZONE => 10,
+            ResultCode::BADVERS => 16,
+            ResultCode::BADCOOKIE => 23,
+        }
+    }
+}
+
+impl OpCode {
+    pub fn to_num(&self) -> u8 {
+        match *self {
+            OpCode::UNKNOWN(x) => x,
+            OpCode::QUERY => 0,
+            OpCode::IQUERY => 1,
+            OpCode::STATUS => 2,
+            OpCode::NOTIFY => 4,
+            OpCode::UPDATE => 5,
+        }
+    }
+
+    pub fn from_num(num: u8) -> OpCode {
+        match num {
+            0 => OpCode::QUERY,
+            1 => OpCode::IQUERY,
+            2 => OpCode::STATUS,
+            4 => OpCode::NOTIFY,
+            5 => OpCode::UPDATE,
+            _ => OpCode::UNKNOWN(num),
         }
     }
 }
 
+/// The mnemonics of RFC 6895, with codes that have none shown as dig does
+impl fmt::Display for ResultCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ResultCode::UNKNOWN(x) => write!(f, "RESERVED{}", x),
+            ResultCode::NOERROR => write!(f, "NOERROR"),
+            ResultCode::FORMERR => write!(f, "FORMERR"),
+            ResultCode::SERVFAIL => write!(f, "SERVFAIL"),
+            ResultCode::NXDOMAIN => write!(f, "NXDOMAIN"),
+            ResultCode::NOTIMP => write!(f, "NOTIMP"),
+            ResultCode::REFUSED => write!(f, "REFUSED"),
+            ResultCode::YXDOMAIN => write!(f, "YXDOMAIN"),
+            ResultCode::YXRRSET => write!(f, "YXRRSET"),
+            ResultCode::NXRRSET => write!(f, "NXRRSET"),
+            ResultCode::NOTAUTH => write!(f, "NOTAUTH"),
+            ResultCode::NOTZONE => write!(f, "NOTZONE"),
+            ResultCode::BADVERS => write!(f, "BADVERS"),
+            ResultCode::BADCOOKIE => write!(f, "BADCOOKIE"),
+        }
+    }
+}
+
+impl fmt::Display for OpCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            OpCode::UNKNOWN(x) => write!(f, "RESERVED{}", x),
+            OpCode::QUERY => write!(f, "QUERY"),
+            OpCode::IQUERY => write!(f, "IQUERY"),
+            OpCode::STATUS => write!(f, "STATUS"),
+            OpCode::NOTIFY => write!(f, "NOTIFY"),
+            OpCode::UPDATE => write!(f, "UPDATE"),
+        }
+    }
+}
+
+impl Default for DnsHeader {
+    fn default() -> Self {
+        DnsHeader::new()
+    }
+}
+
 impl DnsHeader {
     pub fn new() -> DnsHeader {
         DnsHeader {
@@ -54,7 +169,7 @@ impl DnsHeader {
             recursion_desired: false,
             truncated_message: false,
             authoritative_answer: false,
-            opcode: 0,
+            opcode: OpCode::QUERY,
             response: false,
 
             rescode: ResultCode::NOERROR,
@@ -70,7 +185,43 @@ impl DnsHeader {
         }
     }
 
-    pub fn read(&mut self, buffer: &mut BytePacketBuffer) -> Result<(), Box<dyn Error>> {
+    /// Prepare the header of an outgoing recursive query
+    pub fn set_query(&mut self, id: u16) {
+        self.id = id;
+        self.response = false;
+        self.recursion_desired = true;
+    }
+
+    /// Prepare the header of a response from our recursive resolver
+    pub fn set_response(&mut self, id: u16) {
+        self.id = id;
+        self.response = true;
+        self.recursion_desired = true;
+        self.recursion_available = true;
+    }
+
+    /// The set flags in the order dig prints them, e.g. `qr rd ra`
+    pub fn flags_summary(&self) -> String {
+        let flags = [
+            ("qr", self.response),
+            ("aa", self.authoritative_answer),
+            ("tc", self.truncated_message),
+            ("rd", self.recursion_desired),
+            ("ra", self.recursion_available),
+            ("z", self.z),
+            ("ad", self.authed_data),
+            ("cd", self.checking_disabled),
+        ];
+
+        flags
+            .iter()
+            .filter(|(_, set)| *set)
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    pub fn read<T: PacketBuffer>(&mut self, buffer: &mut T) -> Result<(), DnsError> {
         self.id = buffer.read_u16()?;
 
         let flags = buffer.read_u16()?;
@@ -79,10 +230,10 @@ impl DnsHeader {
         self.recursion_desired = (a & (1 << 0)) > 0;
         self.truncated_message = (a & (1 << 1)) > 0;
         self.authoritative_answer = (a & (1 << 2)) > 0;
-        self.opcode = (a >> 3) & 0x0F;
+        self.opcode = OpCode::from_num((a >> 3) & 0x0F);
         self.response = (a & (1 << 7)) > 0;
 
-        self.rescode = ResultCode::from_num(b & 0x0F);
+        self.rescode = ResultCode::from_num((b & 0x0F) as u16);
         self.checking_disabled = (b & (1 << 4)) > 0;
         self.authed_data = (b & (1 << 5)) > 0;
         self.z = (b & (1 << 6)) > 0;
@@ -96,19 +247,20 @@ impl DnsHeader {
         Ok(())
     }
 
-    pub fn write(&self, buffer: &mut BytePacketBuffer) -> Result<(), Box<dyn Error>> {
+    pub fn write<T: PacketBuffer>(&self, buffer: &mut T) -> Result<(), DnsError> {
         buffer.write_u16(self.id)?;
 
         buffer.write_u8(
         (self.recursion_desired as u8)
                 | ((self.truncated_message as u8) << 1)
                 | ((self.authoritative_answer as u8) << 2)
-                | (self.opcode << 3)
-                | ((self.response as u8) << 7) as u8  
+                | (self.opcode.to_num() << 3)
+                | ((self.response as u8) << 7)
         )?;
 
         buffer.write_u8(
-        (self.rescode as u8)
+        // Only the lower four bits of an extended code fit in the header
+        ((self.rescode.to_num() & 0x0F) as u8)
                 | ((self.checking_disabled as u8) << 4)
                 | ((self.authed_data as u8) << 5)
                 | ((self.z as u8) << 6)
@@ -123,4 +275,127 @@ impl DnsHeader {
 
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+/// The two header lines dig prints above a response, with the opcode, status
+/// and id, followed by the flags and the number of entries in each section.
+impl fmt::Display for DnsHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            ";; ->>HEADER<<- opcode: {}, status: {}, id: {}",
+            self.opcode, self.rescode, self.id
+        )?;
+        writeln!(
+            f,
+            ";; flags: {}; QUERY: {}, ANSWER: {}, AUTHORITY: {}, ADDITIONAL: {}",
+            self.flags_summary(),
+            self.questions,
+            self.answers,
+            self.authoritative_entries,
+            self.resource_entries
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packets::VectorPacketBuffer;
+
+    #[test]
+    fn flags_summary_lists_set_flags() {
+        let mut header = DnsHeader::new();
+        assert_eq!(header.flags_summary(), "");
+
+        header.set_query(1);
+        assert_eq!(header.flags_summary(), "rd");
+
+        header.set_response(1);
+        assert_eq!(header.flags_summary(), "qr rd ra");
+
+        header.authoritative_answer = true;
+        header.checking_disabled = true;
+        assert_eq!(header.flags_summary(), "qr aa rd ra cd");
+    }
+
+    #[test]
+    fn status_opcode_is_read() {
+        // id 0x1234, opcode 2 (STATUS) with RD set, no sections
+        let mut buffer = VectorPacketBuffer::from_hex("1234 1100 0000 0000 0000 0000").unwrap();
+        let mut header = DnsHeader::new();
+        header.read(&mut buffer).unwrap();
+
+        assert_eq!(header.opcode, OpCode::STATUS);
+        assert!(header.recursion_desired);
+        assert!(!header.response);
+
+        let mut written = VectorPacketBuffer::new();
+        header.write(&mut written).unwrap();
+        assert_eq!(written.to_hex(), buffer.to_hex());
+    }
+
+    /// Read the twelve bytes of a header from hex
+    fn header_from_hex(hex: &str) -> DnsHeader {
+        let mut buffer = VectorPacketBuffer::from_hex(hex).unwrap();
+        let mut header = DnsHeader::new();
+        header.read(&mut buffer).unwrap();
+        header
+    }
+
+    /// The two flag bytes of a header as written
+    fn flag_bytes(header: &DnsHeader) -> [u8; 2] {
+        let mut buffer = VectorPacketBuffer::new();
+        header.write(&mut buffer).unwrap();
+        [buffer.buf[2], buffer.buf[3]]
+    }
+
+    #[test]
+    fn ad_cd_and_z_bits() {
+        // Each of the upper bits of the second flag byte on its own
+        let patterns = [
+            (0x80, (true, false, false, false)),
+            (0x40, (false, true, false, false)),
+            (0x20, (false, false, true, false)),
+            (0x10, (false, false, false, true)),
+        ];
+        for (byte, (ra, z, ad, cd)) in patterns {
+            let header = header_from_hex(&format!("0000 81{:02x} 0000 0000 0000 0000", byte));
+            assert_eq!(
+                (header.recursion_available, header.z, header.authed_data, header.checking_disabled),
+                (ra, z, ad, cd),
+                "{:02x}",
+                byte
+            );
+            assert_eq!(header.rescode, ResultCode::NOERROR);
+            assert_eq!(flag_bytes(&header), [0x81, byte]);
+        }
+
+        // All of them along with NXDOMAIN, the reserved bit included
+        let header = header_from_hex("abcd 81f3 0001 0000 0000 0000");
+        assert!(header.recursion_available && header.z && header.authed_data && header.checking_disabled);
+        assert_eq!(header.rescode, ResultCode::NXDOMAIN);
+        assert_eq!(flag_bytes(&header), [0x81, 0xf3]);
+
+        let mut header = DnsHeader::new();
+        header.authed_data = true;
+        assert_eq!(flag_bytes(&header), [0x00, 0x20]);
+    }
+
+    #[test]
+    fn cd_survives_a_round_trip() {
+        // A query from a validating client: RD and CD set, RA clear
+        let header = header_from_hex("1f2e 0110 0001 0000 0000 0001");
+        assert!(header.checking_disabled);
+        assert!(header.recursion_desired);
+        assert!(!header.recursion_available);
+        assert!(!header.authed_data);
+        assert!(!header.z);
+        assert_eq!(flag_bytes(&header), [0x01, 0x10]);
+
+        // And in a response, next to RA
+        let header = header_from_hex("1f2e 8190 0001 0001 0000 0001");
+        assert!(header.checking_disabled && header.recursion_available);
+        assert_eq!(flag_bytes(&header), [0x81, 0x90]);
+    }
+}
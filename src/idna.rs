@@ -0,0 +1,213 @@
+// Internationalized domain names (RFC 5890). Labels with characters outside
+// of ASCII travel on the wire in their ASCII compatible form: the prefix
+// `xn--` followed by the punycode encoding of the label (RFC 3492).
+
+const ACE_PREFIX: &str = "xn--";
+
+// The punycode parameters from RFC 3492 section 5
+const BASE: u32 = 36;
+const TMIN: u32 = 1;
+const TMAX: u32 = 26;
+const SKEW: u32 = 38;
+const DAMP: u32 = 700;
+const INITIAL_BIAS: u32 = 72;
+const INITIAL_N: u32 = 128;
+
+/// The form of a name which can be written to a packet, with every label
+/// containing non-ASCII characters encoded as `xn--` followed by punycode.
+/// ASCII labels, including ones which are already encoded, are left as
+/// they are.
+pub fn to_ascii(name: &str) -> String {
+    name.split('.')
+        .map(|label| {
+            if label.is_ascii() {
+                label.to_string()
+            } else {
+                // A stand-in for the full mapping of UTS 46, which is mostly
+                // about folding case
+                format!("{}{}", ACE_PREFIX, encode(&label.to_lowercase()))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// The name for display, with `xn--` labels decoded back to Unicode. Labels
+/// which aren't valid punycode are left encoded.
+pub fn to_unicode(name: &str) -> String {
+    name.split('.')
+        .map(|label| {
+            let prefix = label.get(..ACE_PREFIX.len());
+            match prefix {
+                Some(prefix) if prefix.eq_ignore_ascii_case(ACE_PREFIX) => {
+                    decode(&label[ACE_PREFIX.len()..]).unwrap_or_else(|| label.to_string())
+                }
+                _ => label.to_string(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+fn adapt(delta: u32, num_points: u32, first_time: bool) -> u32 {
+    let mut delta = if first_time { delta / DAMP } else { delta / 2 };
+    delta += delta / num_points;
+
+    let mut k = 0;
+    while delta > ((BASE - TMIN) * TMAX) / 2 {
+        delta /= BASE - TMIN;
+        k += BASE;
+    }
+
+    k + (BASE - TMIN + 1) * delta / (delta + SKEW)
+}
+
+/// The threshold for the digit at position `k` of a variable-length integer
+fn threshold(k: u32, bias: u32) -> u32 {
+    if k <= bias {
+        TMIN
+    } else if k >= bias + TMAX {
+        TMAX
+    } else {
+        k - bias
+    }
+}
+
+fn encode_digit(d: u32) -> char {
+    match d {
+        0..=25 => (b'a' + d as u8) as char,
+        _ => (b'0' + (d - 26) as u8) as char,
+    }
+}
+
+fn decode_digit(c: char) -> Option<u32> {
+    match c {
+        'a'..='z' => Some(c as u32 - 'a' as u32),
+        'A'..='Z' => Some(c as u32 - 'A' as u32),
+        '0'..='9' => Some(c as u32 - '0' as u32 + 26),
+        _ => None,
+    }
+}
+
+/// Punycode encoding of a single label, without the `xn--` prefix
+fn encode(label: &str) -> String {
+    let input: Vec<u32> = label.chars().map(|c| c as u32).collect();
+
+    let mut output: String = label.chars().filter(|c| c.is_ascii()).collect();
+    let basic = output.len() as u32;
+    if basic > 0 {
+        output.push('-');
+    }
+
+    let mut n = INITIAL_N;
+    let mut delta = 0;
+    let mut bias = INITIAL_BIAS;
+    let mut handled = basic;
+
+    while (handled as usize) < input.len() {
+        // The smallest code point we haven't handled yet
+        let m = input.iter().copied().filter(|&c| c >= n).min().unwrap_or(n);
+        delta += (m - n) * (handled + 1);
+        n = m;
+
+        for &c in &input {
+            if c < n {
+                delta += 1;
+            }
+            if c != n {
+                continue;
+            }
+
+            let mut q = delta;
+            let mut k = BASE;
+            loop {
+                let t = threshold(k, bias);
+                if q < t {
+                    break;
+                }
+                output.push(encode_digit(t + (q - t) % (BASE - t)));
+                q = (q - t) / (BASE - t);
+                k += BASE;
+            }
+            output.push(encode_digit(q));
+
+            bias = adapt(delta, handled + 1, handled == basic);
+            delta = 0;
+            handled += 1;
+        }
+
+        delta += 1;
+        n += 1;
+    }
+
+    output
+}
+
+/// Decoding of a punycode label, without the `xn--` prefix. Returns `None`
+/// for input which isn't valid punycode.
+fn decode(label: &str) -> Option<String> {
+    let (basic, extended) = match label.rfind('-') {
+        Some(pos) => (&label[..pos], &label[pos + 1..]),
+        None => ("", label),
+    };
+    if !basic.is_ascii() {
+        return None;
+    }
+
+    let mut output: Vec<char> = basic.chars().collect();
+    let mut n = INITIAL_N;
+    let mut i: u32 = 0;
+    let mut bias = INITIAL_BIAS;
+
+    let mut digits = extended.chars();
+    while !digits.as_str().is_empty() {
+        let old_i = i;
+        let mut w: u32 = 1;
+        let mut k = BASE;
+        loop {
+            let digit = decode_digit(digits.next()?)?;
+            i = i.checked_add(digit.checked_mul(w)?)?;
+
+            let t = threshold(k, bias);
+            if digit < t {
+                break;
+            }
+            w = w.checked_mul(BASE - t)?;
+            k += BASE;
+        }
+
+        let len = output.len() as u32 + 1;
+        bias = adapt(i - old_i, len, old_i == 0);
+        n = n.checked_add(i / len)?;
+        i %= len;
+
+        output.insert(i as usize, char::from_u32(n)?);
+        i += 1;
+    }
+
+    Some(output.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unicode_labels_are_encoded() {
+        assert_eq!(to_ascii("bücher.example"), "xn--bcher-kva.example");
+        assert_eq!(to_ascii("www.münchen.de"), "www.xn--mnchen-3ya.de");
+        assert_eq!(to_ascii("例え.テスト"), "xn--r8jz45g.xn--zckzah");
+
+        // Names which are already fit for the wire pass through as they are
+        assert_eq!(to_ascii("xn--bcher-kva.example"), "xn--bcher-kva.example");
+        assert_eq!(to_ascii("www.example.com"), "www.example.com");
+    }
+
+    #[test]
+    fn encoded_labels_are_decoded_for_display() {
+        assert_eq!(to_unicode("xn--bcher-kva.example"), "bücher.example");
+        assert_eq!(to_unicode("www.XN--mnchen-3ya.de"), "www.münchen.de");
+        assert_eq!(to_unicode("www.example.com"), "www.example.com");
+        assert_eq!(to_unicode(&to_ascii("例え.テスト")), "例え.テスト");
+    }
+}
@@ -1,3 +1,9 @@
+pub mod cache;
+pub mod error;
 pub mod packets;
 pub mod record;
-pub mod header;
\ No newline at end of file
+pub mod header;
+pub mod idna;
+pub mod name;
+pub mod observer;
+pub mod zone;
\ No newline at end of file
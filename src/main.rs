@@ -1,34 +1,20 @@
+use std::env;
 use std::fs::File;
-use std::io::Read;
 use std::error::Error;
 
-use packets::{BytePacketBuffer, DnsPacket};
-
-mod packets;
-mod header;
-mod record;
+use dnsrust::packets::{DnsPacket, VectorPacketBuffer};
 
 fn main() -> Result<(), Box<dyn Error>> {
-    let mut f = File::open("test/response_packet.txt")?;
-    let mut buffer = BytePacketBuffer::new();
-    f.read(&mut buffer.buf)?;
-
-    let packet = DnsPacket::from_buffer(&mut buffer)?;
-    println!("{:#?}", packet.header);
+    let path = env::args().nth(1).unwrap_or_else(|| "test/response_packet.txt".to_string());
+    let mut buffer = VectorPacketBuffer::read_from(&mut File::open(path)?)?;
 
-    for q in packet.questions {
-        println!("{:#?}", q);
-    }
-    for rec in packet.answers {
-        println!("{:#?}", rec);
-    }
-    for rec in packet.authorities {
-        println!("{:#?}", rec);
-    }
-    for rec in packet.resources {
-        println!("{:#?}", rec);
+    // Fixtures may be kept as a readable hex dump rather than raw bytes
+    if let Some(hex) = std::str::from_utf8(&buffer.buf).ok().and_then(|text| VectorPacketBuffer::from_hex(text).ok()) {
+        buffer = hex;
     }
 
+    let packet = DnsPacket::from_buffer(&mut buffer)?;
+    println!("{}", packet);
 
     Ok(())
 }
@@ -0,0 +1,264 @@
+use std::{
+    cmp::Ordering,
+    fmt,
+    hash::{Hash, Hasher},
+    str::FromStr,
+};
+
+use crate::error::DnsError;
+
+/// A domain name as a sequence of labels, from the leftmost one up to the
+/// top level domain. The root is the name without any labels.
+///
+/// Names compare and hash case-insensitively, and relationships between
+/// them are decided label by label, so `notexample.com` is no subdomain of
+/// `example.com`. They are kept in the case they were read or written in.
+#[derive(Clone, Debug, Default)]
+pub struct Name {
+    labels: Vec<Vec<u8>>,
+}
+
+impl Name {
+    pub fn root() -> Name {
+        Name::default()
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    /// The labels of the name, starting with the leftmost one
+    pub fn labels(&self) -> impl Iterator<Item = &[u8]> {
+        self.labels.iter().map(|label| label.as_slice())
+    }
+
+    /// The number of labels, which is zero for the root
+    pub fn label_count(&self) -> usize {
+        self.labels.len()
+    }
+
+    /// The name with its leftmost label removed, or `None` for the root
+    pub fn parent(&self) -> Option<Name> {
+        if self.is_root() {
+            return None;
+        }
+
+        Some(Name { labels: self.labels[1..].to_vec() })
+    }
+
+    /// Whether this name is `other` or lies somewhere below it. Every name
+    /// is a subdomain of the root.
+    pub fn is_subdomain_of(&self, other: &Name) -> bool {
+        let skip = match self.labels.len().checked_sub(other.labels.len()) {
+            Some(skip) => skip,
+            None => return false,
+        };
+
+        self.labels[skip..]
+            .iter()
+            .zip(&other.labels)
+            .all(|(a, b)| a.eq_ignore_ascii_case(b))
+    }
+}
+
+/// Splits a name on its dots without checking it against the limits of the
+/// wire format, which `parse` does.
+impl From<&str> for Name {
+    fn from(s: &str) -> Name {
+        let s = s.strip_suffix('.').unwrap_or(s);
+        if s.is_empty() {
+            return Name::root();
+        }
+
+        Name { labels: s.split('.').map(|label| label.as_bytes().to_vec()).collect() }
+    }
+}
+
+impl FromStr for Name {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<Name, DnsError> {
+        let name = Name::from(s);
+
+        if name.labels.iter().any(|label| label.is_empty()) {
+            return Err(DnsError::EmptyLabel);
+        }
+        if name.labels.iter().any(|label| label.len() > 0x3f) {
+            return Err(DnsError::LabelTooLong);
+        }
+
+        // Each label takes a length byte, plus the terminating zero
+        let total = name.labels.iter().map(|label| label.len() + 1).sum::<usize>() + 1;
+        if total > 255 {
+            return Err(DnsError::NameTooLong);
+        }
+
+        Ok(name)
+    }
+}
+
+/// The name without a trailing dot, except for the root which is shown as
+/// a single dot
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_root() {
+            return write!(f, ".");
+        }
+
+        for (i, label) in self.labels.iter().enumerate() {
+            if i > 0 {
+                write!(f, ".")?;
+            }
+            write!(f, "{}", String::from_utf8_lossy(label))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl PartialEq for Name {
+    fn eq(&self, other: &Name) -> bool {
+        self.labels.len() == other.labels.len() && self.is_subdomain_of(other)
+    }
+}
+
+impl Eq for Name {}
+
+impl PartialEq<str> for Name {
+    fn eq(&self, other: &str) -> bool {
+        let other = Name::from(other);
+        *self == other
+    }
+}
+
+impl PartialEq<&str> for Name {
+    fn eq(&self, other: &&str) -> bool {
+        *self == **other
+    }
+}
+
+/// The canonical order of RFC 4034 section 6.1, which compares names label
+/// by label starting from the right, ignoring case
+impl Ord for Name {
+    fn cmp(&self, other: &Name) -> Ordering {
+        let lowercase = |name: &Name| -> Vec<Vec<u8>> {
+            name.labels.iter().rev().map(|label| label.to_ascii_lowercase()).collect()
+        };
+
+        lowercase(self).cmp(&lowercase(other))
+    }
+}
+
+impl PartialOrd for Name {
+    fn partial_cmp(&self, other: &Name) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Hash for Name {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // Consistent with the case-insensitive comparison
+        for label in &self.labels {
+            label.to_ascii_lowercase().hash(state);
+        }
+        self.labels.len().hash(state);
+    }
+}
+
+/// Names are written out as text in their presentation form, so they read
+/// the same as they would in a zone file
+#[cfg(feature = "serde")]
+impl serde::Serialize for Name {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Name {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Name, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        Ok(Name::from(text.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn subdomains_end_on_a_label_boundary() {
+        let example = Name::from("example.com");
+
+        assert!(Name::from("www.example.com").is_subdomain_of(&example));
+        assert!(Name::from("WWW.Example.COM.").is_subdomain_of(&example));
+        assert!(example.is_subdomain_of(&example));
+        assert!(!Name::from("notexample.com").is_subdomain_of(&example));
+        assert!(!Name::from("com").is_subdomain_of(&example));
+    }
+
+    #[test]
+    fn root_is_above_every_name() {
+        let root = Name::from(".");
+        assert!(root.is_root());
+        assert_eq!(root, Name::root());
+        assert_eq!(root.label_count(), 0);
+        assert_eq!(root.parent(), None);
+        assert_eq!(root.to_string(), ".");
+        assert_eq!("".parse::<Name>().unwrap(), root);
+
+        assert!(Name::from("example.com").is_subdomain_of(&root));
+        assert!(!root.is_subdomain_of(&Name::from("com")));
+
+        let mut name = Name::from("www.example.com");
+        let mut parents = vec![];
+        while let Some(parent) = name.parent() {
+            parents.push(parent.to_string());
+            name = parent;
+        }
+        assert_eq!(parents, ["example.com", "com", "."]);
+    }
+
+    #[test]
+    fn names_compare_and_hash_without_case() {
+        assert_eq!(Name::from("Example.COM"), Name::from("example.com."));
+        assert_ne!(Name::from("example.com"), Name::from("www.example.com"));
+
+        let names: HashSet<Name> = ["example.com", "EXAMPLE.com", "example.net"].into_iter().map(Name::from).collect();
+        assert_eq!(names.len(), 2);
+
+        let labels: Vec<_> = Name::from("www.Example.com").labels().map(|l| l.to_vec()).collect();
+        assert_eq!(labels, [b"www".to_vec(), b"Example".to_vec(), b"com".to_vec()]);
+        assert_eq!(Name::from("www.Example.com").to_string(), "www.Example.com");
+    }
+
+    #[test]
+    fn names_sort_in_canonical_order() {
+        // The example of RFC 4034 section 6.1, less the escaped label
+        let sorted = [
+            "example",
+            "a.example",
+            "yljkjljk.a.example",
+            "Z.a.example",
+            "zABC.a.EXAMPLE",
+            "z.example",
+            "*.z.example",
+        ];
+        let mut names: Vec<Name> = sorted.iter().rev().map(|name| Name::from(*name)).collect();
+        names.sort();
+
+        assert_eq!(names.iter().map(|name| name.to_string()).collect::<Vec<_>>(), sorted);
+        assert!(Name::root() < names[0]);
+        assert_eq!(Name::from("example.com"), "EXAMPLE.com.");
+    }
+
+    #[test]
+    fn parse_checks_the_wire_limits() {
+        assert!(matches!("foo..bar".parse::<Name>(), Err(DnsError::EmptyLabel)));
+        assert!(matches!("a".repeat(64).parse::<Name>(), Err(DnsError::LabelTooLong)));
+        assert!(matches!(vec!["a"; 128].join(".").parse::<Name>(), Err(DnsError::NameTooLong)));
+        assert_eq!(vec!["a"; 127].join(".").parse::<Name>().unwrap().label_count(), 127);
+    }
+}
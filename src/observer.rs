@@ -0,0 +1,30 @@
+use crate::packets::DnsPacket;
+
+/// Gets to see every query the server handles and the response it sends
+/// back, for logging and metrics. Both methods do nothing by default.
+pub trait QueryObserver {
+    /// Called with each query once it has been parsed
+    fn on_query(&self, _query: &DnsPacket) {}
+
+    /// Called with each response right before it's sent
+    fn on_response(&self, _response: &DnsPacket) {}
+}
+
+/// An observer which ignores everything
+pub struct NoopObserver;
+
+impl QueryObserver for NoopObserver {}
+
+/// Logs every query and response to stderr in the dig-like format of
+/// `DnsPacket`'s `Display` implementation
+pub struct StderrLogger;
+
+impl QueryObserver for StderrLogger {
+    fn on_query(&self, query: &DnsPacket) {
+        eprintln!(";; Query\n{}", query);
+    }
+
+    fn on_response(&self, response: &DnsPacket) {
+        eprintln!(";; Response\n{}", response);
+    }
+}
@@ -1,13 +1,53 @@
-use std::{error::Error, net::Ipv4Addr};
+use std::{
+    fmt,
+    io::{Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
 
-use crate::{header::DnsHeader, record::{DnsQuestion, DnsRecord, QueryType}};
+use rand::{seq::SliceRandom, Rng};
 
+use crate::{
+    error::DnsError,
+    header::{DnsHeader, ResultCode},
+    idna,
+    name::Name,
+    record::{DnsClass, DnsQuestion, DnsRecord, QueryType},
+};
+
+/// The largest message DNS can carry, limited by the 16 bit length prefix
+/// used over TCP.
+pub const MAX_PACKET_SIZE: usize = 65535;
+
+/// Builds a query packet, starting out with a random id, recursion desired
+/// and a single question.
+pub struct DnsPacketBuilder {
+    packet: DnsPacket,
+}
+
+/// A buffer of the 512 bytes a UDP message without EDNS is limited to,
+/// which can't be written past
 pub struct BytePacketBuffer {
     pub buf: [u8; 512],
     pub pos: usize,
+    /// Keep names exactly as they're written in the packet when reading,
+    /// rather than lowercasing them
+    pub preserve_case: bool,
 }
 
-#[derive(Clone, Debug)]
+/// A buffer backed by a `Vec<u8>`, for TCP and EDNS messages larger than
+/// 512 bytes. Writing past the end grows it, up to `max_size` bytes.
+pub struct VectorPacketBuffer {
+    pub buf: Vec<u8>,
+    pub pos: usize,
+    /// Keep names exactly as they're written in the packet when reading,
+    /// rather than lowercasing them
+    pub preserve_case: bool,
+    /// The most the buffer may grow to, `MAX_PACKET_SIZE` unless set lower
+    pub max_size: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DnsPacket {
     pub header: DnsHeader,
     pub questions: Vec<DnsQuestion>,
@@ -16,6 +56,12 @@ pub struct DnsPacket {
     pub resources: Vec<DnsRecord>,
 }
 
+impl Default for DnsPacket {
+    fn default() -> Self {
+        DnsPacket::new()
+    }
+}
+
 impl DnsPacket {
     pub fn new() -> DnsPacket {
         DnsPacket {
@@ -27,12 +73,30 @@ impl DnsPacket {
         }
     }
 
-    pub fn from_buffer(buffer: &mut BytePacketBuffer) -> Result<DnsPacket, Box<dyn Error>> {
+    /// A recursive query for a single question with a random id, see
+    /// `DnsPacketBuilder` for more control over it.
+    pub fn query(qname: &str, qtype: QueryType) -> DnsPacket {
+        DnsPacketBuilder::query(qname, qtype).build()
+    }
+
+    /// An empty response to a request, with its id, opcode, questions and
+    /// RD flag, and with recursion available.
+    pub fn response_to(request: &DnsPacket) -> DnsPacket {
+        let mut packet = DnsPacket::new();
+        packet.header.set_response(request.header.id);
+        packet.header.recursion_desired = request.header.recursion_desired;
+        packet.header.opcode = request.header.opcode;
+        packet.questions = request.questions.clone();
+
+        packet
+    }
+
+    pub fn from_buffer<T: PacketBuffer>(buffer: &mut T) -> Result<DnsPacket, DnsError> {
         let mut result = DnsPacket::new();
         result.header.read(buffer)?;
 
         for _ in 0..result.header.questions {
-            let mut question = DnsQuestion::new("".to_string(), QueryType::UNKNOWN(0));
+            let mut question = DnsQuestion::new(Name::root(), QueryType::UNKNOWN(0));
             question.read(buffer)?;
             result.questions.push(question);
         }
@@ -55,7 +119,16 @@ impl DnsPacket {
         Ok(result)
     }
 
-    pub fn write(&mut self, buffer: &mut BytePacketBuffer) -> Result<(), Box<dyn Error>> {
+    pub fn write<T: PacketBuffer>(&mut self, buffer: &mut T) -> Result<(), DnsError> {
+        self.write_with_limit(buffer, MAX_PACKET_SIZE)
+    }
+
+    /// Write the packet, leaving out the records which don't fit within
+    /// `max_size` bytes. When anything had to be left out the TC bit is set,
+    /// so the client knows to retry over TCP.
+    pub fn write_with_limit<T: PacketBuffer>(&mut self, buffer: &mut T, max_size: usize) -> Result<(), DnsError> {
+        let start = buffer.pos();
+
         self.header.questions = self.questions.len() as u16;
         self.header.answers = self.answers.len() as u16;
         self.header.authoritative_entries = self.authorities.len() as u16;
@@ -67,28 +140,365 @@ impl DnsPacket {
             question.write(buffer)?;
         }
 
-        for rec in &self.answers {
-            rec.write(buffer)?;
+        // Everything is written as it is, in its original order, unless it
+        // doesn't all fit
+        let body = buffer.pos();
+        let sections = [
+            self.answers.iter().collect(),
+            self.authorities.iter().collect(),
+            self.resources.iter().collect(),
+        ];
+        let (mut counts, truncated) = write_sections(buffer, start, max_size, sections)?;
+
+        if truncated {
+            // The OPT record tells the client how large an answer it may ask
+            // us for over UDP, which matters most when this one is truncated.
+            // Room is kept for it, and it goes in after whatever else fits.
+            buffer.seek(body)?;
+            let opt = self.resources.iter().find(|rec| matches!(rec, DnsRecord::OPT { .. }));
+            let opt_size = match opt {
+                Some(rec) => rec.write(&mut VectorPacketBuffer::new())?,
+                None => 0,
+            };
+
+            let sections = [
+                self.answers.iter().collect(),
+                self.authorities.iter().collect(),
+                self.resources.iter().filter(|rec| !matches!(rec, DnsRecord::OPT { .. })).collect(),
+            ];
+            (counts, _) = write_sections(buffer, start, max_size.saturating_sub(opt_size), sections)?;
+
+            if let Some(rec) = opt {
+                rec.write(buffer)?;
+                counts[2] += 1;
+            }
+            self.header.truncated_message = true;
+        }
+
+        // Rewrite the header with the counts of what actually made it in
+        self.header.answers = counts[0];
+        self.header.authoritative_entries = counts[1];
+        self.header.resource_entries = counts[2];
+
+        let end = buffer.pos();
+        buffer.seek(start)?;
+        self.header.write(buffer)?;
+        buffer.seek(end)?;
+
+        Ok(())
+    }
+
+    /// Serialize the packet, returning exactly the bytes that were written
+    pub fn to_bytes(&mut self) -> Result<Vec<u8>, DnsError> {
+        self.to_bytes_with_limit(MAX_PACKET_SIZE)
+    }
+
+    /// Serialize the packet like `to_bytes`, truncating it to at most
+    /// `max_size` bytes as `write_with_limit` does
+    pub fn to_bytes_with_limit(&mut self, max_size: usize) -> Result<Vec<u8>, DnsError> {
+        let mut buffer = VectorPacketBuffer::new();
+        self.write_with_limit(&mut buffer, max_size)?;
+
+        let len = buffer.pos();
+        buffer.buf.truncate(len);
+
+        Ok(buffer.buf)
+    }
+
+    /// Parse a packet out of the raw bytes of a message
+    pub fn from_bytes(bytes: &[u8]) -> Result<DnsPacket, DnsError> {
+        let mut buffer = VectorPacketBuffer::with_capacity(bytes.len());
+        buffer.buf.copy_from_slice(bytes);
+
+        DnsPacket::from_buffer(&mut buffer)
+    }
+
+    /// Advertise EDNS support by placing an OPT pseudo-record in the
+    /// additional section, replacing any which was there before.
+    pub fn set_edns(&mut self, payload_size: u16, dnssec_ok: bool) {
+        self.resources.retain(|record| !matches!(record, DnsRecord::OPT { .. }));
+        self.resources.push(DnsRecord::OPT {
+            domain: Name::root(),
+            packet_len: payload_size,
+            extended_rcode: 0,
+            version: 0,
+            dnssec_ok,
+            options: Vec::new(),
+        });
+    }
+
+    /// Declare the UDP payload size we're able to receive, keeping the rest
+    /// of an existing OPT record intact.
+    pub fn set_edns_payload_size(&mut self, payload_size: u16) {
+        for record in self.resources.iter_mut() {
+            if let DnsRecord::OPT { packet_len, .. } = record {
+                *packet_len = payload_size;
+                return;
+            }
         }
 
-        for rec in &self.authorities {
-            rec.write(buffer)?;
+        self.set_edns(payload_size, false);
+    }
+
+    /// The payload size and DO flag from the packet's OPT record, if the
+    /// sender supports EDNS.
+    pub fn get_edns(&self) -> Option<(u16, bool)> {
+        self.resources_by_type(QueryType::OPT)
+            .filter_map(|record| match record {
+                DnsRecord::OPT {packet_len, dnssec_ok, .. } => Some((*packet_len, *dnssec_ok)),
+                _ => None,
+            })
+            .next()
+    }
+
+    /// The response code of the packet, including the upper eight bits of
+    /// extended codes which EDNS carries in the OPT record (RFC 6891).
+    pub fn rcode(&self) -> ResultCode {
+        let extended = self
+            .resources_by_type(QueryType::OPT)
+            .find_map(|record| match record {
+                DnsRecord::OPT { extended_rcode, .. } => Some(*extended_rcode),
+                _ => None,
+            })
+            .unwrap_or(0);
+
+        ResultCode::from_num(((extended as u16) << 4) | (self.header.rescode.to_num() & 0x0F))
+    }
+
+    /// Set the response code, splitting an extended code between the header
+    /// and the OPT record, which is added when it's needed and missing.
+    pub fn set_rcode(&mut self, rcode: ResultCode) {
+        let num = rcode.to_num();
+        self.header.rescode = ResultCode::from_num(num & 0x0F);
+
+        let extended = (num >> 4) as u8;
+        if extended > 0 && self.get_edns().is_none() {
+            self.set_edns(512, false);
         }
+        for record in self.resources.iter_mut() {
+            if let DnsRecord::OPT { extended_rcode, .. } = record {
+                *extended_rcode = extended;
+            }
+        }
+    }
+
+    /// Add the records of another packet to ours, skipping those we already
+    /// have. The OPT pseudo-record describes a single message, so the one
+    /// from `other` is left out.
+    pub fn merge(&mut self, other: &DnsPacket) {
+        let sections = [
+            (&mut self.answers, &other.answers),
+            (&mut self.authorities, &other.authorities),
+            (&mut self.resources, &other.resources),
+        ];
 
-        for rec in &self.resources {
-            rec.write(buffer)?;
+        for (records, others) in sections {
+            for record in others {
+                if !matches!(record, DnsRecord::OPT { .. }) && !records.contains(record) {
+                    records.push(record.clone());
+                }
+            }
         }
 
-        Ok(())
+        self.header.answers = self.answers.len() as u16;
+        self.header.authoritative_entries = self.authorities.len() as u16;
+        self.header.resource_entries = self.resources.len() as u16;
+    }
+
+    /// The records of the given type in the answer section
+    pub fn answers_by_type(&self, qtype: QueryType) -> impl Iterator<Item = &DnsRecord> {
+        records_of_type(&self.answers, qtype)
+    }
+
+    /// The records of the given type in the authority section
+    pub fn authorities_by_type(&self, qtype: QueryType) -> impl Iterator<Item = &DnsRecord> {
+        records_of_type(&self.authorities, qtype)
+    }
+
+    /// The records of the given type in the additional section
+    pub fn resources_by_type(&self, qtype: QueryType) -> impl Iterator<Item = &DnsRecord> {
+        records_of_type(&self.resources, qtype)
+    }
+
+    /// The (domain, address) of every A record in the answer section
+    pub fn a_records(&self) -> Vec<(&Name, Ipv4Addr)> {
+        self.answers_by_type(QueryType::A)
+            .filter_map(|record| match record {
+                DnsRecord::A {domain, addr, .. } => Some((domain, *addr)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The (domain, address) of every AAAA record in the answer section
+    pub fn aaaa_records(&self) -> Vec<(&Name, Ipv6Addr)> {
+        self.answers_by_type(QueryType::AAAA)
+            .filter_map(|record| match record {
+                DnsRecord::AAAA {domain, addr, .. } => Some((domain, *addr)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The (domain, priority, host) of every MX record in the answer section
+    pub fn mx_records(&self) -> Vec<(&Name, u16, &str)> {
+        self.answers_by_type(QueryType::MX)
+            .filter_map(|record| match record {
+                DnsRecord::MX {domain, priority, host, .. } => Some((domain, *priority, host.as_str())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The records in the answer section which belong to the given name and
+    /// are of the given type, leaving out the CNAMEs and such that lead up to
+    /// them. Names are compared case-insensitively.
+    pub fn answers_for(&self, name: &str, qtype: QueryType) -> Vec<&DnsRecord> {
+        records_for(&self.answers, name, qtype).collect()
     }
 
     /// It's useful to be able to pick a random A record from a packet.
     /// When we get multiple IP's for a single name.
     pub fn get_random_a(&self) -> Option<Ipv4Addr> {
-        self.answers
-            .iter()
+        self.get_random_a_with(&mut rand::thread_rng())
+    }
+
+    /// Same as `get_random_a`, but drawing from the given random number
+    /// generator so the choice can be made reproducible.
+    pub fn get_random_a_with<R: Rng>(&self, rng: &mut R) -> Option<Ipv4Addr> {
+        self.get_all_a().choose(rng).copied()
+    }
+
+    /// The addresses of every A record in the answer section, in the order
+    /// the server gave them.
+    pub fn get_all_a(&self) -> Vec<Ipv4Addr> {
+        self.a_records().into_iter().map(|(_, addr)| addr).collect()
+    }
+
+    /// The addresses of every A and AAAA record in the answer section, for
+    /// callers which can connect over either protocol. The IPv4 addresses
+    /// come first, each family in the order the server gave them.
+    pub fn get_all_addrs(&self) -> Vec<IpAddr> {
+        let v4 = self.a_records().into_iter().map(|(_, addr)| IpAddr::V4(addr));
+        let v6 = self.aaaa_records().into_iter().map(|(_, addr)| IpAddr::V6(addr));
+
+        v4.chain(v6).collect()
+    }
+
+    /// The IPv6 counterpart of `get_random_a`, picking an AAAA record
+    /// from the answer section.
+    pub fn get_random_aaaa(&self) -> Option<Ipv6Addr> {
+        self.aaaa_records().choose(&mut rand::thread_rng()).map(|&(_, addr)| addr)
+    }
+
+    /// Returns the alias target if the answer section holds a CNAME for
+    /// the given name.
+    pub fn get_cname(&self, qname: &str) -> Option<&str> {
+        records_for(&self.answers, qname, QueryType::CNAME)
+            .filter_map(|record| match record {
+                DnsRecord::CNAME {host, .. } => Some(host.as_str()),
+                _ => None,
+            })
+            .next()
+    }
+
+    /// The CNAME record a DNAME in the answer section implies for a name
+    /// below it, which is the name with the owner of the DNAME replaced by
+    /// its target (RFC 6672 section 2.2). Returns `None` when there's no such
+    /// DNAME, or when the resulting name would be too long.
+    pub fn synthesize_cname(&self, qname: &str) -> Option<DnsRecord> {
+        let name = Name::from(qname);
+
+        self.answers_by_type(QueryType::DNAME).find_map(|record| match record {
+            DnsRecord::DNAME { domain, target, class, ttl } => {
+                // The DNAME only covers names below its owner, not the owner
+                if name == *domain || !name.is_subdomain_of(domain) {
+                    return None;
+                }
+
+                let prefix = qname
+                    .trim_end_matches('.')
+                    .split('.')
+                    .take(name.label_count() - domain.label_count());
+                let host = prefix
+                    .chain(Some(target.trim_end_matches('.')))
+                    .collect::<Vec<_>>()
+                    .join(".");
+                host.parse::<Name>().ok()?;
+
+                Some(DnsRecord::CNAME {
+                    domain: name.clone(),
+                    host,
+                    class: *class,
+                    ttl: *ttl,
+                })
+            }
+            _ => None,
+        })
+    }
+
+    /// Returns the (priority, weight, port, host) of every SRV record in the
+    /// answer section which belongs to the queried service name.
+    pub fn get_srv_records<'a>(&'a self, qname: &str) -> Vec<(u16, u16, u16, &'a str)> {
+        records_for(&self.answers, qname, QueryType::SRV)
+            .filter_map(|record| match record {
+                DnsRecord::SRV {priority, weight, port, host, .. } => Some((*priority, *weight, *port, host.as_str())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The SRV records for a service in the order a client should try them,
+    /// following RFC 2782: ascending priority, and a weighted random pick
+    /// among records which share the same priority.
+    pub fn get_srv_sorted<'a>(&'a self, qname: &str) -> Vec<(u16, u16, u16, &'a str)> {
+        let mut records = self.get_srv_records(qname);
+
+        // Zero weight records go first within their priority, which gives
+        // them a small chance of being picked as the RFC asks.
+        records.sort_by_key(|&(priority, weight, _, _)| (priority, weight));
+
+        let mut rng = rand::thread_rng();
+        let mut sorted = Vec::with_capacity(records.len());
+        while !records.is_empty() {
+            let priority = records[0].0;
+            let group = records.iter().take_while(|record| record.0 == priority).count();
+
+            let total: u32 = records[..group].iter().map(|record| record.1 as u32).sum();
+            let pick = rng.gen_range(0..=total);
+
+            let mut running = 0;
+            let idx = records[..group]
+                .iter()
+                .position(|record| {
+                    running += record.1 as u32;
+                    running >= pick
+                })
+                .unwrap_or(0);
+
+            sorted.push(records.remove(idx));
+        }
+
+        sorted
+    }
+
+    /// Negative answers carry the zone's SOA record in the authority section,
+    /// whose `minimum` field tells us how long the absence may be cached.
+    pub fn get_soa_minimum(&self) -> Option<u32> {
+        self.authorities_by_type(QueryType::SOA)
             .filter_map(|record| match record {
-                DnsRecord::A {addr, .. } => Some(*addr),
+                DnsRecord::SOA {minimum, .. } => Some(*minimum),
+                _ => None,
+            })
+            .next()
+    }
+
+    /// How long a negative answer may be cached. RFC 2308 caps the SOA
+    /// `minimum` by the TTL of the SOA record itself.
+    pub fn get_negative_ttl(&self) -> Option<u32> {
+        self.authorities_by_type(QueryType::SOA)
+            .filter_map(|record| match record {
+                DnsRecord::SOA {minimum, ttl, .. } => Some(*minimum.min(ttl)),
                 _ => None,
             })
             .next()
@@ -96,41 +506,53 @@ impl DnsPacket {
 
     /// A helper function which returns an iterator over all name servers in
     /// the authorities section, represented as (domain, host) tuples
-    fn get_ns<'a>(&'a self, qname: &'a str) -> impl Iterator<Item = (&'a str, &'a str)> {
-        self.authorities
-            .iter()
-            // In practice, these are always NS records in well formed packages.
-            // Convert the NS records to a tuple which has only the data we need
-            // to make it easy to work with.
+    fn get_ns<'a>(&'a self, qname: &'a str) -> impl Iterator<Item = (&'a Name, &'a str)> {
+        let qname = Name::from(qname);
+
+        // In practice, these are always NS records in well formed packages.
+        // Convert the NS records to a tuple which has only the data we need
+        // to make it easy to work with.
+        self.authorities_by_type(QueryType::NS)
             .filter_map(|record| match record {
-                DnsRecord::NS {domain, host, .. } => Some((domain.as_str(), host.as_str())),
+                DnsRecord::NS {domain, host, .. } => Some((domain, host.as_str())),
                 _ => None,
             })
             // Discard servers which aren't authoritative to our query
-            .filter(move |(domain, _)| qname.ends_with(*domain))
+            .filter(move |(domain, _)| qname.is_subdomain_of(domain))
     }
 
     /// We'll use the fact that name servers often bundle the corresponding
     /// A records when replying to an NS query to implement a function that
     /// returns the actual IP for an NS record if possible.
     pub fn get_resolved_ns(&self, qname: &str) -> Option<Ipv4Addr> {
+        // Since we just want the first valid record, we can just build a
+        // stream of matching records.
+        self.resolved_ns(qname).next()
+    }
+
+    /// Like `get_resolved_ns`, but returns the address of every name server
+    /// which had a matching A record, so callers have something to fall back
+    /// on when one of them doesn't respond.
+    pub fn get_all_resolved_ns(&self, qname: &str) -> Vec<Ipv4Addr> {
+        self.resolved_ns(qname).collect()
+    }
+
+    /// The addresses the additional section gives for the name servers of
+    /// the authorities section which cover the query
+    fn resolved_ns<'a>(&'a self, qname: &'a str) -> impl Iterator<Item = Ipv4Addr> + 'a {
         // Get an iterator over the nameservers in the authorities section
         self.get_ns(qname)
             // Now we need to look for a matching A record in the additional
-            // section. Since we just want the first valid record, we can just
-            // build a stream of matching records.
+            // section
             .flat_map(|(_, host)| {
-                self.resources
-                    .iter()
-                    // Filter for A records where the domain match the host
-                    // of the NS record that we are currently processing
-                    .filter_map(move |record| match record {
-                        DnsRecord::A {domain, addr, .. } if domain == host => Some(addr),
+                // Filter for A records where the domain match the host
+                // of the NS record that we are currently processing
+                records_for(&self.resources, host, QueryType::A)
+                    .filter_map(|record| match record {
+                        DnsRecord::A {addr, .. } => Some(*addr),
                         _ => None,
                     })
             })
-            .map(|addr| *addr)
-            .next()
     }
 
     ///In certain cases there won't be any A records in the additional section,
@@ -142,198 +564,343 @@ impl DnsPacket {
     }
 }
 
-impl BytePacketBuffer {
+/// The records of a section which are of the given type
+fn records_of_type(records: &[DnsRecord], qtype: QueryType) -> impl Iterator<Item = &DnsRecord> {
+    records.iter().filter(move |record| record.qtype() == qtype)
+}
 
-    pub fn new() -> BytePacketBuffer {
-        BytePacketBuffer {
-            buf: [0; 512],
-            pos: 0,
+/// The records of a section which belong to the given name and are of the
+/// given type
+fn records_for<'a>(records: &'a [DnsRecord], name: &str, qtype: QueryType) -> impl Iterator<Item = &'a DnsRecord> {
+    let name = Name::from(name);
+    records_of_type(records, qtype).filter(move |record| *record.domain() == name)
+}
+
+/// Write the records of the answer, authority and additional sections for as
+/// long as the packet stays within `limit` bytes from `start`, returning how
+/// many of each made it in and whether any had to be left out
+fn write_sections<T: PacketBuffer>(
+    buffer: &mut T,
+    start: usize,
+    limit: usize,
+    sections: [Vec<&DnsRecord>; 3],
+) -> Result<([u16; 3], bool), DnsError> {
+    let mut counts = [0u16; 3];
+    for (count, records) in counts.iter_mut().zip(sections) {
+        for rec in records {
+            let pos = buffer.pos();
+            match rec.write(buffer) {
+                Ok(_) if buffer.pos() - start <= limit => *count += 1,
+                Ok(_) | Err(DnsError::EndOfBuffer) => {
+                    buffer.seek(pos)?;
+                    return Ok((counts, true));
+                }
+                Err(e) => return Err(e),
+            }
         }
     }
 
-    /// Current position within buffer
-    pub fn pos(&self) -> usize {
-        self.pos
+    Ok((counts, false))
+}
+
+/// The hex column of a line of `xxd` output, which starts with the offset
+/// and a colon and ends in the ASCII column after two spaces. Anything else
+/// is taken to be nothing but hex.
+fn xxd_bytes(line: &str) -> &str {
+    match line.trim_start().split_once(':') {
+        Some((offset, rest)) if offset.chars().all(|c| c.is_ascii_hexdigit()) => {
+            let rest = rest.trim_start();
+            rest.split_once("  ").map_or(rest, |(hex, _)| hex)
+        }
+        _ => line,
     }
+}
 
-    /// Step the buffer position forward a specific number of steps
-    pub fn step(&mut self, steps: usize) -> Result<(), Box<dyn Error>> {
-        self.pos += steps;
+impl DnsPacketBuilder {
+    pub fn query(qname: &str, qtype: QueryType) -> DnsPacketBuilder {
+        let mut packet = DnsPacket::new();
+        packet.header.set_query(rand::random());
+        packet.questions.push(DnsQuestion::new(Name::from(qname), qtype));
 
-        Ok(())
+        DnsPacketBuilder { packet }
     }
 
-    /// Change the buffer position
-    fn seek(&mut self, pos: usize) -> Result<(), Box<dyn Error>> {
-        self.pos = pos;
-        Ok(())
+    pub fn id(mut self, id: u16) -> DnsPacketBuilder {
+        self.packet.header.id = id;
+        self
     }
 
-    /// Read a single byte and move the position one step forward
-    fn read(&mut self) -> Result<u8, Box<dyn Error>> {
-        if self.pos >= 512 {
-            return Err("End of buffer".into());
+    pub fn recursion_desired(mut self, recursion_desired: bool) -> DnsPacketBuilder {
+        self.packet.header.recursion_desired = recursion_desired;
+        self
+    }
+
+    pub fn class(mut self, class: DnsClass) -> DnsPacketBuilder {
+        for question in &mut self.packet.questions {
+            question.class = class;
         }
-        let res = self.buf[self.pos];
-        self.pos += 1;
+        self
+    }
 
-        Ok(res)
+    pub fn build(self) -> DnsPacket {
+        self.packet
+    }
+}
+
+impl Default for BytePacketBuffer {
+    fn default() -> Self {
+        BytePacketBuffer::new()
     }
+}
 
-    /// Get a single byte, without changing the buffer position
-    fn get(&mut self, pos: usize) -> Result<u8, Box<dyn Error>> {
-        if self.pos >= 512 {
-            return Err("End of buffer".into());
+impl Default for VectorPacketBuffer {
+    fn default() -> Self {
+        VectorPacketBuffer::new()
+    }
+}
+
+/// A summary of the packet in the layout dig uses, with the header, the
+/// number of entries in each section and one line per record.
+impl fmt::Display for DnsPacket {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // The header as it will be written, with the real number of entries
+        // and the status including the bits carried by EDNS
+        let mut header = self.header.clone();
+        header.rescode = self.rcode();
+        header.questions = self.questions.len() as u16;
+        header.answers = self.answers.len() as u16;
+        header.authoritative_entries = self.authorities.len() as u16;
+        header.resource_entries = self.resources.len() as u16;
+        write!(f, "{}", header)?;
+
+        if !self.questions.is_empty() {
+            writeln!(f, "\n;; QUESTION SECTION:")?;
+            for question in &self.questions {
+                writeln!(f, "{}", question)?;
+            }
         }
-        Ok(self.buf[pos])
+
+        let sections = [
+            ("ANSWER", &self.answers),
+            ("AUTHORITY", &self.authorities),
+            ("ADDITIONAL", &self.resources),
+        ];
+        for (name, records) in sections {
+            if records.is_empty() {
+                continue;
+            }
+
+            writeln!(f, "\n;; {} SECTION:", name)?;
+            for rec in records {
+                writeln!(f, "{}", rec)?;
+            }
+        }
+
+        Ok(())
     }
+}
+
+/// The wire format of DNS messages, read from and written to a buffer which
+/// keeps track of the current position. Only the handful of methods which
+/// touch the bytes differ between buffers; the rest is built on them.
+pub trait PacketBuffer {
+    /// Current position within buffer
+    fn pos(&self) -> usize;
+
+    /// Change the buffer position, which may be anywhere up to and including
+    /// the end of the buffer
+    fn seek(&mut self, pos: usize) -> Result<(), DnsError>;
+
+    /// Get a single byte, without changing the buffer position
+    fn get(&self, pos: usize) -> Result<u8, DnsError>;
 
     /// Get a range of bytes
-    pub fn get_range(&mut self, start: usize, len: usize)
-    -> Result<&[u8], Box<dyn Error>> {
-        if start + len >= 512 {
-            return Err("End of buffer".into());
+    fn get_range(&self, start: usize, len: usize) -> Result<&[u8], DnsError>;
+
+    /// Write a single byte and move the position one step forward
+    fn write(&mut self, val: u8) -> Result<(), DnsError>;
+
+    /// Overwrite a byte which has already been written
+    fn set(&mut self, pos: usize, val: u8) -> Result<(), DnsError>;
+
+    /// Whether names are kept exactly as they're written in the packet when
+    /// reading, rather than lowercased
+    fn preserve_case(&self) -> bool;
+
+    /// Step the buffer position forward a specific number of steps
+    fn step(&mut self, steps: usize) -> Result<(), DnsError> {
+        match self.pos().checked_add(steps) {
+            Some(pos) => self.seek(pos),
+            None => Err(DnsError::EndOfBuffer),
         }
-        Ok(&self.buf[start..start + len as usize])
+    }
+
+    /// Read a single byte and move the position one step forward
+    fn read(&mut self) -> Result<u8, DnsError> {
+        let res = self.get(self.pos())?;
+        self.step(1)?;
+
+        Ok(res)
+    }
+
+    /// Read a range of bytes, stepping past them
+    fn read_bytes(&mut self, len: usize) -> Result<&[u8], DnsError> {
+        let start = self.pos();
+        self.step(len)?;
+
+        self.get_range(start, len)
+    }
+
+    /// Read a single byte, stepping one step forward
+    fn read_u8(&mut self) -> Result<u8, DnsError> {
+        self.read()
     }
 
     /// Read two bytes, stepping two steps forward
-    pub fn read_u16(&mut self) -> Result <u16, Box<dyn Error>> {
+    fn read_u16(&mut self) -> Result <u16, DnsError> {
         let res = ((self.read()? as u16) << 8) | (self.read()? as u16);
 
         Ok(res)
     }
 
     // Read four bytes, stepping four steps forward
-    pub fn read_u32(&mut self) -> Result<u32, Box<dyn Error>> {
+    fn read_u32(&mut self) -> Result<u32, DnsError> {
         let res = ((self.read()? as u32) << 24)
             | ((self.read()? as u32) << 16)
             | ((self.read()? as u32) << 8)
-            | ((self.read()? as u32) << 0);
+            | (self.read()? as u32);
         
         Ok(res)
     }
+
+    /// Read sixteen bytes, stepping sixteen steps forward
+    fn read_u128(&mut self) -> Result<u128, DnsError> {
+        let res = ((self.read_u32()? as u128) << 96)
+            | ((self.read_u32()? as u128) << 64)
+            | ((self.read_u32()? as u128) << 32)
+            | (self.read_u32()? as u128);
+
+        Ok(res)
+    }
+
     /// Read a qname
     /// 
     /// The tricky part: Reading domain names, taking labels into consideration.
     /// Will take something like [3]www[6]google[3]com and append
     /// www.google.com to outstr.
-    pub fn read_qname(&mut self, outstr: &mut String) -> Result<(), Box<dyn Error>> {
-        // Since we might encounter jumps, we'll keep track of our position
-        // locally as opposed to using the position within the struct. This
-        // allows us to move the shared position to a point past our current
-        // qname, while keeping track of our progress on the current qname
-        // using this variable.
-        let mut pos = self.pos();
-
-        // track wheter or not we've jumped
-        let mut jumped = false;
-        let max_jumps = 5;
-        let mut jumps_performed = 0;
-
-        // Our delimiter which we append for each label. Since we don't want a
-        // dot at the beginning of the domain name we'll leave it empty for now
-        // and set it to "." at the end of the first iteration.
-        let mut delim = "";
-        loop {
-            // Dns Packets are untrusted data, so we need to be paranoid. Someone
-            // can craft a packet with a cycle in the jump instructions. This guards
-            // against such packets.
-            if jumps_performed > max_jumps {
-                return Err(format!("Limit of {} jumps exceeded", max_jumps).into());
-            }
-
-            // At this point, we're always at the beginning of a label. Recall
-            // that labels start with a length byte.
-            let len = self.get(pos)?;
-
-            if (len & 0xC0) == 0xC0 {
-                // Update the buffer position to a point past the current
-                //label. We don't need to touch it any further.
-                if !jumped {
-                    self.seek(pos + 2)?;
-                }
+    ///
+    /// Names are lowercased, since they're compared case-insensitively,
+    /// unless the buffer is set to preserve their case.
+    fn read_qname(&mut self, outstr: &mut String) -> Result<(), DnsError> {
+        let mut name = String::new();
+        read_labels(self, &mut name, true)?;
+        push_name(self, outstr, name);
 
-                // Read another byte, calculate offset and perform the jump by
-                // updating our local position variable
-                let b2 = self.get(pos + 1)? as u16;
-                let offset = (((len as u16) ^ 0xC0) << 8) | b2;
-                pos = offset as usize;
-
-                jumped = true;
-                jumps_performed += 1;
+        Ok(())
+    }
 
-                continue;
-            }
-            // The base scenario, where we're reading a single label and
-            // appending it to the output:
-            else {
-                // Move a single byte forward to move past the length byte.
-                pos += 1;
-
-                // Domain names are terminated by an empty label of length 0,
-                // so if the length is zero we're done.
-                if len == 0 {
-                    break;
-                }
-                
-                outstr.push_str(delim);
+    /// Read a name, such as the owner of a record
+    fn read_name(&mut self) -> Result<Name, DnsError> {
+        let mut name = String::new();
+        self.read_qname(&mut name)?;
 
-                // Extract the actual ASCII bytes for this label and append them
-                // to the output buffer.
-                let str_buffer = self.get_range(pos, len as usize)?;
-                outstr.push_str(&String::from_utf8_lossy(str_buffer).to_lowercase());
+        Ok(Name::from(name.as_str()))
+    }
 
-                delim = ".";
+    /// Read a qname which may not be compressed, such as the replacement of
+    /// a NAPTR record. A pointer is an error rather than being followed.
+    fn read_qname_uncompressed(&mut self, outstr: &mut String) -> Result<(), DnsError> {
+        let mut name = String::new();
+        read_labels(self, &mut name, false)?;
+        push_name(self, outstr, name);
 
-                // Move forward the full length of the label.
-                pos += len as usize;
-            }
-        }
+        Ok(())
+    }
 
-        if !jumped {
-            self.seek(pos)?;
-        }
+    /// Read a qname exactly as it's written in the packet
+    fn read_qname_preserving_case(&mut self, outstr: &mut String) -> Result<(), DnsError> {
+        read_labels(self, outstr, true)
+    }
 
-        Ok(())
+    /// Read a qname which may not be compressed exactly as it's written in
+    /// the packet, such as the signer name of an RRSIG record.
+    fn read_qname_uncompressed_preserving_case(&mut self, outstr: &mut String) -> Result<(), DnsError> {
+        read_labels(self, outstr, false)
     }
 
-    fn write(&mut self, val: u8) -> Result<(), Box<dyn Error>> {
-        if self.pos >= 512 {
-            return Err("End of buffer".into());
+    /// Write a range of bytes
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), DnsError> {
+        for &b in bytes {
+            self.write(b)?;
         }
-        self.buf[self.pos] = val;
-        self.pos += 1;
+
         Ok(())
     }
 
-    pub fn write_u8(&mut self, val: u8) -> Result<(), Box<dyn Error>> {
+    fn write_u8(&mut self, val: u8) -> Result<(), DnsError> {
         self.write(val)?;
 
         Ok(())
     }
 
-    pub fn write_u16(&mut self, val: u16) -> Result<(), Box<dyn Error>> {
+    fn write_u16(&mut self, val: u16) -> Result<(), DnsError> {
         self.write((val >> 8) as u8)?;
         self.write((val & 0xFF) as u8)?;
 
         Ok(())
     }
 
-    pub fn write_u32(&mut self, val: u32) -> Result<(), Box<dyn Error>> {
+    fn write_u32(&mut self, val: u32) -> Result<(), DnsError> {
         self.write(((val >> 24) & 0xFF) as u8)?;
         self.write(((val >> 16) & 0xFF) as u8)?;
         self.write(((val >> 8) & 0xFF) as u8)?;
-        self.write(((val >> 0) & 0xFF) as u8)?;
+        self.write((val & 0xFF) as u8)?;
 
         Ok(()) 
     }
 
-    pub fn write_qname(&mut self, qname: &str) -> Result<(), Box<dyn Error>> {
+    fn write_u128(&mut self, val: u128) -> Result<(), DnsError> {
+        self.write_u32((val >> 96) as u32)?;
+        self.write_u32((val >> 64) as u32)?;
+        self.write_u32((val >> 32) as u32)?;
+        self.write_u32(val as u32)?;
+
+        Ok(())
+    }
+
+    fn write_qname(&mut self, qname: &str) -> Result<(), DnsError> {
+        // Unicode labels have to be turned into their ASCII form first
+        let encoded;
+        let qname = if qname.is_ascii() {
+            qname
+        } else {
+            encoded = idna::to_ascii(qname);
+            &encoded
+        };
+
+        // A single trailing dot just spells out the root explicitly
+        let qname = qname.strip_suffix('.').unwrap_or(qname);
+
+        // The root name is nothing but the terminating empty label
+        if qname.is_empty() {
+            return self.write_u8(0);
+        }
+
+        if qname.split('.').any(|label| label.is_empty()) {
+            return Err(DnsError::EmptyLabel);
+        }
+
+        // Every label costs its length byte on top of its contents, and the
+        // terminating zero counts towards the limit as well
+        let total = qname.split('.').map(|label| label.len() + 1).sum::<usize>() + 1;
+        if total > 255 {
+            return Err(DnsError::NameTooLong);
+        }
+
         for label in qname.split('.') {
             let len = label.len();
             if len > 0x3f {
-                return Err("Single label exceeds 63 characters of length.".into());
+                return Err(DnsError::LabelTooLong);
             }
 
             self.write_u8(len as u8)?;
@@ -348,16 +915,1798 @@ impl BytePacketBuffer {
         Ok(())
     }
 
-    pub fn set(&mut self, pos: usize, val: u8) -> Result<(), Box<dyn Error>> {
-        self.buf[pos] = val;
-
-        Ok(())
+    /// Write a name, such as the owner of a record
+    fn write_name(&mut self, name: &Name) -> Result<(), DnsError> {
+        self.write_qname(&name.to_string())
     }
 
-    pub fn set_u16(&mut self, pos: usize, val: u16) -> Result<(), Box<dyn Error>> {
+    fn set_u16(&mut self, pos: usize, val: u16) -> Result<(), DnsError> {
         self.set(pos, (val >> 8) as u8)?;
         self.set(pos + 1, (val & 0xFF) as u8)?;
 
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+/// Append a name which has been read, lowercased unless the buffer is set
+/// to preserve its case
+fn push_name<T: PacketBuffer + ?Sized>(buffer: &T, outstr: &mut String, name: String) {
+    if buffer.preserve_case() {
+        outstr.push_str(&name);
+    } else {
+        outstr.push_str(&name.to_lowercase());
+    }
+}
+
+fn read_labels<T: PacketBuffer + ?Sized>(buffer: &mut T, outstr: &mut String, follow_pointers: bool) -> Result<(), DnsError> {
+    // Since we might encounter jumps, we'll keep track of our position
+    // locally as opposed to using the position within the struct. This
+    // allows us to move the shared position to a point past our current
+    // qname, while keeping track of our progress on the current qname
+    // using this variable.
+    let mut pos = buffer.pos();
+
+    // track wheter or not we've jumped
+    let mut jumped = false;
+
+    // Where the labels we're currently reading started. A pointer has to
+    // lead somewhere before that, so every jump takes us further towards
+    // the start of the packet, and the jumps can't go round in circles.
+    let mut segment_start = pos;
+
+    // Our delimiter which we append for each label. Since we don't want a
+    // dot at the beginning of the domain name we'll leave it empty for now
+    // and set it to "." at the end of the first iteration.
+    let mut delim = "";
+
+    // The encoded length of the name so far, counting the terminating
+    // zero up front. It may not exceed 255 bytes, however the labels are
+    // spread out through compression.
+    let mut name_len = 1;
+    loop {
+        // At this point, we're always at the beginning of a label. Recall
+        // that labels start with a length byte.
+        let len = buffer.get(pos)?;
+
+        if (len & 0xC0) == 0xC0 {
+            // Update the buffer position to a point past the current
+            //label. We don't need to touch it any further.
+            if !jumped {
+                buffer.seek(pos + 2)?;
+            }
+
+            // Read another byte, calculate offset and perform the jump by
+            // updating our local position variable
+            let b2 = buffer.get(pos + 1)? as u16;
+            let offset = (((len as u16) ^ 0xC0) << 8) | b2;
+
+            // Dns Packets are untrusted data, so we need to be paranoid.
+            // Someone can craft a packet with pointers to themselves or
+            // with a cycle of them, which would keep us going forever.
+            if !follow_pointers || offset as usize >= segment_start {
+                return Err(DnsError::InvalidPointer(offset as usize));
+            }
+            pos = offset as usize;
+            segment_start = pos;
+
+            jumped = true;
+
+            continue;
+        }
+        // The base scenario, where we're reading a single label and
+        // appending it to the output:
+        else {
+            // Move a single byte forward to move past the length byte.
+            pos += 1;
+
+            // Length bytes starting with 01 or 10 aren't pointers, but
+            // aren't valid lengths either, as labels stop at 63 bytes.
+            if len > 0x3f {
+                return Err(DnsError::LabelTooLong);
+            }
+
+            // Domain names are terminated by an empty label of length 0,
+            // so if the length is zero we're done.
+            if len == 0 {
+                break;
+            }
+
+            name_len += len as usize + 1;
+            if name_len > 255 {
+                return Err(DnsError::NameTooLong);
+            }
+            
+            outstr.push_str(delim);
+
+            // Extract the actual ASCII bytes for this label and append them
+            // to the output buffer.
+            let str_buffer = buffer.get_range(pos, len as usize)?;
+            outstr.push_str(&String::from_utf8_lossy(str_buffer));
+
+            delim = ".";
+
+            // Move forward the full length of the label.
+            pos += len as usize;
+        }
+    }
+
+    if !jumped {
+        buffer.seek(pos)?;
+    }
+
+    Ok(())
+}
+
+impl BytePacketBuffer {
+    /// A zeroed buffer of the classic 512 bytes a UDP message is limited to
+    pub fn new() -> BytePacketBuffer {
+        BytePacketBuffer {
+            buf: [0; 512],
+            pos: 0,
+            preserve_case: false,
+        }
+    }
+}
+
+impl PacketBuffer for BytePacketBuffer {
+    fn pos(&self) -> usize {
+        self.pos
+    }
+
+    fn seek(&mut self, pos: usize) -> Result<(), DnsError> {
+        if pos > self.buf.len() {
+            return Err(DnsError::EndOfBuffer);
+        }
+        self.pos = pos;
+
+        Ok(())
+    }
+
+    fn get(&self, pos: usize) -> Result<u8, DnsError> {
+        if pos >= self.buf.len() {
+            return Err(DnsError::EndOfBuffer);
+        }
+        Ok(self.buf[pos])
+    }
+
+    fn get_range(&self, start: usize, len: usize) -> Result<&[u8], DnsError> {
+        match start.checked_add(len) {
+            Some(end) if end <= self.buf.len() => Ok(&self.buf[start..end]),
+            _ => Err(DnsError::EndOfBuffer),
+        }
+    }
+
+    /// Writing past the 512 bytes fails, the buffer never grows
+    fn write(&mut self, val: u8) -> Result<(), DnsError> {
+        if self.pos >= self.buf.len() {
+            return Err(DnsError::EndOfBuffer);
+        }
+        self.buf[self.pos] = val;
+        self.pos += 1;
+
+        Ok(())
+    }
+
+    fn set(&mut self, pos: usize, val: u8) -> Result<(), DnsError> {
+        if pos >= self.buf.len() {
+            return Err(DnsError::EndOfBuffer);
+        }
+        self.buf[pos] = val;
+
+        Ok(())
+    }
+
+    fn preserve_case(&self) -> bool {
+        self.preserve_case
+    }
+}
+
+impl VectorPacketBuffer {
+    /// A zeroed buffer of 512 bytes, which grows as more is written
+    pub fn new() -> VectorPacketBuffer {
+        VectorPacketBuffer::with_capacity(512)
+    }
+
+    /// A zeroed buffer of the given size, for receiving messages which may
+    /// be larger than 512 bytes. Writing past the end grows the buffer.
+    pub fn with_capacity(size: usize) -> VectorPacketBuffer {
+        VectorPacketBuffer {
+            buf: vec![0; size],
+            pos: 0,
+            preserve_case: false,
+            max_size: MAX_PACKET_SIZE,
+        }
+    }
+
+    /// A buffer holding the bytes of a hex dump. Whitespace between bytes is
+    /// ignored, and so the bytes may be grouped in any way. The offsets and
+    /// ASCII columns of `hexdump -C` and `xxd` output are skipped, so dumps
+    /// of either can be pasted in as they are.
+    pub fn from_hex(s: &str) -> Result<VectorPacketBuffer, DnsError> {
+        // The ASCII column of `hexdump -C` is set off by bars, and lines
+        // without one only hold the offset of the end
+        let canonical = s.contains('|');
+
+        let mut buf = Vec::new();
+        for line in s.lines() {
+            let hex = match line.split_once('|') {
+                Some((hex, _)) => hex.split_once(char::is_whitespace).map_or("", |(_, bytes)| bytes),
+                None if canonical => continue,
+                None => xxd_bytes(line),
+            };
+
+            for token in hex.split_whitespace() {
+                if token.len() % 2 != 0 {
+                    return Err(DnsError::InvalidHex(token.to_string()));
+                }
+                for i in (0..token.len()).step_by(2) {
+                    let byte = token
+                        .get(i..i + 2)
+                        .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                        .ok_or_else(|| DnsError::InvalidHex(token.to_string()))?;
+                    buf.push(byte);
+                }
+            }
+        }
+
+        Ok(VectorPacketBuffer { buf, pos: 0, preserve_case: false, max_size: MAX_PACKET_SIZE })
+    }
+
+    /// The bytes written so far as space separated hex, the inverse of
+    /// `from_hex`
+    pub fn to_hex(&self) -> String {
+        self.buf[..self.pos]
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// A buffer holding everything a reader has to offer, such as a packet
+    /// stored in a file. Only the bytes actually read make up the buffer, so
+    /// reading past them fails rather than turning up stale zeros.
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<VectorPacketBuffer, DnsError> {
+        let mut buf = Vec::new();
+        reader.take(MAX_PACKET_SIZE as u64).read_to_end(&mut buf)?;
+
+        Ok(VectorPacketBuffer { buf, pos: 0, preserve_case: false, max_size: MAX_PACKET_SIZE })
+    }
+
+    /// Read a single message from a TCP stream, where it's preceded by its
+    /// length as a two byte integer (RFC 1035 section 4.2.2). The buffer is
+    /// sized to fit the message, however large it is.
+    pub fn read_tcp<R: Read>(stream: &mut R) -> Result<VectorPacketBuffer, DnsError> {
+        let mut len_bytes = [0; 2];
+        stream.read_exact(&mut len_bytes)?;
+        let len = u16::from_be_bytes(len_bytes) as usize;
+
+        let mut buffer = VectorPacketBuffer::with_capacity(len);
+        stream.read_exact(&mut buffer.buf)?;
+
+        Ok(buffer)
+    }
+
+    /// Send the bytes written so far over a TCP stream, preceded by their
+    /// length as `read_tcp` expects.
+    pub fn write_tcp<W: Write>(&self, stream: &mut W) -> Result<(), DnsError> {
+        let message = &self.buf[..self.pos];
+        if message.len() > MAX_PACKET_SIZE {
+            return Err(DnsError::EndOfBuffer);
+        }
+
+        stream.write_all(&(message.len() as u16).to_be_bytes())?;
+        stream.write_all(message)?;
+
+        Ok(())
+    }
+
+    /// The first `len` bytes of the buffer in the layout of `hexdump -C`:
+    /// the offset, sixteen bytes in hex and the same bytes as ASCII on each
+    /// line. `from_hex` reads it back.
+    pub fn hexdump(&self, len: usize) -> String {
+        let len = len.min(self.buf.len());
+
+        let mut out = String::new();
+        for (i, chunk) in self.buf[..len].chunks(16).enumerate() {
+            let mut hex = String::new();
+            for (j, b) in chunk.iter().enumerate() {
+                if j == 8 {
+                    hex.push(' ');
+                }
+                hex.push_str(&format!("{:02x} ", b));
+            }
+
+            let ascii: String = chunk
+                .iter()
+                .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+                .collect();
+
+            out.push_str(&format!("{:08x}  {:<49} |{}|\n", i * 16, hex, ascii));
+        }
+
+        out
+    }
+}
+
+impl PacketBuffer for VectorPacketBuffer {
+    fn pos(&self) -> usize {
+        self.pos
+    }
+
+    fn seek(&mut self, pos: usize) -> Result<(), DnsError> {
+        if pos > self.buf.len() {
+            return Err(DnsError::EndOfBuffer);
+        }
+        self.pos = pos;
+
+        Ok(())
+    }
+
+    fn get(&self, pos: usize) -> Result<u8, DnsError> {
+        if pos >= self.buf.len() {
+            return Err(DnsError::EndOfBuffer);
+        }
+        Ok(self.buf[pos])
+    }
+
+    fn get_range(&self, start: usize, len: usize) -> Result<&[u8], DnsError> {
+        match start.checked_add(len) {
+            Some(end) if end <= self.buf.len() => Ok(&self.buf[start..end]),
+            _ => Err(DnsError::EndOfBuffer),
+        }
+    }
+
+    /// Writing past the end grows the buffer, up to `max_size`
+    fn write(&mut self, val: u8) -> Result<(), DnsError> {
+        self.write_bytes(&[val])
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), DnsError> {
+        let end = self.pos + bytes.len();
+        if end > self.max_size {
+            return Err(DnsError::EndOfBuffer);
+        }
+        if end > self.buf.len() {
+            self.buf.resize(end, 0);
+        }
+        self.buf[self.pos..end].copy_from_slice(bytes);
+        self.pos = end;
+
+        Ok(())
+    }
+
+    fn set(&mut self, pos: usize, val: u8) -> Result<(), DnsError> {
+        if pos >= self.buf.len() {
+            return Err(DnsError::EndOfBuffer);
+        }
+        self.buf[pos] = val;
+
+        Ok(())
+    }
+
+    fn preserve_case(&self) -> bool {
+        self.preserve_case
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_random_aaaa_picks_every_answer() {
+        let mut packet = DnsPacket::new();
+        for addr in ["2001:db8::1", "2001:db8::2"] {
+            packet.answers.push(DnsRecord::AAAA {
+                domain: "example.com".into(),
+                addr: addr.parse().unwrap(),
+                class: DnsClass::IN,
+                ttl: 60,
+            });
+        }
+
+        let picked: std::collections::HashSet<Ipv6Addr> = (0..100)
+            .map(|_| packet.get_random_aaaa().unwrap())
+            .collect();
+        assert_eq!(picked.len(), 2);
+        assert_eq!(DnsPacket::new().get_random_aaaa(), None);
+    }
+
+    #[test]
+    fn referral_from_root_server() {
+        // A root server referring a query for www.google.com to the com
+        // servers, with glue for each of them
+        let mut buffer = VectorPacketBuffer::from_hex(
+            "3a7c 8000 0001 0000 0003 0004 0377 7777
+            0667 6f6f 676c 6503 636f 6d00 0001 0001
+            c017 0002 0001 0002 a300 0014 0161 0c67
+            746c 642d 7365 7276 6572 7303 6e65 7400
+            c017 0002 0001 0002 a300 0004 0162 c02e
+            c017 0002 0001 0002 a300 0004 0163 c02e
+            c02c 0001 0001 0002 a300 0004 c005 061e
+            c04c 0001 0001 0002 a300 0004 c021 0e1e
+            c05c 0001 0001 0002 a300 0004 c01a 5c1e
+            c02c 001c 0001 0002 a300 0010 2001 0503
+            a83e 0000 0000 0000 0002 0030",
+        )
+        .unwrap();
+        let packet = DnsPacket::from_buffer(&mut buffer).unwrap();
+
+        assert_eq!(packet.authorities.len(), 3);
+        assert_eq!(packet.get_unresolved_ns("www.google.com"), Some("a.gtld-servers.net"));
+        assert_eq!(packet.get_resolved_ns("www.google.com"), Some(Ipv4Addr::new(192, 5, 6, 30)));
+        assert_eq!(packet.get_unresolved_ns("www.example.org"), None);
+    }
+
+    #[test]
+    fn mx_response_for_yahoo() {
+        // The answer to the client's default query, yahoo.com MX
+        let mut buffer = VectorPacketBuffer::from_hex(
+            "1b2f 8180 0001 0003 0000 0000 0579 6168
+            6f6f 0363 6f6d 0000 0f00 01c0 0c00 0f00
+            0100 0007 0800 1900 0104 6d74 6135 0361
+            6d30 0879 6168 6f6f 646e 7303 6e65 7400
+            c00c 000f 0001 0000 0708 0009 0001 046d
+            7461 36c0 2ec0 0c00 0f00 0100 0007 0800
+            0900 0104 6d74 6137 c02e",
+        )
+        .unwrap();
+        let packet = DnsPacket::from_buffer(&mut buffer).unwrap();
+
+        let hosts: Vec<&str> = packet
+            .answers
+            .iter()
+            .map(|record| match record {
+                DnsRecord::MX { priority: 1, host, .. } => host.as_str(),
+                _ => panic!("not an MX record: {:?}", record),
+            })
+            .collect();
+        assert_eq!(hosts, ["mta5.am0.yahoodns.net", "mta6.am0.yahoodns.net", "mta7.am0.yahoodns.net"]);
+    }
+
+    #[test]
+    fn get_cname_of_cdn_hosted_name() {
+        // www.github.io is an alias, and its addresses only follow the CNAME
+        let mut buffer = VectorPacketBuffer::from_hex(
+            "5d21 8180 0001 0003 0000 0000 0377 7777
+            0667 6974 6875 6202 696f 0000 0100 01c0
+            0c00 0500 0100 000e 1000 0906 6769 7468
+            7562 c010 c02b 0001 0001 0000 0e10 0004
+            b9c7 6c99 c02b 0001 0001 0000 0e10 0004
+            b9c7 6d99",
+        )
+        .unwrap();
+        let packet = DnsPacket::from_buffer(&mut buffer).unwrap();
+
+        assert_eq!(packet.get_cname("www.github.io"), Some("github.github.io"));
+        assert_eq!(packet.get_cname("github.github.io"), None);
+        assert_eq!(
+            packet.answers_for("github.github.io", QueryType::A).len(),
+            2
+        );
+    }
+
+    #[test]
+    fn mx_exchangers_round_trip() {
+        let mut packet = DnsPacketBuilder::query("gmail.com", QueryType::MX).build();
+        packet.header.response = true;
+        for (priority, host) in [(5, "gmail-smtp-in.l.google.com"), (10, "alt1.gmail-smtp-in.l.google.com"), (40, "alt4.gmail-smtp-in.l.google.com")] {
+            packet.answers.push(DnsRecord::MX {
+                domain: "gmail.com".into(),
+                priority,
+                host: host.to_string(),
+                class: DnsClass::IN,
+                ttl: 3600,
+            });
+        }
+
+        let parsed = DnsPacket::from_bytes(&packet.to_bytes().unwrap()).unwrap();
+        assert_eq!(parsed.answers, packet.answers);
+    }
+
+    #[test]
+    fn nxdomain_with_soa_authority() {
+        // A public resolver's answer for a name which doesn't exist, with
+        // the SOA of the zone as the only authority record
+        let mut buffer = VectorPacketBuffer::from_hex(
+            "0c4e 8183 0001 0000 0001 0000 0b6e 6f6e
+            6578 6973 7465 6e74 0765 7861 6d70 6c65
+            0363 6f6d 0000 0100 01c0 1800 0600 0100
+            0003 8400 2c02 6e73 0569 6361 6e6e 036f
+            7267 0003 6e6f 6303 646e 73c0 3878 a507
+            f900 001c 2000 000e 1000 1275 0000 000e
+            10",
+        )
+        .unwrap();
+        let packet = DnsPacket::from_buffer(&mut buffer).unwrap();
+
+        assert_eq!(packet.header.rescode, ResultCode::NXDOMAIN);
+        assert!(packet.answers.is_empty());
+        match &packet.authorities[..] {
+            [DnsRecord::SOA { domain, mname, rname, serial, .. }] => {
+                assert_eq!(domain, "example.com");
+                assert_eq!(mname, "ns.icann.org");
+                assert_eq!(rname, "noc.dns.icann.org");
+                assert_eq!(*serial, 2024081401);
+            }
+            authorities => panic!("unexpected authorities {:?}", authorities),
+        }
+        assert_eq!(packet.get_soa_minimum(), Some(3600));
+        assert_eq!(packet.get_negative_ttl(), Some(900));
+    }
+
+    #[test]
+    fn get_srv_sorted_follows_priority_and_weight() {
+        let mut packet = DnsPacket::new();
+        for (priority, weight, host) in [(20, 0, "backup"), (10, 60, "heavy"), (10, 40, "light")] {
+            packet.answers.push(DnsRecord::SRV {
+                domain: "_sip._tcp.example.com".into(),
+                priority,
+                weight,
+                port: 5060,
+                host: host.to_string(),
+                class: DnsClass::IN,
+                ttl: 60,
+            });
+        }
+
+        // The record with the higher weight should come first about 60% of
+        // the time, and the lower priority one always last
+        let mut heavy_first = 0;
+        for _ in 0..1000 {
+            let sorted = packet.get_srv_sorted("_sip._tcp.example.com");
+            assert_eq!(sorted[2].3, "backup");
+            if sorted[0].3 == "heavy" {
+                heavy_first += 1;
+            }
+        }
+        assert!((500..700).contains(&heavy_first), "{}", heavy_first);
+    }
+
+    #[test]
+    fn reverse_lookup_of_google_dns() {
+        let question = DnsQuestion::reverse("8.8.8.8".parse().unwrap());
+        assert_eq!(question.name, "8.8.8.8.in-addr.arpa");
+
+        let mut buffer = VectorPacketBuffer::from_hex(
+            "7e11 8180 0001 0001 0000 0000 0138 0138
+            0138 0138 0769 6e2d 6164 6472 0461 7270
+            6100 000c 0001 c00c 000c 0001 0000 5460
+            000c 0364 6e73 0667 6f6f 676c 6500",
+        )
+        .unwrap();
+        let packet = DnsPacket::from_buffer(&mut buffer).unwrap();
+
+        assert_eq!(packet.questions, [question]);
+        match &packet.answers[..] {
+            [DnsRecord::PTR { host, .. }] => assert_eq!(host, "dns.google"),
+            answers => panic!("unexpected answers {:?}", answers),
+        }
+    }
+
+    #[test]
+    fn opt_with_nsid_round_trip() {
+        let mut packet = DnsPacketBuilder::query("example.com", QueryType::A).build();
+        packet.set_edns(1232, true);
+        if let DnsRecord::OPT { options, .. } = &mut packet.resources[0] {
+            // NSID, the identifier of the name server (RFC 5001)
+            options.push((3, b"ns1.example".to_vec()));
+        }
+
+        let parsed = DnsPacket::from_bytes(&packet.to_bytes().unwrap()).unwrap();
+        assert_eq!(parsed.resources, packet.resources);
+        assert_eq!(parsed.get_edns(), Some((1232, true)));
+    }
+
+    #[test]
+    fn get_past_the_end_is_an_error() {
+        let buffer = VectorPacketBuffer::new();
+        assert_eq!(buffer.pos(), 0);
+        assert!(matches!(buffer.get(600), Err(DnsError::EndOfBuffer)));
+        assert!(buffer.get(511).is_ok());
+        assert!(buffer.get(512).is_err());
+    }
+
+    #[test]
+    fn get_range_bounds() {
+        let buffer = VectorPacketBuffer::new();
+        assert_eq!(buffer.get_range(0, 512).unwrap().len(), 512);
+        assert_eq!(buffer.get_range(512, 0).unwrap().len(), 0);
+        assert!(buffer.get_range(1, 512).is_err());
+        assert!(buffer.get_range(usize::MAX, 2).is_err());
+    }
+
+    #[test]
+    fn ds_set_in_root_referral() {
+        // A root server referring to com, with the DS record of its KSK
+        let mut buffer = VectorPacketBuffer::from_hex(
+            "4410 8000 0001 0000 0002 0001 0765 7861
+            6d70 6c65 0363 6f6d 0000 0100 01c0 1400
+            0200 0100 02a3 0000 1401 610c 6774 6c64
+            2d73 6572 7665 7273 036e 6574 00c0 1400
+            2b00 0100 0151 8000 244d 060d 028a cbb0
+            cd28 f412 50a8 0a49 1389 424d 3415 22d9
+            46b0 da0c 0291 f2d3 d771 d780 5ac0 2900
+            0100 0100 02a3 0000 04c0 0506 1e",
+        )
+        .unwrap();
+        let packet = DnsPacket::from_buffer(&mut buffer).unwrap();
+
+        match &packet.authorities[1] {
+            DnsRecord::DS { domain, key_tag, algorithm, digest_type, digest, .. } => {
+                assert_eq!(domain, "com");
+                assert_eq!((*key_tag, *algorithm, *digest_type), (19718, 13, 2));
+                assert_eq!(digest.len(), 32);
+                assert_eq!(digest[..4], [0x8a, 0xcb, 0xb0, 0xcd]);
+            }
+            record => panic!("not a DS record: {:?}", record),
+        }
+        assert_eq!(packet.get_resolved_ns("example.com"), Some(Ipv4Addr::new(192, 5, 6, 30)));
+    }
+
+    #[test]
+    fn packet_larger_than_512_bytes_round_trip() {
+        let mut packet = DnsPacket::new();
+        for i in 0..60 {
+            packet.answers.push(DnsRecord::A {
+                domain: "example.com".into(),
+                addr: Ipv4Addr::new(10, 0, 0, i),
+                class: DnsClass::IN,
+                ttl: 60,
+            });
+        }
+
+        let mut buffer = VectorPacketBuffer::new();
+        packet.write(&mut buffer).unwrap();
+        assert!(buffer.pos() > 512);
+
+        buffer.seek(0).unwrap();
+        assert_eq!(DnsPacket::from_buffer(&mut buffer).unwrap().answers, packet.answers);
+    }
+
+    #[test]
+    fn hinfo_answer_to_any_query() {
+        // Cloudflare's RFC 8482 answer to cloudflare.com ANY
+        let mut buffer = VectorPacketBuffer::from_hex(
+            "3c1a 8180 0001 0001 0000 0000 0a63 6c6f
+            7564 666c 6172 6503 636f 6d00 00ff 0001
+            c00c 000d 0001 0000 0ecd 0009 0752 4643
+            3834 3832 00",
+        )
+        .unwrap();
+        let mut packet = DnsPacket::from_buffer(&mut buffer).unwrap();
+
+        let hinfo = DnsRecord::HINFO {
+            domain: "cloudflare.com".into(),
+            cpu: "RFC8482".to_string(),
+            os: String::new(),
+            class: DnsClass::IN,
+            ttl: 3789,
+        };
+        assert_eq!(packet.answers, [hinfo]);
+
+        let rewritten = DnsPacket::from_bytes(&packet.to_bytes().unwrap()).unwrap();
+        assert_eq!(rewritten.answers, packet.answers);
+    }
+
+    #[test]
+    fn get_random_a_spreads_over_every_address() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let addrs = [Ipv4Addr::new(192, 0, 2, 1), Ipv4Addr::new(192, 0, 2, 2), Ipv4Addr::new(192, 0, 2, 3)];
+        let mut packet = DnsPacket::new();
+        for addr in addrs {
+            packet.answers.push(DnsRecord::A {
+                domain: "example.com".into(),
+                addr,
+                class: DnsClass::IN,
+                ttl: 60,
+            });
+        }
+
+        let draw = |seed| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            (0..3000).map(|_| packet.get_random_a_with(&mut rng).unwrap()).collect::<Vec<_>>()
+        };
+
+        // The same seed makes the same choices
+        let picks = draw(42);
+        assert_eq!(picks, draw(42));
+
+        for addr in addrs {
+            let count = picks.iter().filter(|&&picked| picked == addr).count();
+            assert!((800..1200).contains(&count), "{} picked {} times", addr, count);
+        }
+    }
+
+    #[test]
+    fn bytes_to_packet_and_back() {
+        // example.com A, with no compressed names since we never write any
+        let bytes = VectorPacketBuffer::from_hex(
+            "9a3c 8180 0001 0001 0000 0000 0765 7861
+            6d70 6c65 0363 6f6d 0000 0100 0107 6578
+            616d 706c 6503 636f 6d00 0001 0001 0000
+            0e10 0004 5db8 d822",
+        )
+        .unwrap()
+        .buf;
+
+        let mut packet = DnsPacket::from_bytes(&bytes).unwrap();
+        assert_eq!(packet.header.id, 0x9a3c);
+        assert_eq!(packet.get_random_a(), Some(Ipv4Addr::new(93, 184, 216, 34)));
+
+        assert_eq!(packet.to_bytes().unwrap(), bytes);
+    }
+
+    #[test]
+    fn names_are_limited_to_255_bytes() {
+        // Labels of 49 letters take 50 bytes each on the wire
+        let name = |labels: usize| vec!["a".repeat(49); labels].join(".");
+
+        let mut buffer = VectorPacketBuffer::new();
+        assert!(matches!(buffer.write_qname(&name(6)), Err(DnsError::NameTooLong)));
+        assert_eq!(buffer.pos(), 0);
+
+        buffer.write_qname(&name(4)).unwrap();
+        assert_eq!(buffer.pos(), 201);
+        buffer.seek(0).unwrap();
+
+        let mut read = String::new();
+        buffer.read_qname(&mut read).unwrap();
+        assert_eq!(read, name(4));
+    }
+
+    #[test]
+    fn empty_labels_and_trailing_dots() {
+        let written = |name: &str| {
+            let mut buffer = VectorPacketBuffer::new();
+            buffer.write_qname(name).map(|_| buffer.get_range(0, buffer.pos()).unwrap().to_vec())
+        };
+
+        assert!(matches!(written("foo..com"), Err(DnsError::EmptyLabel)));
+        assert!(matches!(written(".com"), Err(DnsError::EmptyLabel)));
+        assert_eq!(written("foo.com.").unwrap(), written("foo.com").unwrap());
+        assert_eq!(written("foo.com").unwrap(), b"\x03foo\x03com\x00");
+        assert_eq!(written(".").unwrap(), [0]);
+        assert_eq!(written("").unwrap(), [0]);
+    }
+
+    #[test]
+    fn unknown_type_reserializes_byte_for_byte() {
+        // An answer of the private use type 65280, which we know nothing of
+        let bytes = VectorPacketBuffer::from_hex(
+            "004d 8180 0001 0001 0000 0000 0765 7861
+            6d70 6c65 0363 6f6d 00ff 0000 0107 6578
+            616d 706c 6503 636f 6d00 ff00 0001 0000
+            003c 0006 dead beef 0001",
+        )
+        .unwrap()
+        .buf;
+
+        let mut packet = DnsPacket::from_bytes(&bytes).unwrap();
+        assert_eq!(
+            packet.answers,
+            [DnsRecord::UNKNOWN {
+                domain: "example.com".into(),
+                qtype: 65280,
+                rdata: vec![0xde, 0xad, 0xbe, 0xef, 0x00, 0x01],
+                class: DnsClass::IN,
+                ttl: 60,
+            }]
+        );
+
+        assert_eq!(packet.to_bytes().unwrap(), bytes);
+    }
+
+    /// A response to example.com A with more addresses than fit in 512 bytes
+    fn oversized_response() -> DnsPacket {
+        let mut packet = DnsPacket::response_to(&DnsPacket::query("example.com", QueryType::A));
+        for i in 0..100u32 {
+            packet.answers.push(DnsRecord::A {
+                domain: "example.com".into(),
+                addr: Ipv4Addr::from(0xc0000200 + i),
+                class: DnsClass::IN,
+                ttl: 60,
+            });
+        }
+
+        packet
+    }
+
+    #[test]
+    fn truncation_sets_tc_and_counts() {
+        let mut packet = oversized_response();
+        let bytes = packet.to_bytes_with_limit(512).unwrap();
+        assert!(bytes.len() <= 512);
+
+        let truncated = DnsPacket::from_bytes(&bytes).unwrap();
+        assert!(truncated.header.truncated_message);
+        assert_eq!(truncated.header.answers as usize, truncated.answers.len());
+        assert!(truncated.answers.len() < 100);
+        assert_eq!(truncated.answers[..], packet.answers[..truncated.answers.len()]);
+
+        // Without a limit everything fits and nothing is marked as missing
+        let mut packet = oversized_response();
+        let full = DnsPacket::from_bytes(&packet.to_bytes().unwrap()).unwrap();
+        assert!(!full.header.truncated_message);
+        assert_eq!(full.answers.len(), 100);
+    }
+
+    #[test]
+    fn truncation_keeps_the_opt_record() {
+        let mut packet = oversized_response();
+        packet.set_edns_payload_size(1232);
+
+        let bytes = packet.to_bytes_with_limit(512).unwrap();
+        assert!(bytes.len() <= 512);
+
+        let truncated = DnsPacket::from_bytes(&bytes).unwrap();
+        assert!(truncated.header.truncated_message);
+        assert_eq!(truncated.header.resource_entries, 1);
+        assert_eq!(truncated.get_edns(), Some((1232, false)));
+        assert_eq!(truncated.header.answers as usize, truncated.answers.len());
+    }
+
+    #[test]
+    fn records_keep_their_order_when_nothing_is_truncated() {
+        let mut packet = DnsPacket::response_to(&DnsPacket::query("example.com", QueryType::A));
+        packet.set_edns(1232, false);
+        packet.resources.push(DnsRecord::A {
+            domain: "ns1.example.com".into(),
+            addr: Ipv4Addr::new(192, 0, 2, 53),
+            class: DnsClass::IN,
+            ttl: 3600,
+        });
+
+        // The OPT record stays ahead of the glue, and the bytes read back
+        // are written out again unchanged
+        let bytes = packet.to_bytes().unwrap();
+        let mut parsed = DnsPacket::from_bytes(&bytes).unwrap();
+        assert!(matches!(parsed.resources[0], DnsRecord::OPT { .. }));
+        assert_eq!(parsed.resources, packet.resources);
+        assert_eq!(parsed.to_bytes().unwrap(), bytes);
+
+        // A second OPT record is still counted along with what was written
+        packet.resources.push(packet.resources[0].clone());
+        let parsed = DnsPacket::from_bytes(&packet.to_bytes().unwrap()).unwrap();
+        assert_eq!(parsed.header.resource_entries, 3);
+        assert_eq!(parsed.resources, packet.resources);
+    }
+
+    #[test]
+    fn a_response_from_hex() {
+        // google.com A, as tcpdump -x shows it
+        let dump = "8680 8180 0001 0001 0000 0000 0667 6f6f
+            676c 6503 636f 6d00 0001 0001 c00c 0001
+            0001 0000 012c 0004 acd9 0ece";
+        let mut buffer = VectorPacketBuffer::from_hex(dump).unwrap();
+        let packet = DnsPacket::from_buffer(&mut buffer).unwrap();
+
+        assert_eq!(packet.questions[0].name, "google.com");
+        assert_eq!(packet.get_random_a(), Some(Ipv4Addr::new(172, 217, 14, 206)));
+        assert_eq!(buffer.to_hex().replace(' ', ""), dump.split_whitespace().collect::<String>());
+
+        assert!(VectorPacketBuffer::from_hex("abc").is_err());
+        assert!(VectorPacketBuffer::from_hex("zz").is_err());
+    }
+
+    #[test]
+    fn display_shows_counts_and_record_lines() {
+        let mut packet = DnsPacket::response_to(&DnsPacket::query("google.com", QueryType::A));
+        packet.header.id = 0x8680;
+        packet.answers.push(DnsRecord::A {
+            domain: "google.com".into(),
+            addr: Ipv4Addr::new(172, 217, 14, 206),
+            class: DnsClass::IN,
+            ttl: 300,
+        });
+
+        let out = packet.to_string();
+        assert!(out.contains("flags: qr rd ra;"), "{}", out);
+        assert!(out.contains("QUERY: 1, ANSWER: 1, AUTHORITY: 0, ADDITIONAL: 0"), "{}", out);
+        assert!(out.contains(";; QUESTION SECTION:\n;google.com.\t\tIN\tA\n"), "{}", out);
+        assert!(out.contains(";; ANSWER SECTION:\ngoogle.com.\t300\tIN\tA\t172.217.14.206\n"), "{}", out);
+        assert!(!out.contains("AUTHORITY SECTION"), "{}", out);
+    }
+
+    #[test]
+    fn any_query_asks_for_type_255() {
+        let qtype: QueryType = "ANY".parse().unwrap();
+        assert_eq!(qtype, QueryType::ANY);
+
+        let bytes = DnsPacket::query("example.com", qtype).to_bytes().unwrap();
+        assert_eq!(bytes[bytes.len() - 4..], [0, 255, 0, 1]);
+    }
+
+    #[test]
+    fn version_bind_answer_keeps_the_chaos_class() {
+        // BIND's answer to version.bind TXT CH
+        let mut buffer = VectorPacketBuffer::from_hex(
+            "5d2e 8500 0001 0001 0001 0000 0776 6572
+            7369 6f6e 0462 696e 6400 0010 0003 c00c
+            0010 0003 0000 0000 0008 0739 2e31 382e
+            3234 c00c 0002 0003 0000 0000 0002 c00c",
+        )
+        .unwrap();
+        let mut packet = DnsPacket::from_buffer(&mut buffer).unwrap();
+
+        assert_eq!(packet.questions[0].class, DnsClass::CH);
+        assert_eq!(packet.answers[0].class(), DnsClass::CH);
+        assert_eq!(packet.answers[0].text().unwrap(), "9.18.24");
+        assert_eq!(packet.authorities[0].class(), DnsClass::CH);
+
+        let rewritten = DnsPacket::from_bytes(&packet.to_bytes().unwrap()).unwrap();
+        assert_eq!(rewritten.questions, packet.questions);
+        assert_eq!(rewritten.answers, packet.answers);
+        assert_eq!(rewritten.authorities, packet.authorities);
+    }
+
+    #[test]
+    fn four_kilobyte_packet_round_trip() {
+        let mut packet = DnsPacket::response_to(&DnsPacket::query("example.com", QueryType::TXT));
+        for i in 0..16u8 {
+            packet.answers.push(DnsRecord::TXT {
+                domain: "example.com".into(),
+                strings: vec![vec![b'a' + i; 255]],
+                class: DnsClass::IN,
+                ttl: 60,
+            });
+        }
+
+        // The buffer starts out at 512 bytes and grows as it's written
+        let mut buffer = VectorPacketBuffer::new();
+        packet.write(&mut buffer).unwrap();
+        assert!(buffer.pos() > 4096);
+
+        buffer.seek(0).unwrap();
+        assert_eq!(DnsPacket::from_buffer(&mut buffer).unwrap().answers, packet.answers);
+
+        // Over UDP without EDNS the same answer still has to fit in 512 bytes
+        let bytes = packet.to_bytes_with_limit(512).unwrap();
+        assert!(bytes.len() <= 512);
+        assert!(DnsPacket::from_bytes(&bytes).unwrap().header.truncated_message);
+
+        // The fixed buffer can't hold any more than that, so writing into it
+        // truncates the packet just the same
+        let mut fixed = BytePacketBuffer::new();
+        packet.write(&mut fixed).unwrap();
+        assert!(fixed.pos() <= 512);
+        fixed.seek(0).unwrap();
+        let truncated = DnsPacket::from_buffer(&mut fixed).unwrap();
+        assert!(truncated.header.truncated_message);
+        assert_eq!(truncated.answers[..], packet.answers[..truncated.answers.len()]);
+    }
+
+    #[test]
+    fn fixed_buffer_stops_at_512_bytes() {
+        let mut buffer = BytePacketBuffer::new();
+        buffer.write_bytes(&[0; 510]).unwrap();
+        buffer.write_u16(0xffff).unwrap();
+        assert_eq!(buffer.pos(), 512);
+
+        assert!(matches!(buffer.write_u8(0), Err(DnsError::EndOfBuffer)));
+        assert!(matches!(buffer.seek(513), Err(DnsError::EndOfBuffer)));
+        assert!(matches!(buffer.get_range(256, 257), Err(DnsError::EndOfBuffer)));
+        assert_eq!(buffer.get_range(510, 2).unwrap(), [0xff, 0xff]);
+    }
+
+    #[test]
+    fn buffer_doesnt_grow_past_the_largest_message() {
+        let mut buffer = VectorPacketBuffer::with_capacity(MAX_PACKET_SIZE);
+        buffer.seek(MAX_PACKET_SIZE - 1).unwrap();
+        buffer.write_u8(0).unwrap();
+
+        assert!(matches!(buffer.write_u8(0), Err(DnsError::EndOfBuffer)));
+        assert!(matches!(buffer.write_bytes(&[0]), Err(DnsError::EndOfBuffer)));
+
+        // The bound can be set lower than the largest message
+        let mut buffer = VectorPacketBuffer::new();
+        buffer.max_size = 1232;
+        buffer.write_bytes(&[0; 1232]).unwrap();
+        assert!(matches!(buffer.write_u8(0), Err(DnsError::EndOfBuffer)));
+    }
+
+    #[test]
+    fn builder_makes_a_valid_query() {
+        let mut packet = DnsPacketBuilder::query("example.com", QueryType::MX).id(42).recursion_desired(false).build();
+        let query = DnsPacket::from_bytes(&packet.to_bytes().unwrap()).unwrap();
+
+        assert_eq!(query.header.id, 42);
+        assert!(!query.header.response);
+        assert!(!query.header.recursion_desired);
+        assert_eq!(query.header.questions, 1);
+        assert_eq!(query.questions, [DnsQuestion::new(Name::from("example.com"), QueryType::MX)]);
+        assert!(query.answers.is_empty());
+
+        // Recursion is asked for unless told otherwise
+        assert!(DnsPacketBuilder::query("example.com", QueryType::A).build().header.recursion_desired);
+    }
+
+    #[test]
+    fn malicious_packets_are_errors() {
+        // An answer whose name is a pointer to offset 5000
+        let mut buffer =
+            VectorPacketBuffer::from_hex("0001 8180 0000 0001 0000 0000 d388 0001 0001 0000 0001 0004 0102 0304").unwrap();
+        assert!(DnsPacket::from_buffer(&mut buffer).is_err());
+
+        // A question with a label running past the end
+        let mut buffer = VectorPacketBuffer::from_hex("0001 8180 0001 0000 0000 0000 3f61 6263").unwrap();
+        assert!(DnsPacket::from_buffer(&mut buffer).is_err());
+
+        // An answer with more rdata than there are bytes left
+        let mut buffer =
+            VectorPacketBuffer::from_hex("0001 8180 0000 0001 0000 0000 0000 0100 0100 0000 0100 1001 0203 04").unwrap();
+        assert!(DnsPacket::from_buffer(&mut buffer).is_err());
+
+        let mut buffer = VectorPacketBuffer::new();
+        assert!(buffer.seek(512).is_ok());
+        assert!(buffer.seek(513).is_err());
+        assert!(buffer.step(1).is_err());
+    }
+
+    #[test]
+    fn record_ending_at_the_last_byte_is_read() {
+        let mut packet = DnsPacket::new();
+        packet.answers.push(DnsRecord::TXT {
+            domain: Name::root(),
+            strings: DnsRecord::text_strings(&"x".repeat(487)),
+            class: DnsClass::IN,
+            ttl: 60,
+        });
+        let bytes = packet.to_bytes().unwrap();
+        assert_eq!(bytes.len(), 512);
+
+        let mut buffer = VectorPacketBuffer::new();
+        buffer.buf.copy_from_slice(&bytes);
+        assert_eq!(DnsPacket::from_buffer(&mut buffer).unwrap().answers, packet.answers);
+    }
+
+    #[test]
+    fn name_limits_on_read() {
+        let encode = |labels: &[String]| {
+            let mut bytes = Vec::new();
+            for label in labels {
+                bytes.push(label.len() as u8);
+                bytes.extend_from_slice(label.as_bytes());
+            }
+            bytes.push(0);
+            bytes
+        };
+        let buffer_of = |bytes: Vec<u8>| {
+            let mut buffer = VectorPacketBuffer::with_capacity(bytes.len());
+            buffer.buf.copy_from_slice(&bytes);
+            buffer
+        };
+
+        // 127 labels of a single letter take exactly 255 bytes
+        let mut buffer = buffer_of(encode(&vec!["a".to_string(); 127]));
+        assert_eq!(buffer.buf.len(), 255);
+        let mut name = String::new();
+        buffer.read_qname(&mut name).unwrap();
+        assert_eq!(name.len(), 253);
+        VectorPacketBuffer::new().write_qname(&name).unwrap();
+
+        // Five labels of 59 letters make a name of 301 bytes
+        let mut buffer = buffer_of(encode(&vec!["a".repeat(59); 5]));
+        assert!(matches!(buffer.read_qname(&mut String::new()), Err(DnsError::NameTooLong)));
+
+        // A label can't be longer than 63 bytes
+        let mut buffer = buffer_of(encode(&["a".repeat(64)]));
+        assert!(buffer.read_qname(&mut String::new()).is_err());
+
+        assert!(matches!(VectorPacketBuffer::new().write_qname("foo..bar"), Err(DnsError::EmptyLabel)));
+    }
+
+    #[test]
+    fn any_response_with_mixed_types() {
+        // example.com ANY, answered with MX, NS and A records
+        let mut buffer = VectorPacketBuffer::from_hex(
+            "2b1d 8180 0001 0003 0000 0000 0765 7861
+            6d70 6c65 0363 6f6d 0000 ff00 01c0 0c00
+            0f00 0100 000e 1000 0900 0a04 6d61 696c
+            c00c c00c 0002 0001 0000 0e10 0006 036e
+            7331 c00c c00c 0001 0001 0000 012c 0004
+            5db8 d822",
+        )
+        .unwrap();
+        let packet = DnsPacket::from_buffer(&mut buffer).unwrap();
+
+        assert_eq!(packet.questions[0].qtype, QueryType::ANY);
+        let types: Vec<QueryType> = packet.answers.iter().map(|record| record.qtype()).collect();
+        assert_eq!(types, [QueryType::MX, QueryType::NS, QueryType::A]);
+        assert!(matches!(&packet.answers[0], DnsRecord::MX { priority: 10, host, .. } if host == "mail.example.com"));
+        assert!(matches!(&packet.answers[1], DnsRecord::NS { host, .. } if host == "ns1.example.com"));
+        assert_eq!(packet.get_random_a(), Some(Ipv4Addr::new(93, 184, 216, 34)));
+    }
+
+    #[test]
+    fn bulk_reads_and_writes_at_the_edges() {
+        let mut buffer = VectorPacketBuffer::with_capacity(4);
+        buffer.write_bytes(&[]).unwrap();
+        assert_eq!(buffer.pos(), 0);
+
+        buffer.write_bytes(&[1, 2, 3, 4]).unwrap();
+        assert_eq!(buffer.buf.len(), 4);
+        buffer.write_bytes(&[5]).unwrap();
+        assert_eq!(buffer.buf, [1, 2, 3, 4, 5]);
+
+        buffer.seek(0).unwrap();
+        assert!(buffer.read_bytes(0).unwrap().is_empty());
+        assert_eq!(buffer.read_bytes(5).unwrap(), [1, 2, 3, 4, 5]);
+        assert!(buffer.read_bytes(0).unwrap().is_empty());
+        assert!(matches!(buffer.read_bytes(1), Err(DnsError::EndOfBuffer)));
+
+        // A failed read leaves the position where it was
+        buffer.seek(3).unwrap();
+        assert!(buffer.read_bytes(3).is_err());
+        assert_eq!(buffer.pos(), 3);
+    }
+
+    #[test]
+    fn merge_drops_shared_records() {
+        let a = |addr: [u8; 4]| DnsRecord::A {
+            domain: "www.example.com".into(),
+            addr: Ipv4Addr::from(addr),
+            class: DnsClass::IN,
+            ttl: 300,
+        };
+        let cname = DnsRecord::CNAME {
+            domain: "example.com".into(),
+            host: "www.example.com".to_string(),
+            class: DnsClass::IN,
+            ttl: 300,
+        };
+
+        let mut first = DnsPacket::response_to(&DnsPacket::query("example.com", QueryType::A));
+        first.answers = vec![cname.clone(), a([192, 0, 2, 1])];
+        first.header.answers = 2;
+
+        let mut second = DnsPacket::response_to(&DnsPacket::query("www.example.com", QueryType::A));
+        second.answers = vec![a([192, 0, 2, 1]), a([192, 0, 2, 2])];
+        second.header.answers = 2;
+
+        first.merge(&second);
+        assert_eq!(first.answers, vec![cname, a([192, 0, 2, 1]), a([192, 0, 2, 2])]);
+        assert_eq!(first.header.answers, 3);
+        assert!(first.questions.iter().all(|q| q.name == "example.com"));
+
+        let parsed = DnsPacket::from_bytes(&first.to_bytes().unwrap()).unwrap();
+        assert_eq!(parsed.answers, first.answers);
+    }
+
+    #[test]
+    fn sixty_a_records_are_truncated_to_512_bytes() {
+        let mut packet = DnsPacket::response_to(&DnsPacket::query("example.com", QueryType::A));
+        for i in 0..60u8 {
+            packet.answers.push(DnsRecord::A {
+                domain: Name::from(format!("host{}.example.com", i).as_str()),
+                addr: Ipv4Addr::new(198, 51, 100, i),
+                class: DnsClass::IN,
+                ttl: 3600,
+            });
+        }
+
+        let bytes = packet.to_bytes_with_limit(512).unwrap();
+        assert!(bytes.len() <= 512);
+
+        let parsed = DnsPacket::from_bytes(&bytes).unwrap();
+        assert!(parsed.header.truncated_message);
+        assert!(!parsed.answers.is_empty() && parsed.answers.len() < 60);
+        assert_eq!(parsed.header.answers as usize, parsed.answers.len());
+        assert_eq!(parsed.answers[..], packet.answers[..parsed.answers.len()]);
+    }
+
+    #[test]
+    fn case_is_kept_when_asked_for() {
+        let mut query = DnsPacket::query("ExAmPlE.CoM", QueryType::NS);
+        let bytes = query.to_bytes().unwrap();
+
+        assert_eq!(DnsPacket::from_bytes(&bytes).unwrap().questions[0].name.to_string(), "example.com");
+
+        let mut buffer = VectorPacketBuffer::with_capacity(bytes.len());
+        buffer.buf.copy_from_slice(&bytes);
+        buffer.preserve_case = true;
+        assert_eq!(DnsPacket::from_buffer(&mut buffer).unwrap().questions[0].name.to_string(), "ExAmPlE.CoM");
+
+        // Matching against a name as it was written still ignores case
+        let mut referral = DnsPacket::new();
+        referral.authorities.push(DnsRecord::NS {
+            domain: "ExAmPlE.CoM".into(),
+            host: "ns1.example.com".to_string(),
+            class: DnsClass::IN,
+            ttl: 3600,
+        });
+        let ns: Vec<_> = referral.get_ns("www.example.com").map(|(domain, host)| (domain.to_string(), host)).collect();
+        assert_eq!(ns, [("ExAmPlE.CoM".to_string(), "ns1.example.com")]);
+        assert_eq!(referral.get_ns("WWW.EXAMPLE.COM").count(), 1);
+        assert_eq!(referral.get_ns("example.org").count(), 0);
+    }
+
+    #[test]
+    fn unicode_names_are_written_as_punycode() {
+        let mut buffer = VectorPacketBuffer::new();
+        buffer.write_qname("bücher.example").unwrap();
+        assert_eq!(buffer.get_range(0, buffer.pos()).unwrap(), b"\x0dxn--bcher-kva\x07example\x00");
+
+        let mut query = DnsPacket::query("bücher.example", QueryType::A);
+        let bytes = query.to_bytes().unwrap();
+        assert_eq!(&bytes[12..bytes.len() - 4], b"\x0dxn--bcher-kva\x07example\x00");
+        assert_eq!(DnsPacket::from_bytes(&bytes).unwrap().questions[0].name, "xn--bcher-kva.example");
+    }
+
+    #[test]
+    fn name_servers_only_cover_their_own_zone() {
+        let mut referral = DnsPacket::new();
+        referral.authorities.push(DnsRecord::NS {
+            domain: "example.com".into(),
+            host: "ns1.example.com".to_string(),
+            class: DnsClass::IN,
+            ttl: 3600,
+        });
+
+        assert_eq!(referral.get_ns("www.example.com").count(), 1);
+        assert_eq!(referral.get_ns("notexample.com").count(), 0);
+    }
+
+    #[test]
+    fn hexdump_reads_back() {
+        let bytes = VectorPacketBuffer::from_hex(
+            "8680 8180 0001 0001 0000 0000 0667 6f6f
+            676c 6503 636f 6d00 0001 0001 c00c 0001
+            0001 0000 012c 0004 acd9 0ece",
+        )
+        .unwrap()
+        .buf;
+
+        let mut buffer = VectorPacketBuffer::with_capacity(bytes.len());
+        buffer.buf.copy_from_slice(&bytes);
+        let dump = buffer.hexdump(bytes.len());
+        assert_eq!(
+            dump.lines().next().unwrap(),
+            "00000000  86 80 81 80 00 01 00 01  00 00 00 00 06 67 6f 6f  |.............goo|"
+        );
+        assert_eq!(dump.lines().count(), 3);
+
+        let mut read = VectorPacketBuffer::from_hex(&dump).unwrap();
+        assert_eq!(read.buf, bytes);
+        assert_eq!(DnsPacket::from_buffer(&mut read).unwrap().questions[0].name, "google.com");
+
+        // The same bytes as xxd shows them
+        let xxd = "00000000: 8680 8180 0001 0001 0000 0000 0667 6f6f  .............goo
+            00000010: 676c 6503 636f 6d00 0001 0001 c00c 0001  gle.com.........
+            00000020: 0001 0000 012c 0004 acd9 0ece            .....,......";
+        assert_eq!(VectorPacketBuffer::from_hex(xxd).unwrap().buf, bytes);
+    }
+
+    #[test]
+    fn pointers_only_lead_backwards() {
+        let read_at = |bytes: &[u8], pos: usize| {
+            let mut buffer = VectorPacketBuffer::with_capacity(bytes.len());
+            buffer.buf.copy_from_slice(bytes);
+            buffer.seek(pos).unwrap();
+            let mut name = String::new();
+            buffer.read_qname(&mut name).map(|_| (name, buffer.pos()))
+        };
+
+        // A pointer to itself
+        assert!(matches!(read_at(b"\xc0\x00", 0), Err(DnsError::InvalidPointer(0))));
+
+        // Two pointers to one another
+        assert!(matches!(read_at(b"\xc0\x02\xc0\x00", 2), Err(DnsError::InvalidPointer(2))));
+
+        // A pointer to a name further on
+        assert!(matches!(read_at(b"\xc0\x02\x01a\x00", 0), Err(DnsError::InvalidPointer(2))));
+
+        // Eight pointers in a row, each one leading further back
+        let mut bytes = b"\x01a\x00".to_vec();
+        let mut previous = 0;
+        for label in b'b'..=b'i' {
+            let start = bytes.len();
+            bytes.extend_from_slice(&[1, label, 0xc0, previous]);
+            previous = start as u8;
+        }
+        let end = bytes.len();
+        assert_eq!(read_at(&bytes, previous as usize).unwrap(), ("i.h.g.f.e.d.c.b.a".to_string(), end));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn response_survives_json() {
+        let mut buffer = VectorPacketBuffer::from_hex(
+            "8680 8180 0001 0001 0000 0000 0667 6f6f
+            676c 6503 636f 6d00 0001 0001 c00c 0001
+            0001 0000 012c 0004 acd9 0ece",
+        )
+        .unwrap();
+        let packet = DnsPacket::from_buffer(&mut buffer).unwrap();
+
+        let json = serde_json::to_string(&packet).unwrap();
+        assert!(json.contains(r#""addr":"172.217.14.206""#), "{}", json);
+
+        let read: DnsPacket = serde_json::from_str(&json).unwrap();
+        assert_eq!(read, packet);
+    }
+
+    #[test]
+    fn tcp_messages_are_framed_by_their_length() {
+        use std::io::{Cursor, Read};
+
+        let mut large = DnsPacket::response_to(&DnsPacket::query("example.com", QueryType::TXT));
+        large.answers.push(DnsRecord::TXT {
+            domain: "example.com".into(),
+            strings: DnsRecord::text_strings(&"x".repeat(1940)),
+            class: DnsClass::IN,
+            ttl: 60,
+        });
+        let mut small = DnsPacket::query("example.com", QueryType::A);
+
+        let mut stream = Vec::new();
+        for packet in [&mut large, &mut small] {
+            let mut buffer = VectorPacketBuffer::new();
+            packet.write(&mut buffer).unwrap();
+            buffer.write_tcp(&mut stream).unwrap();
+        }
+        let large_len = u16::from_be_bytes([stream[0], stream[1]]) as usize;
+        assert!(large_len >= 2000, "{}", large_len);
+
+        // A reader handing out a single byte at a time, as a slow socket might
+        struct Trickle(Cursor<Vec<u8>>);
+        impl Read for Trickle {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let len = buf.len().min(1);
+                self.0.read(&mut buf[..len])
+            }
+        }
+
+        let mut reader = Trickle(Cursor::new(stream.clone()));
+        let mut first = VectorPacketBuffer::read_tcp(&mut reader).unwrap();
+        assert_eq!(first.buf.len(), large_len);
+        assert_eq!(DnsPacket::from_buffer(&mut first).unwrap().answers, large.answers);
+
+        let mut second = VectorPacketBuffer::read_tcp(&mut reader).unwrap();
+        assert_eq!(DnsPacket::from_buffer(&mut second).unwrap().questions, small.questions);
+
+        // Nothing is left, and a message cut short is an error
+        assert!(matches!(VectorPacketBuffer::read_tcp(&mut reader), Err(DnsError::Io(_))));
+        let mut cut = Cursor::new(&stream[..large_len]);
+        assert!(matches!(VectorPacketBuffer::read_tcp(&mut cut), Err(DnsError::Io(_))));
+    }
+
+    #[test]
+    fn only_the_bytes_read_are_parsed() {
+        use std::io::Cursor;
+
+        let mut query = DnsPacketBuilder::query("mail.examples-test.com", QueryType::MX).id(0x1234).build();
+        let fixture = query.to_bytes().unwrap();
+        assert_eq!(fixture.len(), 40);
+
+        let mut buffer = VectorPacketBuffer::read_from(&mut Cursor::new(&fixture)).unwrap();
+        assert_eq!(buffer.buf.len(), 40);
+        let packet = DnsPacket::from_buffer(&mut buffer).unwrap();
+        assert_eq!(packet.questions, query.questions);
+        assert_eq!(buffer.pos(), 40);
+
+        // Nothing lies beyond the fixture, not even zeros
+        assert!(matches!(buffer.read_u8(), Err(DnsError::EndOfBuffer)));
+        assert!(buffer.seek(41).is_err());
+        assert!(buffer.get_range(36, 5).is_err());
+
+        // So a header claiming a second question can't be satisfied
+        let mut claims_more = fixture.clone();
+        claims_more[5] = 2;
+        let mut buffer = VectorPacketBuffer::read_from(&mut Cursor::new(&claims_more)).unwrap();
+        assert!(matches!(DnsPacket::from_buffer(&mut buffer), Err(DnsError::EndOfBuffer)));
+    }
+
+    #[test]
+    fn dname_implies_a_cname() {
+        let dname = DnsRecord::DNAME {
+            domain: "b.example.com".into(),
+            target: "c.example.net".to_string(),
+            class: DnsClass::IN,
+            ttl: 300,
+        };
+        let mut response = DnsPacket::response_to(&DnsPacket::query("a.b.example.com", QueryType::A));
+        response.answers.push(dname.clone());
+
+        // The record itself is written and read back unchanged
+        let mut response = DnsPacket::from_bytes(&response.to_bytes().unwrap()).unwrap();
+        assert_eq!(response.answers, [dname]);
+
+        let cname = response.synthesize_cname("a.b.example.com").unwrap();
+        assert_eq!(
+            cname,
+            DnsRecord::CNAME {
+                domain: "a.b.example.com".into(),
+                host: "a.c.example.net".to_string(),
+                class: DnsClass::IN,
+                ttl: 300,
+            }
+        );
+        assert!(matches!(
+            response.synthesize_cname("x.y.b.example.com"),
+            Some(DnsRecord::CNAME { host, .. }) if host == "x.y.c.example.net"
+        ));
+
+        // The owner and names outside of it aren't redirected
+        assert_eq!(response.synthesize_cname("b.example.com"), None);
+        assert_eq!(response.synthesize_cname("a.notb.example.com"), None);
+
+        response.answers.push(cname);
+        assert_eq!(response.get_cname("a.b.example.com"), Some("a.c.example.net"));
+    }
+
+    #[test]
+    fn extended_rcodes_combine_with_the_opt_record() {
+        let mut response = DnsPacket::response_to(&DnsPacket::query("example.com", QueryType::A));
+        response.set_rcode(ResultCode::BADCOOKIE);
+
+        // 23 is split into 7 in the header and 1 in the OPT record
+        let parsed = DnsPacket::from_bytes(&response.to_bytes().unwrap()).unwrap();
+        assert_eq!(parsed.header.rescode, ResultCode::YXRRSET);
+        assert!(matches!(parsed.resources[..], [DnsRecord::OPT { extended_rcode: 1, .. }]));
+        assert_eq!(parsed.rcode(), ResultCode::BADCOOKIE);
+    }
+
+    #[test]
+    fn unknown_rcodes_keep_their_value() {
+        let mut header = DnsHeader::new();
+        header.read(&mut VectorPacketBuffer::from_hex("0001 818b 0000 0000 0000 0000").unwrap()).unwrap();
+        assert_eq!(header.rescode, ResultCode::UNKNOWN(11));
+        let mut buffer = VectorPacketBuffer::new();
+        header.write(&mut buffer).unwrap();
+        assert_eq!(buffer.to_hex(), "00 01 81 8b 00 00 00 00 00 00 00 00");
+
+        let mut response = DnsPacket::response_to(&DnsPacket::query("example.com", QueryType::A));
+        response.set_rcode(ResultCode::UNKNOWN(3841));
+        let parsed = DnsPacket::from_bytes(&response.to_bytes().unwrap()).unwrap();
+        assert_eq!(parsed.rcode(), ResultCode::UNKNOWN(3841));
+        assert!(parsed.to_string().contains("status: RESERVED3841,"), "{}", parsed);
+
+        #[cfg(feature = "serde")]
+        {
+            let json = serde_json::to_string(&parsed).unwrap();
+            assert_eq!(serde_json::from_str::<DnsPacket>(&json).unwrap(), parsed);
+            assert_eq!(serde_json::to_string(&ResultCode::UNKNOWN(11)).unwrap(), r#"{"UNKNOWN":11}"#);
+            assert_eq!(serde_json::from_str::<ResultCode>(r#"{"UNKNOWN":11}"#).unwrap(), ResultCode::UNKNOWN(11));
+        }
+    }
+
+    #[test]
+    fn golden_display() {
+        let mut response = DnsPacket::response_to(&DnsPacket::query("example.com", QueryType::MX));
+        response.header.id = 1234;
+        response.answers.push(DnsRecord::MX {
+            domain: "example.com".into(),
+            priority: 10,
+            host: "mail.example.com".to_string(),
+            class: DnsClass::IN,
+            ttl: 300,
+        });
+        response.authorities.push(DnsRecord::NS {
+            domain: "example.com".into(),
+            host: "a.iana-servers.net".to_string(),
+            class: DnsClass::IN,
+            ttl: 86400,
+        });
+        response.resources.push(DnsRecord::A {
+            domain: "mail.example.com".into(),
+            addr: Ipv4Addr::new(93, 184, 216, 34),
+            class: DnsClass::IN,
+            ttl: 300,
+        });
+        // Classes without a mnemonic and bytes outside printable ASCII are
+        // written in their generic forms
+        response.resources.push(DnsRecord::HINFO {
+            domain: "mail.example.com".into(),
+            cpu: "Caf\u{e9}".to_string(),
+            os: "\"Unix\"".to_string(),
+            class: DnsClass::UNKNOWN(1232),
+            ttl: 0,
+        });
+
+        let golden = "\
+;; ->>HEADER<<- opcode: QUERY, status: NOERROR, id: 1234
+;; flags: qr rd ra; QUERY: 1, ANSWER: 1, AUTHORITY: 1, ADDITIONAL: 2
+
+;; QUESTION SECTION:
+;example.com.\t\tIN\tMX
+
+;; ANSWER SECTION:
+example.com.\t300\tIN\tMX\t10 mail.example.com.
+
+;; AUTHORITY SECTION:
+example.com.\t86400\tIN\tNS\ta.iana-servers.net.
+
+;; ADDITIONAL SECTION:
+mail.example.com.\t300\tIN\tA\t93.184.216.34
+mail.example.com.\t0\tCLASS1232\tHINFO\t\"Caf\\195\\169\" \"\\\"Unix\\\"\"
+";
+        assert_eq!(response.to_string(), golden);
+    }
+
+    #[test]
+    fn malformed_input_gives_specific_errors() {
+        let parse = |hex: &str| DnsPacket::from_buffer(&mut VectorPacketBuffer::from_hex(hex).unwrap());
+
+        // A header cut short
+        assert!(matches!(parse("0001 8180 0001"), Err(DnsError::EndOfBuffer)));
+
+        // A length byte of 0x40, which is neither a label nor a pointer
+        assert!(matches!(parse("0001 0100 0001 0000 0000 0000 4061 0000 0100 01"), Err(DnsError::LabelTooLong)));
+
+        // A question name pointing at itself
+        assert!(matches!(parse("0001 0100 0001 0000 0000 0000 c00c 0001 0001"), Err(DnsError::InvalidPointer(12))));
+
+        // A DS record with three bytes of rdata, too few for its fixed fields
+        assert!(matches!(
+            parse("0001 8180 0000 0001 0000 0000 0000 2b00 0100 0000 3c00 03ec 4505"),
+            Err(DnsError::InvalidRecord(_))
+        ));
+
+        let mut buffer = VectorPacketBuffer::new();
+        assert!(matches!(buffer.write_qname("foo..bar"), Err(DnsError::EmptyLabel)));
+        assert!(matches!(buffer.write_qname(&vec!["a"; 128].join(".")), Err(DnsError::NameTooLong)));
+
+        assert!(matches!(VectorPacketBuffer::from_hex("0g"), Err(DnsError::InvalidHex(token)) if token == "0g"));
+        assert!(matches!("BOGUS".parse::<QueryType>(), Err(DnsError::UnknownQueryType(name)) if name == "BOGUS"));
+
+        match VectorPacketBuffer::read_tcp(&mut std::io::Cursor::new([0u8, 10, 1])) {
+            Err(err @ DnsError::Io(_)) => assert!(std::error::Error::source(&err).is_some()),
+            _ => panic!("a message cut short should be an I/O error"),
+        }
+    }
+
+    #[test]
+    fn response_carries_over_the_id_and_flags() {
+        let query = DnsPacket::query("example.com", QueryType::AAAA);
+        assert!(query.header.recursion_desired);
+        assert!(!query.header.response);
+        assert!(!query.header.recursion_available);
+        assert_eq!(query.header.opcode, crate::header::OpCode::QUERY);
+        assert_eq!(query.questions, [DnsQuestion::new(Name::from("example.com"), QueryType::AAAA)]);
+
+        // Ids are drawn at random, so a handful of queries won't all share one
+        let ids: std::collections::HashSet<u16> =
+            (0..8).map(|_| DnsPacket::query("example.com", QueryType::A).header.id).collect();
+        assert!(ids.len() > 1);
+
+        let response = DnsPacket::response_to(&query);
+        assert_eq!(response.header.id, query.header.id);
+        assert!(response.header.response);
+        assert!(response.header.recursion_available);
+        assert!(response.header.recursion_desired);
+        assert_eq!(response.header.rescode, ResultCode::NOERROR);
+        assert_eq!(response.questions, query.questions);
+
+        // RD is mirrored rather than always set
+        let mut iterative = DnsPacket::query("example.com", QueryType::A);
+        iterative.header.recursion_desired = false;
+        let response = DnsPacket::response_to(&iterative);
+        assert!(!response.header.recursion_desired);
+
+        // And it all survives the trip through the wire format
+        let mut response = DnsPacket::response_to(&query);
+        let parsed = DnsPacket::from_bytes(&response.to_bytes().unwrap()).unwrap();
+        assert_eq!(parsed.header, response.header);
+        assert_eq!(parsed.header.flags_summary(), "qr rd ra");
+    }
+
+    #[test]
+    fn answers_for_picks_the_name_and_type() {
+        let a = |domain: &str, addr: [u8; 4]| DnsRecord::A {
+            domain: domain.into(),
+            addr: Ipv4Addr::from(addr),
+            class: DnsClass::IN,
+            ttl: 300,
+        };
+
+        let mut packet = DnsPacket::response_to(&DnsPacket::query("www.example.com", QueryType::A));
+        packet.answers = vec![
+            DnsRecord::CNAME {
+                domain: "www.example.com".into(),
+                host: "Edge.Example.net".to_string(),
+                class: DnsClass::IN,
+                ttl: 300,
+            },
+            a("edge.example.net", [192, 0, 2, 1]),
+            DnsRecord::AAAA {
+                domain: "edge.example.net".into(),
+                addr: "2001:db8::1".parse().unwrap(),
+                class: DnsClass::IN,
+                ttl: 300,
+            },
+            a("EDGE.example.net", [192, 0, 2, 2]),
+            a("other.example.net", [192, 0, 2, 3]),
+        ];
+
+        let target = packet.get_cname("www.example.com").unwrap();
+        assert_eq!(packet.answers_for(target, QueryType::A), [&packet.answers[1], &packet.answers[3]]);
+        assert_eq!(packet.answers_for("www.example.com", QueryType::CNAME), [&packet.answers[0]]);
+        assert!(packet.answers_for("www.example.com", QueryType::A).is_empty());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_fixture_with_base64_rdata() {
+        let mut packet = DnsPacket::response_to(&DnsPacket::query("example.com", QueryType::TXT));
+        packet.header.id = 0xbeef;
+        packet.answers.push(DnsRecord::TXT {
+            domain: "example.com".into(),
+            strings: vec![b"hello".to_vec(), vec![0xff]],
+            class: DnsClass::IN,
+            ttl: 300,
+        });
+        packet.answers.push(DnsRecord::AAAA {
+            domain: "example.com".into(),
+            addr: "2001:db8::1".parse().unwrap(),
+            class: DnsClass::IN,
+            ttl: 300,
+        });
+        packet.answers.push(DnsRecord::DS {
+            domain: "example.com".into(),
+            key_tag: 60485,
+            algorithm: 5,
+            digest_type: 1,
+            digest: vec![0x2b, 0xb1, 0x83, 0xaf],
+            class: DnsClass::IN,
+            ttl: 300,
+        });
+
+        let json = serde_json::to_value(&packet).unwrap();
+        assert_eq!(
+            json["answers"],
+            serde_json::json!([
+                {"TXT": {"domain": "example.com", "strings": ["aGVsbG8=", "/w=="], "class": "IN", "ttl": 300}},
+                {"AAAA": {"domain": "example.com", "addr": "2001:db8::1", "class": "IN", "ttl": 300}},
+                {"DS": {
+                    "domain": "example.com",
+                    "key_tag": 60485,
+                    "algorithm": 5,
+                    "digest_type": 1,
+                    "digest": "K7GDrw==",
+                    "class": "IN",
+                    "ttl": 300
+                }}
+            ])
+        );
+        assert_eq!(json["questions"], serde_json::json!([{"name": "example.com", "qtype": "TXT", "class": "IN"}]));
+        assert_eq!(json["header"]["id"], 0xbeef);
+        assert_eq!(json["header"]["rescode"], "NOERROR");
+
+        assert_eq!(serde_json::from_value::<DnsPacket>(json).unwrap(), packet);
+    }
+
+    #[test]
+    fn every_address_gets_drawn() {
+        let addrs = [Ipv4Addr::new(198, 51, 100, 1), Ipv4Addr::new(198, 51, 100, 2), Ipv4Addr::new(198, 51, 100, 3)];
+        let mut packet = DnsPacket::response_to(&DnsPacket::query("example.com", QueryType::A));
+        packet.answers.push(DnsRecord::CNAME {
+            domain: "example.com".into(),
+            host: "edge.example.com".to_string(),
+            class: DnsClass::IN,
+            ttl: 60,
+        });
+        for addr in addrs {
+            packet.answers.push(DnsRecord::A {
+                domain: "edge.example.com".into(),
+                addr,
+                class: DnsClass::IN,
+                ttl: 60,
+            });
+        }
+        packet.answers.push(DnsRecord::AAAA {
+            domain: "edge.example.com".into(),
+            addr: "2001:db8::1".parse().unwrap(),
+            class: DnsClass::IN,
+            ttl: 60,
+        });
+
+        assert_eq!(packet.get_all_a(), addrs);
+        let all = packet.get_all_addrs();
+        assert_eq!(all.len(), 4);
+        assert_eq!(all[..3], addrs.map(IpAddr::V4));
+        assert_eq!(all[3], "2001:db8::1".parse::<IpAddr>().unwrap());
+
+        // With 300 draws the chance of missing one of the three by bad luck
+        // is below 1e-50, so a miss means the choice isn't random
+        let mut counts = [0; 3];
+        for _ in 0..300 {
+            let picked = packet.get_random_a().unwrap();
+            counts[addrs.iter().position(|&addr| addr == picked).unwrap()] += 1;
+        }
+        assert!(counts.iter().all(|&count| count > 0), "{:?}", counts);
+
+        assert_eq!(DnsPacket::new().get_random_a(), None);
+    }
+
+    #[test]
+    fn typed_accessors_over_mixed_sections() {
+        let mut packet = DnsPacket::response_to(&DnsPacket::query("example.com", QueryType::ANY));
+        packet.answers = vec![
+            DnsRecord::A {
+                domain: "example.com".into(),
+                addr: Ipv4Addr::new(192, 0, 2, 1),
+                class: DnsClass::IN,
+                ttl: 300,
+            },
+            DnsRecord::MX {
+                domain: "example.com".into(),
+                priority: 10,
+                host: "mail.example.com".to_string(),
+                class: DnsClass::IN,
+                ttl: 300,
+            },
+            DnsRecord::AAAA {
+                domain: "example.com".into(),
+                addr: "2001:db8::1".parse().unwrap(),
+                class: DnsClass::IN,
+                ttl: 300,
+            },
+            DnsRecord::SRV {
+                domain: "_sip._udp.Example.com".into(),
+                priority: 1,
+                weight: 5,
+                port: 5060,
+                host: "sip.example.com".to_string(),
+                class: DnsClass::IN,
+                ttl: 300,
+            },
+            DnsRecord::A {
+                domain: "example.com".into(),
+                addr: Ipv4Addr::new(192, 0, 2, 2),
+                class: DnsClass::IN,
+                ttl: 300,
+            },
+        ];
+        packet.authorities = vec![
+            DnsRecord::NS {
+                domain: "example.com".into(),
+                host: "ns1.example.com".to_string(),
+                class: DnsClass::IN,
+                ttl: 3600,
+            },
+            DnsRecord::SOA {
+                domain: "example.com".into(),
+                mname: "ns1.example.com".to_string(),
+                rname: "hostmaster.example.com".to_string(),
+                serial: 1,
+                refresh: 7200,
+                retry: 3600,
+                expire: 1209600,
+                minimum: 600,
+                class: DnsClass::IN,
+                ttl: 300,
+            },
+        ];
+        packet.resources = vec![DnsRecord::A {
+            domain: "ns1.example.com".into(),
+            addr: Ipv4Addr::new(192, 0, 2, 53),
+            class: DnsClass::IN,
+            ttl: 3600,
+        }];
+        packet.set_edns_payload_size(1232);
+
+        assert_eq!(packet.answers_by_type(QueryType::A).count(), 2);
+        assert_eq!(packet.answers_by_type(QueryType::NS).count(), 0);
+        assert_eq!(packet.authorities_by_type(QueryType::NS).count(), 1);
+        assert_eq!(packet.resources_by_type(QueryType::A).count(), 1);
+        assert_eq!(packet.resources_by_type(QueryType::OPT).count(), 1);
+
+        let example = Name::from("example.com");
+        let a = [(&example, Ipv4Addr::new(192, 0, 2, 1)), (&example, Ipv4Addr::new(192, 0, 2, 2))];
+        assert_eq!(packet.a_records(), a);
+        assert_eq!(packet.aaaa_records(), [(&example, "2001:db8::1".parse().unwrap())]);
+        assert_eq!(packet.mx_records(), [(&example, 10, "mail.example.com")]);
+        assert_eq!(packet.get_srv_records("_sip._udp.example.com"), [(1, 5, 5060, "sip.example.com")]);
+        assert_eq!(packet.get_all_addrs().len(), 3);
+
+        // The helpers built on top read the right sections
+        assert_eq!(packet.get_soa_minimum(), Some(600));
+        assert_eq!(packet.get_negative_ttl(), Some(300));
+        assert_eq!(packet.get_edns(), Some((1232, false)));
+        assert_eq!(packet.get_resolved_ns("www.example.com"), Some(Ipv4Addr::new(192, 0, 2, 53)));
+        assert_eq!(packet.get_unresolved_ns("www.example.com"), Some("ns1.example.com"));
+        assert_eq!(packet.get_cname("example.com"), None);
+    }
+}
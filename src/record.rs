@@ -1,131 +1,429 @@
-use std::{error::Error, net::Ipv4Addr, net::Ipv6Addr};
+use std::{fmt, net::IpAddr, net::Ipv4Addr, net::Ipv6Addr, str::FromStr};
 
-use crate::packets::BytePacketBuffer;
+use crate::{error::DnsError, name::Name, packets::PacketBuffer};
 
-#[derive(PartialEq, Eq, Debug, Clone, Hash, Copy)]
+#[derive(PartialEq, Eq, Debug, Clone, Hash, Copy, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum QueryType {
     UNKNOWN(u16),
     A, // 1
     NS, // 2
     CNAME, // 5
+    SOA, // 6
+    NULL, // 10
+    WKS, // 11
+    PTR, // 12
+    HINFO, // 13
     MX, // 15
+    TXT, // 16
+    RP, // 17
+    AFSDB, // 18
     AAAA, // 28
+    LOC, // 29
+    SRV, // 33
+    NAPTR, // 35
+    DNAME, // 39
+    OPT, // 41
+    DS, // 43
+    IPSECKEY, // 45
+    RRSIG, // 46
+    NSEC, // 47
+    DNSKEY, // 48
+    NSEC3, // 50
+    NSEC3PARAM, // 51
+    TLSA, // 52
+    SMIMEA, // 53
+    OPENPGPKEY, // 61
+    SPF, // 99
+    ANY, // 255
+    URI, // 256
+    CAA, // 257
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Hash, Copy, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DnsClass {
+    UNKNOWN(u16),
+    IN, // 1
+    CH, // 3
+    HS, // 4
+    NONE, // 254
+    ANY, // 255
+}
+
+/// Where an IPSECKEY record says the IPsec tunnel should be set up
+#[derive(PartialEq, Eq, Debug, Clone, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum IpsecGateway {
+    None, // 0
+    V4(Ipv4Addr), // 1
+    V6(Ipv6Addr), // 2
+    Name(String), // 3
+}
+
+impl IpsecGateway {
+    pub fn type_num(&self) -> u8 {
+        match *self {
+            IpsecGateway::None => 0,
+            IpsecGateway::V4(_) => 1,
+            IpsecGateway::V6(_) => 2,
+            IpsecGateway::Name(_) => 3,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DnsQuestion {
-    pub name: String,
+    pub name: Name,
     pub qtype: QueryType,
+    pub class: DnsClass,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[allow(dead_code)]
 pub enum DnsRecord {
     UNKNOWN {
-        domain: String,
+        domain: Name,
         qtype: u16,
-        data_len: u16,
+        #[cfg_attr(feature = "serde", serde(with = "base64_serde"))]
+        rdata: Vec<u8>,
+        class: DnsClass,
         ttl: u32,
     }, // 0
     A {
-        domain: String,
+        domain: Name,
         addr: Ipv4Addr,
+        class: DnsClass,
         ttl: u32,
     }, // 1
     NS {
-        domain: String,
+        domain: Name,
         host: String,
+        class: DnsClass,
         ttl: u32,
     }, // 2
     CNAME {
-        domain: String,
+        domain: Name,
         host: String,
+        class: DnsClass,
         ttl: u32,
     }, // 5
+    SOA {
+        domain: Name,
+        mname: String,
+        rname: String,
+        serial: u32,
+        refresh: u32,
+        retry: u32,
+        expire: u32,
+        minimum: u32,
+        class: DnsClass,
+        ttl: u32,
+    }, // 6
+    NULL {
+        domain: Name,
+        #[cfg_attr(feature = "serde", serde(with = "base64_serde"))]
+        data: Vec<u8>,
+        class: DnsClass,
+        ttl: u32,
+    }, // 10
+    WKS {
+        domain: Name,
+        addr: Ipv4Addr,
+        protocol: u8,
+        ports: Vec<u16>,
+        class: DnsClass,
+        ttl: u32,
+    }, // 11
+    PTR {
+        domain: Name,
+        host: String,
+        class: DnsClass,
+        ttl: u32,
+    }, // 12
+    HINFO {
+        domain: Name,
+        cpu: String,
+        os: String,
+        class: DnsClass,
+        ttl: u32,
+    }, // 13
     MX {
-        domain: String,
+        domain: Name,
         priority: u16,
         host: String,
+        class: DnsClass,
         ttl: u32,
     }, // 15
+    TXT {
+        domain: Name,
+        #[cfg_attr(feature = "serde", serde(with = "base64_list_serde"))]
+        strings: Vec<Vec<u8>>,
+        class: DnsClass,
+        ttl: u32,
+    }, // 16
+    RP {
+        domain: Name,
+        mbox: String,
+        txt: String,
+        class: DnsClass,
+        ttl: u32,
+    }, // 17
+    AFSDB {
+        domain: Name,
+        subtype: u16,
+        host: String,
+        class: DnsClass,
+        ttl: u32,
+    }, // 18
     AAAA {
-        domain: String,
+        domain: Name,
         addr: Ipv6Addr,
+        class: DnsClass,
         ttl: u32,
     }, // 28
+    LOC {
+        domain: Name,
+        version: u8,
+        size: u8,
+        horiz_pre: u8,
+        vert_pre: u8,
+        latitude: u32,
+        longitude: u32,
+        altitude: u32,
+        class: DnsClass,
+        ttl: u32,
+    }, // 29
+    SRV {
+        domain: Name,
+        priority: u16,
+        weight: u16,
+        port: u16,
+        host: String,
+        class: DnsClass,
+        ttl: u32,
+    }, // 33
+    NAPTR {
+        domain: Name,
+        order: u16,
+        preference: u16,
+        flags: String,
+        services: String,
+        regexp: String,
+        replacement: String,
+        class: DnsClass,
+        ttl: u32,
+    }, // 35
+    DNAME {
+        domain: Name,
+        target: String,
+        class: DnsClass,
+        ttl: u32,
+    }, // 39
+    OPT {
+        domain: Name,
+        packet_len: u16,
+        extended_rcode: u8,
+        version: u8,
+        dnssec_ok: bool,
+        options: Vec<(u16, Vec<u8>)>,
+    }, // 41
+    DS {
+        domain: Name,
+        key_tag: u16,
+        algorithm: u8,
+        digest_type: u8,
+        #[cfg_attr(feature = "serde", serde(with = "base64_serde"))]
+        digest: Vec<u8>,
+        class: DnsClass,
+        ttl: u32,
+    }, // 43
+    IPSECKEY {
+        domain: Name,
+        precedence: u8,
+        algorithm: u8,
+        gateway: IpsecGateway,
+        #[cfg_attr(feature = "serde", serde(with = "base64_serde"))]
+        public_key: Vec<u8>,
+        class: DnsClass,
+        ttl: u32,
+    }, // 45
+    RRSIG {
+        domain: Name,
+        type_covered: QueryType,
+        algorithm: u8,
+        labels: u8,
+        original_ttl: u32,
+        expiration: u32,
+        inception: u32,
+        key_tag: u16,
+        signer_name: String,
+        #[cfg_attr(feature = "serde", serde(with = "base64_serde"))]
+        signature: Vec<u8>,
+        class: DnsClass,
+        ttl: u32,
+    }, // 46
+    NSEC {
+        domain: Name,
+        next_domain: String,
+        types: Vec<QueryType>,
+        class: DnsClass,
+        ttl: u32,
+    }, // 47
+    DNSKEY {
+        domain: Name,
+        flags: u16,
+        protocol: u8,
+        algorithm: u8,
+        #[cfg_attr(feature = "serde", serde(with = "base64_serde"))]
+        public_key: Vec<u8>,
+        class: DnsClass,
+        ttl: u32,
+    }, // 48
+    NSEC3 {
+        domain: Name,
+        hash_algorithm: u8,
+        flags: u8,
+        iterations: u16,
+        #[cfg_attr(feature = "serde", serde(with = "base64_serde"))]
+        salt: Vec<u8>,
+        #[cfg_attr(feature = "serde", serde(with = "base64_serde"))]
+        next_hashed: Vec<u8>,
+        types: Vec<QueryType>,
+        class: DnsClass,
+        ttl: u32,
+    }, // 50
+    NSEC3PARAM {
+        domain: Name,
+        hash_algorithm: u8,
+        flags: u8,
+        iterations: u16,
+        #[cfg_attr(feature = "serde", serde(with = "base64_serde"))]
+        salt: Vec<u8>,
+        class: DnsClass,
+        ttl: u32,
+    }, // 51
+    TLSA {
+        domain: Name,
+        usage: u8,
+        selector: u8,
+        matching_type: u8,
+        #[cfg_attr(feature = "serde", serde(with = "base64_serde"))]
+        cert_data: Vec<u8>,
+        class: DnsClass,
+        ttl: u32,
+    }, // 52
+    SMIMEA {
+        domain: Name,
+        usage: u8,
+        selector: u8,
+        matching_type: u8,
+        #[cfg_attr(feature = "serde", serde(with = "base64_serde"))]
+        data: Vec<u8>,
+        class: DnsClass,
+        ttl: u32,
+    }, // 53
+    OPENPGPKEY {
+        domain: Name,
+        #[cfg_attr(feature = "serde", serde(with = "base64_serde"))]
+        public_key: Vec<u8>,
+        class: DnsClass,
+        ttl: u32,
+    }, // 61
+    SPF {
+        domain: Name,
+        #[cfg_attr(feature = "serde", serde(with = "base64_list_serde"))]
+        strings: Vec<Vec<u8>>,
+        class: DnsClass,
+        ttl: u32,
+    }, // 99
+    URI {
+        domain: Name,
+        priority: u16,
+        weight: u16,
+        target: String,
+        class: DnsClass,
+        ttl: u32,
+    }, // 256
+    CAA {
+        domain: Name,
+        flags: u8,
+        tag: String,
+        #[cfg_attr(feature = "serde", serde(with = "base64_serde"))]
+        value: Vec<u8>,
+        class: DnsClass,
+        ttl: u32,
+    }, // 257
 }
 
 impl DnsRecord {
-    pub fn read(buffer: &mut BytePacketBuffer) -> Result<DnsRecord, Box<dyn Error>> {
-        let mut domain = String::new();
-        buffer.read_qname(&mut domain)?;
+    pub fn read<T: PacketBuffer>(buffer: &mut T) -> Result<DnsRecord, DnsError> {
+        let domain = buffer.read_name()?;
 
         let qtype_num = buffer.read_u16()?;
         let qtype = QueryType::from_num(qtype_num);
-        let _ = buffer.read_u16()?;
+        let class_num = buffer.read_u16()?;
+        let class = DnsClass::from_num(class_num);
         let ttl = buffer.read_u32()?;
         let data_len = buffer.read_u16()?;
+        let data_start = buffer.pos();
 
-        match qtype {
+        let record = match qtype {
             QueryType::A => {
                 let raw_addr = buffer.read_u32()?;
                 let addr = Ipv4Addr::new(
                     ((raw_addr >> 24) & 0xFF) as u8,
                     ((raw_addr >> 16) & 0xFF) as u8,
                     ((raw_addr >> 8) & 0xFF) as u8,
-                    ((raw_addr >> 0) & 0xFF) as u8,
+                    (raw_addr & 0xFF) as u8,
                 );
 
-                Ok(DnsRecord::A {
-                    domain: domain,
-                    addr: addr,
-                    ttl: ttl
-                })
+                DnsRecord::A {
+                    domain,
+                    addr,
+                    class,
+                    ttl
+                }
             }
 
             QueryType::AAAA => {
-                let raw_addr1 = buffer.read_u32()?;
-                let raw_addr2 = buffer.read_u32()?;
-                let raw_addr3 = buffer.read_u32()?;
-                let raw_addr4 = buffer.read_u32()?;
-                let addr = Ipv6Addr::new(
-                    ((raw_addr1 >> 16) & 0xFFFF) as u16,
-                    ((raw_addr1 >> 0) & 0xFFFF) as u16,
-                    ((raw_addr2 >> 16) & 0xFFFF) as u16,
-                    ((raw_addr2 >> 0) & 0xFFFF) as u16,
-                    ((raw_addr3 >> 16) & 0xFFFF) as u16,
-                    ((raw_addr3 >> 0) & 0xFFFF) as u16,
-                    ((raw_addr4 >> 16) & 0xFFFF) as u16,
-                    ((raw_addr4 >> 0) & 0xFFFF) as u16,
-                );
+                if data_len != 16 {
+                    return Err(DnsError::InvalidRecord(format!("Invalid AAAA data length {}", data_len)));
+                }
+                let addr = Ipv6Addr::from(buffer.read_u128()?);
 
-                Ok(DnsRecord::AAAA {
-                    domain: domain,
-                    addr: addr,
-                    ttl: ttl
-                })
+                DnsRecord::AAAA {
+                    domain,
+                    addr,
+                    class,
+                    ttl
+                }
             }
 
             QueryType::NS => {
                 let mut ns = String::new();
                 buffer.read_qname(&mut ns)?;
 
-                Ok(DnsRecord::NS {
-                    domain: domain,
+                DnsRecord::NS {
+                    domain,
                     host: ns,
-                    ttl: ttl,
-                })
+                    class,
+                    ttl,
+                }
             }
 
             QueryType::CNAME => {
                 let mut cname = String::new();
                 buffer.read_qname(&mut cname)?;
 
-                Ok(DnsRecord::CNAME {
-                    domain: domain,
+                DnsRecord::CNAME {
+                    domain,
                     host: cname,
-                    ttl: ttl,
-                })
+                    class,
+                    ttl,
+                }
             }
 
             QueryType::MX => {
@@ -133,39 +431,583 @@ impl DnsRecord {
                 let mut mx = String::new();
                 buffer.read_qname(&mut mx)?;
 
-                Ok(DnsRecord::MX {
-                    domain: domain,
-                    priority: priority,
+                DnsRecord::MX {
+                    domain,
+                    priority,
                     host: mx,
-                    ttl: ttl
-                })
+                    class,
+                    ttl
+                }
+            }
+
+            QueryType::SOA => {
+                let mut mname = String::new();
+                buffer.read_qname(&mut mname)?;
+
+                let mut rname = String::new();
+                buffer.read_qname(&mut rname)?;
+
+                let serial = buffer.read_u32()?;
+                let refresh = buffer.read_u32()?;
+                let retry = buffer.read_u32()?;
+                let expire = buffer.read_u32()?;
+                let minimum = buffer.read_u32()?;
+
+                DnsRecord::SOA {
+                    domain,
+                    mname,
+                    rname,
+                    serial,
+                    refresh,
+                    retry,
+                    expire,
+                    minimum,
+                    class,
+                    ttl,
+                }
+            }
+
+            QueryType::TXT => {
+                let strings = read_strings(buffer, data_start + data_len as usize)?;
+
+                DnsRecord::TXT {
+                    domain,
+                    strings,
+                    class,
+                    ttl,
+                }
+            }
+
+            QueryType::SRV => {
+                let priority = buffer.read_u16()?;
+                let weight = buffer.read_u16()?;
+                let port = buffer.read_u16()?;
+                let mut srv = String::new();
+                buffer.read_qname(&mut srv)?;
+
+                DnsRecord::SRV {
+                    domain,
+                    priority,
+                    weight,
+                    port,
+                    host: srv,
+                    class,
+                    ttl,
+                }
+            }
+
+            QueryType::PTR => {
+                let mut ptr = String::new();
+                buffer.read_qname(&mut ptr)?;
+
+                DnsRecord::PTR {
+                    domain,
+                    host: ptr,
+                    class,
+                    ttl,
+                }
+            }
+
+            QueryType::CAA => {
+                let flags = buffer.read_u8()?;
+                let tag_len = buffer.read_u8()? as usize;
+                if tag_len + 2 > data_len as usize {
+                    return Err(DnsError::InvalidRecord("CAA tag exceeds record data length".to_string()));
+                }
+
+                // Whatever follows the tag up to the end of the rdata is the value
+                let tag = String::from_utf8_lossy(buffer.read_bytes(tag_len)?).to_string();
+                let value_len = data_len as usize - 2 - tag_len;
+                let value = buffer.read_bytes(value_len)?.to_vec();
+
+                DnsRecord::CAA {
+                    domain,
+                    flags,
+                    tag,
+                    value,
+                    class,
+                    ttl,
+                }
+            }
+
+            QueryType::OPT => {
+                // The EDNS pseudo-record reuses the class as the requestor's
+                // payload size and packs its flags into the ttl.
+                let mut options = Vec::new();
+                let data_end = data_start + data_len as usize;
+                while buffer.pos() < data_end {
+                    let code = buffer.read_u16()?;
+                    let len = buffer.read_u16()? as usize;
+                    if buffer.pos() + len > data_end {
+                        return Err(DnsError::InvalidRecord("EDNS option exceeds record data length".to_string()));
+                    }
+
+                    options.push((code, buffer.read_bytes(len)?.to_vec()));
+                }
+
+                DnsRecord::OPT {
+                    domain,
+                    packet_len: class_num,
+                    extended_rcode: (ttl >> 24) as u8,
+                    version: ((ttl >> 16) & 0xFF) as u8,
+                    dnssec_ok: (ttl & 0x8000) > 0,
+                    options,
+                }
+            }
+
+            QueryType::DNSKEY => {
+                if data_len < 4 {
+                    return Err(DnsError::InvalidRecord(format!("Invalid DNSKEY data length {}", data_len)));
+                }
+                let flags = buffer.read_u16()?;
+                let protocol = buffer.read_u8()?;
+                let algorithm = buffer.read_u8()?;
+
+                // The key itself makes up the rest of the rdata
+                let public_key = buffer.read_bytes(data_len as usize - 4)?.to_vec();
+
+                DnsRecord::DNSKEY {
+                    domain,
+                    flags,
+                    protocol,
+                    algorithm,
+                    public_key,
+                    class,
+                    ttl,
+                }
+            }
+
+            QueryType::DS => {
+                if data_len < 4 {
+                    return Err(DnsError::InvalidRecord(format!("Invalid DS data length {}", data_len)));
+                }
+                let key_tag = buffer.read_u16()?;
+                let algorithm = buffer.read_u8()?;
+                let digest_type = buffer.read_u8()?;
+
+                // The digest length depends on the digest type, so take
+                // whatever is left of the rdata.
+                let digest = buffer.read_bytes(data_len as usize - 4)?.to_vec();
+
+                DnsRecord::DS {
+                    domain,
+                    key_tag,
+                    algorithm,
+                    digest_type,
+                    digest,
+                    class,
+                    ttl,
+                }
+            }
+
+            QueryType::RRSIG => {
+                let type_covered = QueryType::from_num(buffer.read_u16()?);
+                let algorithm = buffer.read_u8()?;
+                let labels = buffer.read_u8()?;
+                let original_ttl = buffer.read_u32()?;
+                let expiration = buffer.read_u32()?;
+                let inception = buffer.read_u32()?;
+                let key_tag = buffer.read_u16()?;
+                // The signer name is taken as it was sent, since it's part of
+                // the data the signature covers, and is never compressed
+                // (RFC 4034 section 3.1.7)
+                let mut signer_name = String::new();
+                buffer.read_qname_uncompressed_preserving_case(&mut signer_name)?;
+
+                // The signature makes up the rest of the rdata
+                let pos = buffer.pos();
+                let data_end = data_start + data_len as usize;
+                if pos > data_end {
+                    return Err(DnsError::InvalidRecord("RRSIG signer name exceeds record data length".to_string()));
+                }
+                let signature = buffer.read_bytes(data_end - pos)?.to_vec();
+
+                DnsRecord::RRSIG {
+                    domain,
+                    type_covered,
+                    algorithm,
+                    labels,
+                    original_ttl,
+                    expiration,
+                    inception,
+                    key_tag,
+                    signer_name,
+                    signature,
+                    class,
+                    ttl,
+                }
+            }
+
+            QueryType::NSEC => {
+                let mut next_domain = String::new();
+                buffer.read_qname(&mut next_domain)?;
+                let types = read_type_bitmap(buffer, data_start + data_len as usize)?;
+
+                DnsRecord::NSEC {
+                    domain,
+                    next_domain,
+                    types,
+                    class,
+                    ttl,
+                }
+            }
+
+            QueryType::NSEC3 => {
+                let hash_algorithm = buffer.read_u8()?;
+                let flags = buffer.read_u8()?;
+                let iterations = buffer.read_u16()?;
+
+                let salt_len = buffer.read_u8()? as usize;
+                let salt = buffer.read_bytes(salt_len)?.to_vec();
+
+                let hash_len = buffer.read_u8()? as usize;
+                let next_hashed = buffer.read_bytes(hash_len)?.to_vec();
+
+                let types = read_type_bitmap(buffer, data_start + data_len as usize)?;
+
+                DnsRecord::NSEC3 {
+                    domain,
+                    hash_algorithm,
+                    flags,
+                    iterations,
+                    salt,
+                    next_hashed,
+                    types,
+                    class,
+                    ttl,
+                }
+            }
+
+            QueryType::NSEC3PARAM => {
+                let hash_algorithm = buffer.read_u8()?;
+                let flags = buffer.read_u8()?;
+                let iterations = buffer.read_u16()?;
+
+                let salt_len = buffer.read_u8()? as usize;
+                let salt = buffer.read_bytes(salt_len)?.to_vec();
+
+                DnsRecord::NSEC3PARAM {
+                    domain,
+                    hash_algorithm,
+                    flags,
+                    iterations,
+                    salt,
+                    class,
+                    ttl,
+                }
+            }
+
+            QueryType::HINFO => {
+                let data_end = data_start + data_len as usize;
+                let cpu = read_character_string(buffer, data_end)?;
+                let os = read_character_string(buffer, data_end)?;
+
+                DnsRecord::HINFO {
+                    domain,
+                    cpu,
+                    os,
+                    class,
+                    ttl,
+                }
+            }
+
+            QueryType::LOC => {
+                if data_len != 16 {
+                    return Err(DnsError::InvalidRecord(format!("Invalid LOC data length {}", data_len)));
+                }
+                let version = buffer.read_u8()?;
+                let size = buffer.read_u8()?;
+                let horiz_pre = buffer.read_u8()?;
+                let vert_pre = buffer.read_u8()?;
+                let latitude = buffer.read_u32()?;
+                let longitude = buffer.read_u32()?;
+                let altitude = buffer.read_u32()?;
+
+                DnsRecord::LOC {
+                    domain,
+                    version,
+                    size,
+                    horiz_pre,
+                    vert_pre,
+                    latitude,
+                    longitude,
+                    altitude,
+                    class,
+                    ttl,
+                }
+            }
+
+            QueryType::URI => {
+                if data_len < 4 {
+                    return Err(DnsError::InvalidRecord(format!("Invalid URI data length {}", data_len)));
+                }
+                let priority = buffer.read_u16()?;
+                let weight = buffer.read_u16()?;
+
+                // The target is neither a name nor a character string, just
+                // the raw bytes making up the rest of the rdata.
+                let target = String::from_utf8_lossy(buffer.read_bytes(data_len as usize - 4)?).to_string();
+
+                DnsRecord::URI {
+                    domain,
+                    priority,
+                    weight,
+                    target,
+                    class,
+                    ttl,
+                }
+            }
+
+            QueryType::SMIMEA => {
+                if data_len < 3 {
+                    return Err(DnsError::InvalidRecord(format!("Invalid SMIMEA data length {}", data_len)));
+                }
+                let usage = buffer.read_u8()?;
+                let selector = buffer.read_u8()?;
+                let matching_type = buffer.read_u8()?;
+
+                let data = buffer.read_bytes(data_len as usize - 3)?.to_vec();
+
+                DnsRecord::SMIMEA {
+                    domain,
+                    usage,
+                    selector,
+                    matching_type,
+                    data,
+                    class,
+                    ttl,
+                }
+            }
+
+            QueryType::OPENPGPKEY => {
+                let public_key = buffer.read_bytes(data_len as usize)?.to_vec();
+
+                DnsRecord::OPENPGPKEY {
+                    domain,
+                    public_key,
+                    class,
+                    ttl,
+                }
+            }
+
+            QueryType::IPSECKEY => {
+                let precedence = buffer.read_u8()?;
+                let gateway_type = buffer.read_u8()?;
+                let algorithm = buffer.read_u8()?;
+
+                // The gateway type decides how the gateway field is laid out
+                let gateway = match gateway_type {
+                    0 => IpsecGateway::None,
+                    1 => IpsecGateway::V4(Ipv4Addr::from(buffer.read_u32()?)),
+                    2 => IpsecGateway::V6(Ipv6Addr::from(buffer.read_u128()?)),
+                    3 => {
+                        // A gateway name is never compressed (RFC 4025
+                        // section 2.5)
+                        let mut name = String::new();
+                        buffer.read_qname_uncompressed(&mut name)?;
+                        IpsecGateway::Name(name)
+                    }
+                    _ => {
+                        return Err(DnsError::InvalidRecord(format!("Unknown IPSECKEY gateway type {}", gateway_type)));
+                    }
+                };
+
+                // The public key makes up the rest of the rdata
+                let pos = buffer.pos();
+                let data_end = data_start + data_len as usize;
+                if pos > data_end {
+                    return Err(DnsError::InvalidRecord("IPSECKEY gateway exceeds record data length".to_string()));
+                }
+                let public_key = buffer.read_bytes(data_end - pos)?.to_vec();
+
+                DnsRecord::IPSECKEY {
+                    domain,
+                    precedence,
+                    algorithm,
+                    gateway,
+                    public_key,
+                    class,
+                    ttl,
+                }
+            }
+
+            QueryType::RP => {
+                let mut mbox = String::new();
+                buffer.read_qname(&mut mbox)?;
+                let mut txt = String::new();
+                buffer.read_qname(&mut txt)?;
+
+                DnsRecord::RP {
+                    domain,
+                    mbox,
+                    txt,
+                    class,
+                    ttl,
+                }
+            }
+
+            QueryType::AFSDB => {
+                let subtype = buffer.read_u16()?;
+                let mut host = String::new();
+                buffer.read_qname(&mut host)?;
+
+                DnsRecord::AFSDB {
+                    domain,
+                    subtype,
+                    host,
+                    class,
+                    ttl,
+                }
+            }
+
+            QueryType::NULL => {
+                let data = buffer.read_bytes(data_len as usize)?.to_vec();
+
+                DnsRecord::NULL {
+                    domain,
+                    data,
+                    class,
+                    ttl,
+                }
             }
 
-            QueryType::UNKNOWN(_) => {
-                buffer.step(data_len as usize)?;
+            QueryType::WKS => {
+                // 8192 bytes of bitmap already cover every port there is
+                if data_len < 5 || data_len - 5 > 8192 {
+                    return Err(DnsError::InvalidRecord(format!("Invalid WKS data length {}", data_len)));
+                }
+                let addr = Ipv4Addr::from(buffer.read_u32()?);
+                let protocol = buffer.read_u8()?;
+
+                // Bit n of the bitmap, counting from the most significant
+                // bit of the first byte, is set when port n is in service
+                let mut ports = Vec::new();
+                for i in 0..(data_len - 5) {
+                    let byte = buffer.read_u8()?;
+                    for bit in 0..8 {
+                        if byte & (0x80 >> bit) > 0 {
+                            ports.push(i * 8 + bit);
+                        }
+                    }
+                }
+
+                DnsRecord::WKS {
+                    domain,
+                    addr,
+                    protocol,
+                    ports,
+                    class,
+                    ttl,
+                }
+            }
+
+            QueryType::SPF => {
+                let strings = read_strings(buffer, data_start + data_len as usize)?;
+
+                DnsRecord::SPF {
+                    domain,
+                    strings,
+                    class,
+                    ttl,
+                }
+            }
+
+            QueryType::TLSA => {
+                if data_len < 3 {
+                    return Err(DnsError::InvalidRecord(format!("Invalid TLSA data length {}", data_len)));
+                }
+                let usage = buffer.read_u8()?;
+                let selector = buffer.read_u8()?;
+                let matching_type = buffer.read_u8()?;
+
+                let cert_data = buffer.read_bytes(data_len as usize - 3)?.to_vec();
+
+                DnsRecord::TLSA {
+                    domain,
+                    usage,
+                    selector,
+                    matching_type,
+                    cert_data,
+                    class,
+                    ttl,
+                }
+            }
 
-                Ok(DnsRecord::UNKNOWN {
-                    domain: domain,
+            QueryType::NAPTR => {
+                let data_end = data_start + data_len as usize;
+                let order = buffer.read_u16()?;
+                let preference = buffer.read_u16()?;
+                let flags = read_character_string(buffer, data_end)?;
+                let services = read_character_string(buffer, data_end)?;
+                let regexp = read_character_string(buffer, data_end)?;
+
+                // The replacement is never compressed (RFC 3403 section 4.1)
+                let mut replacement = String::new();
+                buffer.read_qname_uncompressed(&mut replacement)?;
+
+                DnsRecord::NAPTR {
+                    domain,
+                    order,
+                    preference,
+                    flags,
+                    services,
+                    regexp,
+                    replacement,
+                    class,
+                    ttl,
+                }
+            }
+
+            QueryType::DNAME => {
+                // The target is never compressed (RFC 6672 section 2.5)
+                let mut target = String::new();
+                buffer.read_qname_uncompressed(&mut target)?;
+
+                DnsRecord::DNAME {
+                    domain,
+                    target,
+                    class,
+                    ttl,
+                }
+            }
+
+            // ANY only makes sense in questions, so a record claiming it is
+            // treated like any other type we don't understand
+            QueryType::UNKNOWN(_) | QueryType::ANY => {
+                // Types we don't understand are kept as opaque rdata (RFC 3597),
+                // so they can still be passed along unchanged
+                let rdata = buffer.read_bytes(data_len as usize)?.to_vec();
+
+                DnsRecord::UNKNOWN {
+                    domain,
                     qtype: qtype_num,
-                    data_len: data_len,
-                    ttl: ttl,
-                })
+                    rdata,
+                    class,
+                    ttl,
+                }
             }
-        }
+        };
+
+        // Compressed names can make the rdata we parsed shorter than
+        // data_len, so always continue right after the declared rdata.
+        buffer.seek(data_start + data_len as usize)?;
+
+        Ok(record)
     }
 
-    pub fn write(&self, buffer: &mut BytePacketBuffer) -> Result<usize, Box<dyn Error>> {
+    pub fn write<T: PacketBuffer>(&self, buffer: &mut T) -> Result<usize, DnsError> {
         let start_pos = buffer.pos();
 
         match *self {
             DnsRecord::A {
                 ref domain,
                 ref addr,
+                class,
                 ttl,
             } => {
-                buffer.write_qname(domain)?;
+                buffer.write_name(domain)?;
                 buffer.write_u16(QueryType::A.to_num())?;
-                buffer.write_u16(1)?;
+                buffer.write_u16(class.to_num())?;
                 buffer.write_u32(ttl)?;
                 buffer.write_u16(4)?;
 
@@ -180,11 +1022,12 @@ impl DnsRecord {
             DnsRecord::NS {
                 ref domain,
                 ref host,
+                class,
                 ttl,
             } => {
-                buffer.write_qname(domain)?;
+                buffer.write_name(domain)?;
                 buffer.write_u16(QueryType::NS.to_num())?;
-                buffer.write_u16(1)?;
+                buffer.write_u16(class.to_num())?;
                 buffer.write_u32(ttl)?;
                 
                 let pos = buffer.pos();
@@ -199,11 +1042,12 @@ impl DnsRecord {
             DnsRecord::CNAME {
                 ref domain,
                 ref host,
+                class,
                 ttl,
             } => {
-                buffer.write_qname(domain)?;
+                buffer.write_name(domain)?;
                 buffer.write_u16(QueryType::CNAME.to_num())?;
-                buffer.write_u16(1)?;
+                buffer.write_u16(class.to_num())?;
                 buffer.write_u32(ttl)?;
 
                 let pos = buffer.pos();
@@ -219,11 +1063,12 @@ impl DnsRecord {
                 ref domain,
                 priority,
                 ref host,
+                class,
                 ttl,
             } => {
-                buffer.write_qname(domain)?;
+                buffer.write_name(domain)?;
                 buffer.write_u16(QueryType::MX.to_num())?;
-                buffer.write_u16(1)?;
+                buffer.write_u16(class.to_num())?;
                 buffer.write_u32(ttl)?;
 
                 let pos = buffer.pos();
@@ -239,74 +1084,2532 @@ impl DnsRecord {
             DnsRecord::AAAA {
                 ref domain,
                 ref addr,
+                class,
                 ttl,
             } => {
-                buffer.write_qname(domain)?;
+                buffer.write_name(domain)?;
                 buffer.write_u16(QueryType::AAAA.to_num())?;
-                buffer.write_u16(1)?;
+                buffer.write_u16(class.to_num())?;
                 buffer.write_u32(ttl)?;
                 buffer.write_u16(16)?;
-
-                for octet in &addr.segments() {
-                    buffer.write_u16(*octet)?;
-                }
+                buffer.write_u128(u128::from(*addr))?;
             }
 
-            DnsRecord::UNKNOWN { .. } => {
-                println!("Skipping record: {:?}", self);
+            DnsRecord::SOA {
+                ref domain,
+                ref mname,
+                ref rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+                class,
+                ttl,
+            } => {
+                buffer.write_name(domain)?;
+                buffer.write_u16(QueryType::SOA.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(ttl)?;
+
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+
+                buffer.write_qname(mname)?;
+                buffer.write_qname(rname)?;
+                buffer.write_u32(serial)?;
+                buffer.write_u32(refresh)?;
+                buffer.write_u32(retry)?;
+                buffer.write_u32(expire)?;
+                buffer.write_u32(minimum)?;
+
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
             }
-        }
 
-        Ok(buffer.pos() - start_pos)
-    }
-}
+            DnsRecord::TXT {
+                ref domain,
+                ref strings,
+                class,
+                ttl,
+            } => {
+                buffer.write_name(domain)?;
+                buffer.write_u16(QueryType::TXT.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(ttl)?;
 
-impl DnsQuestion {
-    pub fn new(name: String, qtype: QueryType) -> DnsQuestion {
-        DnsQuestion {
-            name: name,
-            qtype: qtype,
-        }
-    }
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
 
-    pub fn read(&mut self, buffer: &mut BytePacketBuffer) -> Result<(), Box<dyn Error>> {
-        buffer.read_qname(&mut self.name)?;
-        self.qtype = QueryType::from_num(buffer.read_u16()?); // qtype
-        let _ = buffer.read_u16()?; // class
-        Ok(())
-    }
+                write_strings(buffer, strings)?;
 
-    pub fn write(&self, buffer: &mut BytePacketBuffer) -> Result<(), Box<dyn Error>> {
-        buffer.write_qname(&self.name)?;
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
 
-        let typenum = self.qtype.to_num();
-        buffer.write_u16(typenum)?;
-        buffer.write_u16(1)?;
+            DnsRecord::SRV {
+                ref domain,
+                priority,
+                weight,
+                port,
+                ref host,
+                class,
+                ttl,
+            } => {
+                buffer.write_name(domain)?;
+                buffer.write_u16(QueryType::SRV.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(ttl)?;
 
-        Ok(())
-    }
-}
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
 
-impl QueryType {
-    pub fn to_num(&self) -> u16 {
-        match *self {
-            QueryType::UNKNOWN(x) => x,
-            QueryType::A => 1,
-            QueryType::NS => 2,
-            QueryType::CNAME => 5,
-            QueryType::MX => 15,
-            QueryType::AAAA => 28,
-        }
-    }
+                buffer.write_u16(priority)?;
+                buffer.write_u16(weight)?;
+                buffer.write_u16(port)?;
+                buffer.write_qname(host)?;
 
-    pub fn from_num(num: u16) -> QueryType {
-        match num {
-            1 => QueryType::A,
-            2 => QueryType::NS,
-            5 => QueryType::CNAME,
-            15 => QueryType::MX,
-            28 => QueryType::AAAA,
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+
+            DnsRecord::PTR {
+                ref domain,
+                ref host,
+                class,
+                ttl,
+            } => {
+                buffer.write_name(domain)?;
+                buffer.write_u16(QueryType::PTR.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(ttl)?;
+
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+
+                buffer.write_qname(host)?;
+
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+
+            DnsRecord::CAA {
+                ref domain,
+                flags,
+                ref tag,
+                ref value,
+                class,
+                ttl,
+            } => {
+                buffer.write_name(domain)?;
+                buffer.write_u16(QueryType::CAA.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(ttl)?;
+
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+
+                // RFC 8659 section 4.1 only allows tags of up to 15 letters
+                // and digits, which also keeps the length within its byte
+                if tag.is_empty() || tag.len() > 15 || !tag.bytes().all(|b| b.is_ascii_alphanumeric()) {
+                    return Err(DnsError::InvalidRecord(format!("Invalid CAA tag {:?}", tag)));
+                }
+
+                buffer.write_u8(flags)?;
+                buffer.write_u8(tag.len() as u8)?;
+                buffer.write_bytes(tag.as_bytes())?;
+                buffer.write_bytes(value)?;
+
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+
+            DnsRecord::OPT {
+                ref domain,
+                packet_len,
+                extended_rcode,
+                version,
+                dnssec_ok,
+                ref options,
+            } => {
+                buffer.write_name(domain)?;
+                buffer.write_u16(QueryType::OPT.to_num())?;
+                buffer.write_u16(packet_len)?;
+                buffer.write_u32(
+                    ((extended_rcode as u32) << 24)
+                        | ((version as u32) << 16)
+                        | ((dnssec_ok as u32) << 15),
+                )?;
+
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+
+                for (code, data) in options {
+                    buffer.write_u16(*code)?;
+                    buffer.write_u16(rdata_len(0, data.len())?)?;
+                    buffer.write_bytes(data)?;
+                }
+
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+
+            DnsRecord::DNSKEY {
+                ref domain,
+                flags,
+                protocol,
+                algorithm,
+                ref public_key,
+                class,
+                ttl,
+            } => {
+                buffer.write_name(domain)?;
+                buffer.write_u16(QueryType::DNSKEY.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(ttl)?;
+                buffer.write_u16(rdata_len(4, public_key.len())?)?;
+
+                buffer.write_u16(flags)?;
+                buffer.write_u8(protocol)?;
+                buffer.write_u8(algorithm)?;
+                buffer.write_bytes(public_key)?;
+            }
+
+            DnsRecord::DS {
+                ref domain,
+                key_tag,
+                algorithm,
+                digest_type,
+                ref digest,
+                class,
+                ttl,
+            } => {
+                buffer.write_name(domain)?;
+                buffer.write_u16(QueryType::DS.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(ttl)?;
+                buffer.write_u16(rdata_len(4, digest.len())?)?;
+
+                buffer.write_u16(key_tag)?;
+                buffer.write_u8(algorithm)?;
+                buffer.write_u8(digest_type)?;
+                buffer.write_bytes(digest)?;
+            }
+
+            DnsRecord::RRSIG {
+                ref domain,
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                expiration,
+                inception,
+                key_tag,
+                ref signer_name,
+                ref signature,
+                class,
+                ttl,
+            } => {
+                buffer.write_name(domain)?;
+                buffer.write_u16(QueryType::RRSIG.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(ttl)?;
+
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+
+                buffer.write_u16(type_covered.to_num())?;
+                buffer.write_u8(algorithm)?;
+                buffer.write_u8(labels)?;
+                buffer.write_u32(original_ttl)?;
+                buffer.write_u32(expiration)?;
+                buffer.write_u32(inception)?;
+                buffer.write_u16(key_tag)?;
+                // Signatures are computed over the uncompressed signer name,
+                // which is the only form write_qname produces.
+                buffer.write_qname(signer_name)?;
+                buffer.write_bytes(signature)?;
+
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+
+            DnsRecord::NSEC {
+                ref domain,
+                ref next_domain,
+                ref types,
+                class,
+                ttl,
+            } => {
+                buffer.write_name(domain)?;
+                buffer.write_u16(QueryType::NSEC.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(ttl)?;
+
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+
+                buffer.write_qname(next_domain)?;
+                write_type_bitmap(buffer, types)?;
+
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+
+            DnsRecord::NSEC3 {
+                ref domain,
+                hash_algorithm,
+                flags,
+                iterations,
+                ref salt,
+                ref next_hashed,
+                ref types,
+                class,
+                ttl,
+            } => {
+                buffer.write_name(domain)?;
+                buffer.write_u16(QueryType::NSEC3.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(ttl)?;
+
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+
+                buffer.write_u8(hash_algorithm)?;
+                buffer.write_u8(flags)?;
+                buffer.write_u16(iterations)?;
+                write_u8_prefixed(buffer, salt, "NSEC3 salt")?;
+                write_u8_prefixed(buffer, next_hashed, "NSEC3 next hashed owner name")?;
+                write_type_bitmap(buffer, types)?;
+
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+
+            DnsRecord::NSEC3PARAM {
+                ref domain,
+                hash_algorithm,
+                flags,
+                iterations,
+                ref salt,
+                class,
+                ttl,
+            } => {
+                buffer.write_name(domain)?;
+                buffer.write_u16(QueryType::NSEC3PARAM.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(ttl)?;
+                buffer.write_u16(rdata_len(5, salt.len())?)?;
+
+                buffer.write_u8(hash_algorithm)?;
+                buffer.write_u8(flags)?;
+                buffer.write_u16(iterations)?;
+                write_u8_prefixed(buffer, salt, "NSEC3PARAM salt")?;
+            }
+
+            DnsRecord::HINFO {
+                ref domain,
+                ref cpu,
+                ref os,
+                class,
+                ttl,
+            } => {
+                buffer.write_name(domain)?;
+                buffer.write_u16(QueryType::HINFO.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(ttl)?;
+
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+
+                write_character_string(buffer, cpu)?;
+                write_character_string(buffer, os)?;
+
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+
+            DnsRecord::LOC {
+                ref domain,
+                version,
+                size,
+                horiz_pre,
+                vert_pre,
+                latitude,
+                longitude,
+                altitude,
+                class,
+                ttl,
+            } => {
+                buffer.write_name(domain)?;
+                buffer.write_u16(QueryType::LOC.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(ttl)?;
+                buffer.write_u16(16)?;
+
+                buffer.write_u8(version)?;
+                buffer.write_u8(size)?;
+                buffer.write_u8(horiz_pre)?;
+                buffer.write_u8(vert_pre)?;
+                buffer.write_u32(latitude)?;
+                buffer.write_u32(longitude)?;
+                buffer.write_u32(altitude)?;
+            }
+
+            DnsRecord::URI {
+                ref domain,
+                priority,
+                weight,
+                ref target,
+                class,
+                ttl,
+            } => {
+                buffer.write_name(domain)?;
+                buffer.write_u16(QueryType::URI.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(ttl)?;
+                buffer.write_u16(rdata_len(4, target.len())?)?;
+
+                buffer.write_u16(priority)?;
+                buffer.write_u16(weight)?;
+                buffer.write_bytes(target.as_bytes())?;
+            }
+
+            DnsRecord::SMIMEA {
+                ref domain,
+                usage,
+                selector,
+                matching_type,
+                ref data,
+                class,
+                ttl,
+            } => {
+                buffer.write_name(domain)?;
+                buffer.write_u16(QueryType::SMIMEA.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(ttl)?;
+                buffer.write_u16(rdata_len(3, data.len())?)?;
+
+                buffer.write_u8(usage)?;
+                buffer.write_u8(selector)?;
+                buffer.write_u8(matching_type)?;
+                buffer.write_bytes(data)?;
+            }
+
+            DnsRecord::OPENPGPKEY {
+                ref domain,
+                ref public_key,
+                class,
+                ttl,
+            } => {
+                buffer.write_name(domain)?;
+                buffer.write_u16(QueryType::OPENPGPKEY.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(ttl)?;
+                buffer.write_u16(rdata_len(0, public_key.len())?)?;
+
+                buffer.write_bytes(public_key)?;
+            }
+
+            DnsRecord::IPSECKEY {
+                ref domain,
+                precedence,
+                algorithm,
+                ref gateway,
+                ref public_key,
+                class,
+                ttl,
+            } => {
+                buffer.write_name(domain)?;
+                buffer.write_u16(QueryType::IPSECKEY.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(ttl)?;
+
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+
+                buffer.write_u8(precedence)?;
+                buffer.write_u8(gateway.type_num())?;
+                buffer.write_u8(algorithm)?;
+                match gateway {
+                    IpsecGateway::None => {}
+                    IpsecGateway::V4(addr) => buffer.write_u32(u32::from(*addr))?,
+                    IpsecGateway::V6(addr) => buffer.write_u128(u128::from(*addr))?,
+                    // Gateway names are never compressed
+                    IpsecGateway::Name(name) => buffer.write_qname(name)?,
+                }
+                buffer.write_bytes(public_key)?;
+
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+
+            DnsRecord::RP {
+                ref domain,
+                ref mbox,
+                ref txt,
+                class,
+                ttl,
+            } => {
+                buffer.write_name(domain)?;
+                buffer.write_u16(QueryType::RP.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(ttl)?;
+
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+
+                buffer.write_qname(mbox)?;
+                buffer.write_qname(txt)?;
+
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+
+            DnsRecord::AFSDB {
+                ref domain,
+                subtype,
+                ref host,
+                class,
+                ttl,
+            } => {
+                buffer.write_name(domain)?;
+                buffer.write_u16(QueryType::AFSDB.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(ttl)?;
+
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+
+                buffer.write_u16(subtype)?;
+                buffer.write_qname(host)?;
+
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+
+            DnsRecord::NULL {
+                ref domain,
+                ref data,
+                class,
+                ttl,
+            } => {
+                buffer.write_name(domain)?;
+                buffer.write_u16(QueryType::NULL.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(ttl)?;
+                buffer.write_u16(rdata_len(0, data.len())?)?;
+
+                buffer.write_bytes(data)?;
+            }
+
+            DnsRecord::WKS {
+                ref domain,
+                addr,
+                protocol,
+                ref ports,
+                class,
+                ttl,
+            } => {
+                buffer.write_name(domain)?;
+                buffer.write_u16(QueryType::WKS.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(ttl)?;
+
+                let len = ports.iter().max().map_or(0, |max| *max as usize / 8 + 1);
+                let mut bitmap = vec![0u8; len];
+                for port in ports {
+                    bitmap[*port as usize / 8] |= 0x80 >> (port % 8);
+                }
+
+                buffer.write_u16(rdata_len(5, len)?)?;
+                buffer.write_u32(u32::from(addr))?;
+                buffer.write_u8(protocol)?;
+                buffer.write_bytes(&bitmap)?;
+            }
+
+            DnsRecord::SPF {
+                ref domain,
+                ref strings,
+                class,
+                ttl,
+            } => {
+                buffer.write_name(domain)?;
+                buffer.write_u16(QueryType::SPF.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(ttl)?;
+
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+
+                write_strings(buffer, strings)?;
+
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+
+            DnsRecord::TLSA {
+                ref domain,
+                usage,
+                selector,
+                matching_type,
+                ref cert_data,
+                class,
+                ttl,
+            } => {
+                buffer.write_name(domain)?;
+                buffer.write_u16(QueryType::TLSA.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(ttl)?;
+                buffer.write_u16(rdata_len(3, cert_data.len())?)?;
+
+                buffer.write_u8(usage)?;
+                buffer.write_u8(selector)?;
+                buffer.write_u8(matching_type)?;
+                buffer.write_bytes(cert_data)?;
+            }
+
+            DnsRecord::NAPTR {
+                ref domain,
+                order,
+                preference,
+                ref flags,
+                ref services,
+                ref regexp,
+                ref replacement,
+                class,
+                ttl,
+            } => {
+                buffer.write_name(domain)?;
+                buffer.write_u16(QueryType::NAPTR.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(ttl)?;
+
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+
+                buffer.write_u16(order)?;
+                buffer.write_u16(preference)?;
+                write_character_string(buffer, flags)?;
+                write_character_string(buffer, services)?;
+                write_character_string(buffer, regexp)?;
+                // Never to be compressed (RFC 3403 section 4.1)
+                buffer.write_qname(replacement)?;
+
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+
+            DnsRecord::DNAME {
+                ref domain,
+                ref target,
+                class,
+                ttl,
+            } => {
+                buffer.write_name(domain)?;
+                buffer.write_u16(QueryType::DNAME.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(ttl)?;
+
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+
+                buffer.write_qname(target)?;
+
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+
+            DnsRecord::UNKNOWN {
+                ref domain,
+                qtype,
+                ref rdata,
+                class,
+                ttl,
+            } => {
+                buffer.write_name(domain)?;
+                buffer.write_u16(qtype)?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(ttl)?;
+                buffer.write_u16(rdata_len(0, rdata.len())?)?;
+
+                buffer.write_bytes(rdata)?;
+            }
+        }
+
+        Ok(buffer.pos() - start_pos)
+    }
+
+    /// Whether this is a CAA record with the issuer critical flag set, in
+    /// which case a CA that doesn't understand the tag must not issue.
+    pub fn is_critical(&self) -> bool {
+        matches!(self, DnsRecord::CAA { flags, .. } if flags & 0x80 != 0)
+    }
+
+    /// The key tag of a DNSKEY record as computed in RFC 4034 Appendix B,
+    /// which is how DS and RRSIG records refer to the key.
+    pub fn key_tag(&self) -> Option<u16> {
+        match self {
+            DnsRecord::DNSKEY { flags, protocol, algorithm, public_key, .. } => {
+                let header = [(flags >> 8) as u8, (flags & 0xFF) as u8, *protocol, *algorithm];
+
+                let mut ac: u32 = 0;
+                for (i, b) in header.iter().chain(public_key.iter()).enumerate() {
+                    if i & 1 == 0 {
+                        ac += (*b as u32) << 8;
+                    } else {
+                        ac += *b as u32;
+                    }
+                }
+                ac += (ac >> 16) & 0xFFFF;
+
+                Some((ac & 0xFFFF) as u16)
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether an RRSIG record's validity period covers the given unix time.
+    /// The timestamps wrap around, so they're compared using serial number
+    /// arithmetic (RFC 1982) as RFC 4034 asks.
+    pub fn is_valid_at(&self, unix_time: u32) -> bool {
+        match self {
+            DnsRecord::RRSIG { expiration, inception, .. } => {
+                (unix_time.wrapping_sub(*inception) as i32) >= 0
+                    && (expiration.wrapping_sub(unix_time) as i32) >= 0
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether an NSEC record proves the existence of a name, i.e. the
+    /// name is the owner of the record.
+    pub fn matches(&self, name: &str) -> bool {
+        match self {
+            DnsRecord::NSEC { domain, .. } => *domain == *name,
+            _ => false,
+        }
+    }
+
+    /// Whether an NSEC record proves that a name doesn't exist, because it
+    /// falls strictly between the owner and the next name in the zone. The
+    /// last NSEC of a zone points back to the apex, wrapping around.
+    pub fn covers(&self, name: &str) -> bool {
+        match self {
+            DnsRecord::NSEC { domain, next_domain, .. } => {
+                let name = Name::from(name);
+                let next_domain = Name::from(next_domain.as_str());
+                let after_owner = *domain < name;
+                let before_next = name < next_domain;
+
+                if *domain < next_domain {
+                    after_owner && before_next
+                } else {
+                    after_owner || before_next
+                }
+            }
+            _ => false,
+        }
+    }
+
+    /// The position of a LOC record as latitude and longitude in decimal
+    /// degrees, and altitude in meters. On the wire the angles are
+    /// thousandths of an arc second offset by 2^31, and the altitude is in
+    /// centimeters above a base of 100000 meters below the WGS 84 ellipsoid.
+    pub fn loc_position(&self) -> Option<(f64, f64, f64)> {
+        match self {
+            DnsRecord::LOC { latitude, longitude, altitude, .. } => {
+                let degrees = |raw: u32| (raw as i64 - (1 << 31)) as f64 / 3_600_000.0;
+                let meters = *altitude as f64 / 100.0 - 100_000.0;
+
+                Some((degrees(*latitude), degrees(*longitude), meters))
+            }
+            _ => None,
+        }
+    }
+
+    /// The size, horizontal precision and vertical precision of a LOC record
+    /// in meters. Each is encoded as a mantissa in the high nibble and a
+    /// power of ten in the low one, counting centimeters.
+    pub fn loc_precision(&self) -> Option<(f64, f64, f64)> {
+        match self {
+            DnsRecord::LOC { size, horiz_pre, vert_pre, .. } => {
+                let meters = |raw: u8| (raw >> 4) as f64 * 10f64.powi((raw & 0x0F) as i32) / 100.0;
+
+                Some((meters(*size), meters(*horiz_pre), meters(*vert_pre)))
+            }
+            _ => None,
+        }
+    }
+
+    /// The key or certificate material of OPENPGPKEY and SMIMEA records in
+    /// base64, the way it's presented in zone files, which is a lot easier
+    /// on the eyes than the raw bytes.
+    pub fn payload_base64(&self) -> Option<String> {
+        match self {
+            DnsRecord::OPENPGPKEY { public_key, .. } => Some(base64_encode(public_key)),
+            DnsRecord::SMIMEA { data, .. } => Some(base64_encode(data)),
+            _ => None,
+        }
+    }
+
+    /// The text of a TXT or SPF record, with its character strings joined
+    /// together the way SPF policies are meant to be read. Bytes which
+    /// aren't valid UTF-8 are replaced.
+    pub fn text(&self) -> Option<String> {
+        match self {
+            DnsRecord::TXT { strings, .. } | DnsRecord::SPF { strings, .. } => {
+                Some(String::from_utf8_lossy(&strings.concat()).into_owned())
+            }
+            _ => None,
+        }
+    }
+
+    /// Split a text into the character strings of TXT or SPF rdata, none of
+    /// which can hold more than 255 bytes.
+    pub fn text_strings(text: &str) -> Vec<Vec<u8>> {
+        if text.is_empty() {
+            return vec![Vec::new()];
+        }
+
+        text.as_bytes().chunks(255).map(|chunk| chunk.to_vec()).collect()
+    }
+
+    /// The owner name of the record, regardless of its type
+    pub fn domain(&self) -> &Name {
+        match self {
+            DnsRecord::UNKNOWN { domain, .. }
+            | DnsRecord::A { domain, .. }
+            | DnsRecord::NS { domain, .. }
+            | DnsRecord::CNAME { domain, .. }
+            | DnsRecord::MX { domain, .. }
+            | DnsRecord::SOA { domain, .. }
+            | DnsRecord::TXT { domain, .. }
+            | DnsRecord::SRV { domain, .. }
+            | DnsRecord::PTR { domain, .. }
+            | DnsRecord::CAA { domain, .. }
+            | DnsRecord::OPT { domain, .. }
+            | DnsRecord::DNSKEY { domain, .. }
+            | DnsRecord::DS { domain, .. }
+            | DnsRecord::RRSIG { domain, .. }
+            | DnsRecord::NSEC { domain, .. }
+            | DnsRecord::NSEC3 { domain, .. }
+            | DnsRecord::NSEC3PARAM { domain, .. }
+            | DnsRecord::HINFO { domain, .. }
+            | DnsRecord::LOC { domain, .. }
+            | DnsRecord::URI { domain, .. }
+            | DnsRecord::SMIMEA { domain, .. }
+            | DnsRecord::OPENPGPKEY { domain, .. }
+            | DnsRecord::IPSECKEY { domain, .. }
+            | DnsRecord::RP { domain, .. }
+            | DnsRecord::AFSDB { domain, .. }
+            | DnsRecord::NULL { domain, .. }
+            | DnsRecord::WKS { domain, .. }
+            | DnsRecord::SPF { domain, .. }
+            | DnsRecord::TLSA { domain, .. }
+            | DnsRecord::NAPTR { domain, .. }
+            | DnsRecord::DNAME { domain, .. }
+            | DnsRecord::AAAA { domain, .. } => domain,
+        }
+    }
+
+
+    /// The type of the record, regardless of its data
+    pub fn qtype(&self) -> QueryType {
+        match self {
+            DnsRecord::UNKNOWN { qtype, .. } => QueryType::from_num(*qtype),
+            DnsRecord::A { .. } => QueryType::A,
+            DnsRecord::NS { .. } => QueryType::NS,
+            DnsRecord::CNAME { .. } => QueryType::CNAME,
+            DnsRecord::SOA { .. } => QueryType::SOA,
+            DnsRecord::NULL { .. } => QueryType::NULL,
+            DnsRecord::WKS { .. } => QueryType::WKS,
+            DnsRecord::PTR { .. } => QueryType::PTR,
+            DnsRecord::HINFO { .. } => QueryType::HINFO,
+            DnsRecord::MX { .. } => QueryType::MX,
+            DnsRecord::TXT { .. } => QueryType::TXT,
+            DnsRecord::RP { .. } => QueryType::RP,
+            DnsRecord::AFSDB { .. } => QueryType::AFSDB,
+            DnsRecord::AAAA { .. } => QueryType::AAAA,
+            DnsRecord::LOC { .. } => QueryType::LOC,
+            DnsRecord::SRV { .. } => QueryType::SRV,
+            DnsRecord::NAPTR { .. } => QueryType::NAPTR,
+            DnsRecord::DNAME { .. } => QueryType::DNAME,
+            DnsRecord::OPT { .. } => QueryType::OPT,
+            DnsRecord::DS { .. } => QueryType::DS,
+            DnsRecord::IPSECKEY { .. } => QueryType::IPSECKEY,
+            DnsRecord::RRSIG { .. } => QueryType::RRSIG,
+            DnsRecord::NSEC { .. } => QueryType::NSEC,
+            DnsRecord::DNSKEY { .. } => QueryType::DNSKEY,
+            DnsRecord::NSEC3 { .. } => QueryType::NSEC3,
+            DnsRecord::NSEC3PARAM { .. } => QueryType::NSEC3PARAM,
+            DnsRecord::TLSA { .. } => QueryType::TLSA,
+            DnsRecord::SMIMEA { .. } => QueryType::SMIMEA,
+            DnsRecord::OPENPGPKEY { .. } => QueryType::OPENPGPKEY,
+            DnsRecord::SPF { .. } => QueryType::SPF,
+            DnsRecord::URI { .. } => QueryType::URI,
+            DnsRecord::CAA { .. } => QueryType::CAA,
+        }
+    }
+
+    /// The class of the record. The OPT pseudo-record uses the class field
+    /// for the UDP payload size of the sender, which is returned as is.
+    pub fn class(&self) -> DnsClass {
+        match self {
+            DnsRecord::UNKNOWN { class, .. }
+            | DnsRecord::A { class, .. }
+            | DnsRecord::NS { class, .. }
+            | DnsRecord::CNAME { class, .. }
+            | DnsRecord::SOA { class, .. }
+            | DnsRecord::NULL { class, .. }
+            | DnsRecord::WKS { class, .. }
+            | DnsRecord::PTR { class, .. }
+            | DnsRecord::HINFO { class, .. }
+            | DnsRecord::MX { class, .. }
+            | DnsRecord::TXT { class, .. }
+            | DnsRecord::RP { class, .. }
+            | DnsRecord::AFSDB { class, .. }
+            | DnsRecord::AAAA { class, .. }
+            | DnsRecord::LOC { class, .. }
+            | DnsRecord::SRV { class, .. }
+            | DnsRecord::NAPTR { class, .. }
+            | DnsRecord::DNAME { class, .. }
+            | DnsRecord::DS { class, .. }
+            | DnsRecord::IPSECKEY { class, .. }
+            | DnsRecord::RRSIG { class, .. }
+            | DnsRecord::NSEC { class, .. }
+            | DnsRecord::DNSKEY { class, .. }
+            | DnsRecord::NSEC3 { class, .. }
+            | DnsRecord::NSEC3PARAM { class, .. }
+            | DnsRecord::TLSA { class, .. }
+            | DnsRecord::SMIMEA { class, .. }
+            | DnsRecord::OPENPGPKEY { class, .. }
+            | DnsRecord::SPF { class, .. }
+            | DnsRecord::URI { class, .. }
+            | DnsRecord::CAA { class, .. } => *class,
+            DnsRecord::OPT { packet_len, .. } => DnsClass::UNKNOWN(*packet_len),
+        }
+    }
+
+    /// The time to live of the record in seconds
+    pub fn ttl(&self) -> u32 {
+        match self {
+            DnsRecord::UNKNOWN { ttl, .. }
+            | DnsRecord::A { ttl, .. }
+            | DnsRecord::NS { ttl, .. }
+            | DnsRecord::CNAME { ttl, .. }
+            | DnsRecord::MX { ttl, .. }
+            | DnsRecord::SOA { ttl, .. }
+            | DnsRecord::TXT { ttl, .. }
+            | DnsRecord::SRV { ttl, .. }
+            | DnsRecord::PTR { ttl, .. }
+            | DnsRecord::CAA { ttl, .. }
+            | DnsRecord::DNSKEY { ttl, .. }
+            | DnsRecord::DS { ttl, .. }
+            | DnsRecord::RRSIG { ttl, .. }
+            | DnsRecord::NSEC { ttl, .. }
+            | DnsRecord::NSEC3 { ttl, .. }
+            | DnsRecord::NSEC3PARAM { ttl, .. }
+            | DnsRecord::HINFO { ttl, .. }
+            | DnsRecord::LOC { ttl, .. }
+            | DnsRecord::URI { ttl, .. }
+            | DnsRecord::SMIMEA { ttl, .. }
+            | DnsRecord::OPENPGPKEY { ttl, .. }
+            | DnsRecord::IPSECKEY { ttl, .. }
+            | DnsRecord::RP { ttl, .. }
+            | DnsRecord::AFSDB { ttl, .. }
+            | DnsRecord::NULL { ttl, .. }
+            | DnsRecord::WKS { ttl, .. }
+            | DnsRecord::SPF { ttl, .. }
+            | DnsRecord::TLSA { ttl, .. }
+            | DnsRecord::NAPTR { ttl, .. }
+            | DnsRecord::DNAME { ttl, .. }
+            | DnsRecord::AAAA { ttl, .. } => *ttl,
+            // The OPT pseudo-record uses the ttl field for EDNS flags
+            DnsRecord::OPT { .. } => 0,
+        }
+    }
+
+    /// Change the time to live, such as when handing out a cached record
+    /// which has been around for a while.
+    pub fn set_ttl(&mut self, value: u32) {
+        match self {
+            DnsRecord::UNKNOWN { ttl, .. }
+            | DnsRecord::A { ttl, .. }
+            | DnsRecord::NS { ttl, .. }
+            | DnsRecord::CNAME { ttl, .. }
+            | DnsRecord::MX { ttl, .. }
+            | DnsRecord::SOA { ttl, .. }
+            | DnsRecord::TXT { ttl, .. }
+            | DnsRecord::SRV { ttl, .. }
+            | DnsRecord::PTR { ttl, .. }
+            | DnsRecord::CAA { ttl, .. }
+            | DnsRecord::DNSKEY { ttl, .. }
+            | DnsRecord::DS { ttl, .. }
+            | DnsRecord::RRSIG { ttl, .. }
+            | DnsRecord::NSEC { ttl, .. }
+            | DnsRecord::NSEC3 { ttl, .. }
+            | DnsRecord::NSEC3PARAM { ttl, .. }
+            | DnsRecord::HINFO { ttl, .. }
+            | DnsRecord::LOC { ttl, .. }
+            | DnsRecord::URI { ttl, .. }
+            | DnsRecord::SMIMEA { ttl, .. }
+            | DnsRecord::OPENPGPKEY { ttl, .. }
+            | DnsRecord::IPSECKEY { ttl, .. }
+            | DnsRecord::RP { ttl, .. }
+            | DnsRecord::AFSDB { ttl, .. }
+            | DnsRecord::NULL { ttl, .. }
+            | DnsRecord::WKS { ttl, .. }
+            | DnsRecord::SPF { ttl, .. }
+            | DnsRecord::TLSA { ttl, .. }
+            | DnsRecord::NAPTR { ttl, .. }
+            | DnsRecord::DNAME { ttl, .. }
+            | DnsRecord::AAAA { ttl, .. } => *ttl = value,
+            // The OPT pseudo-record uses the ttl field for EDNS flags
+            DnsRecord::OPT { .. } => {}
+        }
+    }
+}
+
+/// Records are shown the way they appear in zone files and dig output: owner,
+/// ttl, class and type followed by the data in its presentation format.
+impl fmt::Display for DnsRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // The OPT pseudo-record has none of the usual fields, so like dig we
+        // describe it in a comment instead
+        if let DnsRecord::OPT { packet_len, version, dnssec_ok, .. } = self {
+            let flags = if *dnssec_ok { " do" } else { "" };
+            return write!(f, "; EDNS: version: {}, flags:{}; udp: {}", version, flags, packet_len);
+        }
+
+        write!(f, "{}\t{}\t{}\t{}\t", absolute_name(&self.domain().to_string()), self.ttl(), self.class(), self.qtype())?;
+
+        match self {
+            DnsRecord::UNKNOWN { rdata: data, .. } | DnsRecord::NULL { data, .. } => {
+                // The generic encoding of RFC 3597
+                write!(f, "\\# {} {}", data.len(), hex_encode(data))
+            }
+            DnsRecord::A { addr, .. } => write!(f, "{}", addr),
+            DnsRecord::AAAA { addr, .. } => write!(f, "{}", addr),
+            DnsRecord::NS { host, .. } | DnsRecord::CNAME { host, .. } | DnsRecord::PTR { host, .. } => {
+                write!(f, "{}", absolute_name(host))
+            }
+            DnsRecord::SOA { mname, rname, serial, refresh, retry, expire, minimum, .. } => write!(
+                f,
+                "{} {} {} {} {} {} {}",
+                absolute_name(mname),
+                absolute_name(rname),
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum
+            ),
+            DnsRecord::WKS { addr, protocol, ports, .. } => {
+                write!(f, "{} {}", addr, protocol)?;
+                for port in ports {
+                    write!(f, " {}", port)?;
+                }
+                Ok(())
+            }
+            DnsRecord::HINFO { cpu, os, .. } => {
+                write_quoted(f, cpu.as_bytes())?;
+                write!(f, " ")?;
+                write_quoted(f, os.as_bytes())
+            }
+            DnsRecord::MX { priority, host, .. } => write!(f, "{} {}", priority, absolute_name(host)),
+            DnsRecord::TXT { strings, .. } | DnsRecord::SPF { strings, .. } => {
+                for (i, string) in strings.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write_quoted(f, string)?;
+                }
+                Ok(())
+            }
+            DnsRecord::RP { mbox, txt, .. } => write!(f, "{} {}", absolute_name(mbox), absolute_name(txt)),
+            DnsRecord::AFSDB { subtype, host, .. } => write!(f, "{} {}", subtype, absolute_name(host)),
+            DnsRecord::LOC { latitude, longitude, .. } => {
+                let (_, _, altitude) = self.loc_position().unwrap_or_default();
+                let (size, horiz_pre, vert_pre) = self.loc_precision().unwrap_or_default();
+                write!(
+                    f,
+                    "{} {} {:.2}m {:.2}m {:.2}m {:.2}m",
+                    loc_angle(*latitude, 'N', 'S'),
+                    loc_angle(*longitude, 'E', 'W'),
+                    altitude,
+                    size,
+                    horiz_pre,
+                    vert_pre
+                )
+            }
+            DnsRecord::SRV { priority, weight, port, host, .. } => {
+                write!(f, "{} {} {} {}", priority, weight, port, absolute_name(host))
+            }
+            DnsRecord::DS { key_tag, algorithm, digest_type, digest, .. } => {
+                write!(f, "{} {} {} {}", key_tag, algorithm, digest_type, hex_encode(digest))
+            }
+            DnsRecord::IPSECKEY { precedence, algorithm, gateway, public_key, .. } => {
+                write!(f, "{} {} {} ", precedence, gateway.type_num(), algorithm)?;
+                match gateway {
+                    IpsecGateway::None => write!(f, ".")?,
+                    IpsecGateway::V4(addr) => write!(f, "{}", addr)?,
+                    IpsecGateway::V6(addr) => write!(f, "{}", addr)?,
+                    IpsecGateway::Name(name) => write!(f, "{}", absolute_name(name))?,
+                }
+                write!(f, " {}", base64_encode(public_key))
+            }
+            DnsRecord::RRSIG {
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                expiration,
+                inception,
+                key_tag,
+                signer_name,
+                signature,
+                ..
+            } => write!(
+                f,
+                "{} {} {} {} {} {} {} {} {}",
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                expiration,
+                inception,
+                key_tag,
+                absolute_name(signer_name),
+                base64_encode(signature)
+            ),
+            DnsRecord::NSEC { next_domain, types, .. } => {
+                write!(f, "{}", absolute_name(next_domain))?;
+                for qtype in types {
+                    write!(f, " {}", qtype)?;
+                }
+                Ok(())
+            }
+            DnsRecord::DNSKEY { flags, protocol, algorithm, public_key, .. } => {
+                write!(f, "{} {} {} {}", flags, protocol, algorithm, base64_encode(public_key))
+            }
+            DnsRecord::NSEC3 { hash_algorithm, flags, iterations, salt, next_hashed, types, .. } => {
+                write!(f, "{} {} {} {} {}", hash_algorithm, flags, iterations, salt_text(salt), base32hex_encode(next_hashed))?;
+                for qtype in types {
+                    write!(f, " {}", qtype)?;
+                }
+                Ok(())
+            }
+            DnsRecord::NSEC3PARAM { hash_algorithm, flags, iterations, salt, .. } => {
+                write!(f, "{} {} {} {}", hash_algorithm, flags, iterations, salt_text(salt))
+            }
+            DnsRecord::SMIMEA { usage, selector, matching_type, data, .. } => {
+                write!(f, "{} {} {} {}", usage, selector, matching_type, hex_encode(data))
+            }
+            DnsRecord::OPENPGPKEY { public_key, .. } => write!(f, "{}", base64_encode(public_key)),
+            DnsRecord::URI { priority, weight, target, .. } => {
+                write!(f, "{} {} ", priority, weight)?;
+                write_quoted(f, target.as_bytes())
+            }
+            DnsRecord::CAA { flags, tag, value, .. } => {
+                write!(f, "{} {} ", flags, tag)?;
+                write_quoted(f, value)
+            }
+            DnsRecord::TLSA { usage, selector, matching_type, cert_data, .. } => {
+                write!(f, "{} {} {} {}", usage, selector, matching_type, hex_encode(cert_data))
+            }
+            DnsRecord::NAPTR { order, preference, flags, services, regexp, replacement, .. } => {
+                write!(f, "{} {} ", order, preference)?;
+                for string in [flags, services, regexp] {
+                    write_quoted(f, string.as_bytes())?;
+                    write!(f, " ")?;
+                }
+                write!(f, "{}", absolute_name(replacement))
+            }
+            DnsRecord::DNAME { target, .. } => write!(f, "{}", absolute_name(target)),
+            DnsRecord::OPT { .. } => Ok(()),
+        }
+    }
+}
+
+impl DnsQuestion {
+    pub fn new(name: Name, qtype: QueryType) -> DnsQuestion {
+        DnsQuestion {
+            name,
+            qtype,
+            class: DnsClass::IN,
+        }
+    }
+
+    /// Builds the PTR question for a reverse lookup of an IPv4 address,
+    /// e.g. 1.2.3.4 becomes 4.3.2.1.in-addr.arpa
+    pub fn reverse_from_ipv4(addr: Ipv4Addr) -> DnsQuestion {
+        let [a, b, c, d] = addr.octets();
+        let name = format!("{}.{}.{}.{}.in-addr.arpa", d, c, b, a);
+
+        DnsQuestion::new(Name::from(name.as_str()), QueryType::PTR)
+    }
+
+    /// Builds the PTR question for any address. IPv6 addresses are written
+    /// as reversed nibbles under ip6.arpa.
+    pub fn reverse(addr: IpAddr) -> DnsQuestion {
+        match addr {
+            IpAddr::V4(addr) => DnsQuestion::reverse_from_ipv4(addr),
+            IpAddr::V6(addr) => {
+                let mut name = String::new();
+                for octet in addr.octets().iter().rev() {
+                    name.push_str(&format!("{:x}.{:x}.", octet & 0x0F, octet >> 4));
+                }
+                name.push_str("ip6.arpa");
+
+                DnsQuestion::new(Name::from(name.as_str()), QueryType::PTR)
+            }
+        }
+    }
+
+    pub fn read<T: PacketBuffer>(&mut self, buffer: &mut T) -> Result<(), DnsError> {
+        self.name = buffer.read_name()?;
+        self.qtype = QueryType::from_num(buffer.read_u16()?); // qtype
+        self.class = DnsClass::from_num(buffer.read_u16()?); // class
+        Ok(())
+    }
+
+    pub fn write<T: PacketBuffer>(&self, buffer: &mut T) -> Result<(), DnsError> {
+        buffer.write_name(&self.name)?;
+
+        let typenum = self.qtype.to_num();
+        buffer.write_u16(typenum)?;
+        buffer.write_u16(self.class.to_num())?;
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for DnsQuestion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, ";{}\t\t{}\t{}", absolute_name(&self.name.to_string()), self.class, self.qtype)
+    }
+}
+
+impl QueryType {
+    pub fn to_num(&self) -> u16 {
+        match *self {
+            QueryType::UNKNOWN(x) => x,
+            QueryType::A => 1,
+            QueryType::NS => 2,
+            QueryType::CNAME => 5,
+            QueryType::SOA => 6,
+            QueryType::NULL => 10,
+            QueryType::WKS => 11,
+            QueryType::PTR => 12,
+            QueryType::HINFO => 13,
+            QueryType::MX => 15,
+            QueryType::TXT => 16,
+            QueryType::RP => 17,
+            QueryType::AFSDB => 18,
+            QueryType::AAAA => 28,
+            QueryType::LOC => 29,
+            QueryType::SRV => 33,
+            QueryType::NAPTR => 35,
+            QueryType::DNAME => 39,
+            QueryType::OPT => 41,
+            QueryType::DS => 43,
+            QueryType::IPSECKEY => 45,
+            QueryType::RRSIG => 46,
+            QueryType::NSEC => 47,
+            QueryType::DNSKEY => 48,
+            QueryType::NSEC3 => 50,
+            QueryType::NSEC3PARAM => 51,
+            QueryType::TLSA => 52,
+            QueryType::SMIMEA => 53,
+            QueryType::OPENPGPKEY => 61,
+            QueryType::SPF => 99,
+            QueryType::ANY => 255,
+            QueryType::URI => 256,
+            QueryType::CAA => 257,
+        }
+    }
+
+    pub fn from_num(num: u16) -> QueryType {
+        match num {
+            1 => QueryType::A,
+            2 => QueryType::NS,
+            5 => QueryType::CNAME,
+            6 => QueryType::SOA,
+            10 => QueryType::NULL,
+            11 => QueryType::WKS,
+            12 => QueryType::PTR,
+            13 => QueryType::HINFO,
+            15 => QueryType::MX,
+            16 => QueryType::TXT,
+            17 => QueryType::RP,
+            18 => QueryType::AFSDB,
+            28 => QueryType::AAAA,
+            29 => QueryType::LOC,
+            33 => QueryType::SRV,
+            35 => QueryType::NAPTR,
+            39 => QueryType::DNAME,
+            41 => QueryType::OPT,
+            43 => QueryType::DS,
+            45 => QueryType::IPSECKEY,
+            46 => QueryType::RRSIG,
+            47 => QueryType::NSEC,
+            48 => QueryType::DNSKEY,
+            50 => QueryType::NSEC3,
+            51 => QueryType::NSEC3PARAM,
+            52 => QueryType::TLSA,
+            53 => QueryType::SMIMEA,
+            61 => QueryType::OPENPGPKEY,
+            99 => QueryType::SPF,
+            255 => QueryType::ANY,
+            256 => QueryType::URI,
+            257 => QueryType::CAA,
             _ => QueryType::UNKNOWN(num),
         }
     }
-}
\ No newline at end of file
+}
+
+/// The mnemonic of the type as used in zone files, or the generic `TYPE123`
+/// form of RFC 3597 for types we don't know.
+/// The mnemonics of zone files, with classes that have none written in the
+/// generic form of RFC 3597
+impl fmt::Display for DnsClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DnsClass::UNKNOWN(x) => write!(f, "CLASS{}", x),
+            DnsClass::IN => write!(f, "IN"),
+            DnsClass::CH => write!(f, "CH"),
+            DnsClass::HS => write!(f, "HS"),
+            DnsClass::NONE => write!(f, "NONE"),
+            DnsClass::ANY => write!(f, "ANY"),
+        }
+    }
+}
+
+impl fmt::Display for QueryType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            QueryType::UNKNOWN(x) => write!(f, "TYPE{}", x),
+            QueryType::A => write!(f, "A"),
+            QueryType::NS => write!(f, "NS"),
+            QueryType::CNAME => write!(f, "CNAME"),
+            QueryType::SOA => write!(f, "SOA"),
+            QueryType::NULL => write!(f, "NULL"),
+            QueryType::WKS => write!(f, "WKS"),
+            QueryType::PTR => write!(f, "PTR"),
+            QueryType::HINFO => write!(f, "HINFO"),
+            QueryType::MX => write!(f, "MX"),
+            QueryType::TXT => write!(f, "TXT"),
+            QueryType::RP => write!(f, "RP"),
+            QueryType::AFSDB => write!(f, "AFSDB"),
+            QueryType::AAAA => write!(f, "AAAA"),
+            QueryType::LOC => write!(f, "LOC"),
+            QueryType::SRV => write!(f, "SRV"),
+            QueryType::NAPTR => write!(f, "NAPTR"),
+            QueryType::DNAME => write!(f, "DNAME"),
+            QueryType::OPT => write!(f, "OPT"),
+            QueryType::DS => write!(f, "DS"),
+            QueryType::IPSECKEY => write!(f, "IPSECKEY"),
+            QueryType::RRSIG => write!(f, "RRSIG"),
+            QueryType::NSEC => write!(f, "NSEC"),
+            QueryType::DNSKEY => write!(f, "DNSKEY"),
+            QueryType::NSEC3 => write!(f, "NSEC3"),
+            QueryType::NSEC3PARAM => write!(f, "NSEC3PARAM"),
+            QueryType::TLSA => write!(f, "TLSA"),
+            QueryType::SMIMEA => write!(f, "SMIMEA"),
+            QueryType::OPENPGPKEY => write!(f, "OPENPGPKEY"),
+            QueryType::SPF => write!(f, "SPF"),
+            QueryType::ANY => write!(f, "ANY"),
+            QueryType::URI => write!(f, "URI"),
+            QueryType::CAA => write!(f, "CAA"),
+        }
+    }
+}
+
+/// Parses both mnemonics and the generic `TYPE123` form, ignoring case.
+impl FromStr for QueryType {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<QueryType, DnsError> {
+        let upper = s.to_uppercase();
+        if let Some(num) = upper.strip_prefix("TYPE") {
+            if let Ok(num) = num.parse::<u16>() {
+                return Ok(QueryType::from_num(num));
+            }
+        }
+
+        match upper.as_str() {
+            "A" => Ok(QueryType::A),
+            "NS" => Ok(QueryType::NS),
+            "CNAME" => Ok(QueryType::CNAME),
+            "SOA" => Ok(QueryType::SOA),
+            "NULL" => Ok(QueryType::NULL),
+            "WKS" => Ok(QueryType::WKS),
+            "PTR" => Ok(QueryType::PTR),
+            "HINFO" => Ok(QueryType::HINFO),
+            "MX" => Ok(QueryType::MX),
+            "TXT" => Ok(QueryType::TXT),
+            "RP" => Ok(QueryType::RP),
+            "AFSDB" => Ok(QueryType::AFSDB),
+            "AAAA" => Ok(QueryType::AAAA),
+            "LOC" => Ok(QueryType::LOC),
+            "SRV" => Ok(QueryType::SRV),
+            "NAPTR" => Ok(QueryType::NAPTR),
+            "DNAME" => Ok(QueryType::DNAME),
+            "OPT" => Ok(QueryType::OPT),
+            "DS" => Ok(QueryType::DS),
+            "IPSECKEY" => Ok(QueryType::IPSECKEY),
+            "RRSIG" => Ok(QueryType::RRSIG),
+            "NSEC" => Ok(QueryType::NSEC),
+            "DNSKEY" => Ok(QueryType::DNSKEY),
+            "NSEC3" => Ok(QueryType::NSEC3),
+            "NSEC3PARAM" => Ok(QueryType::NSEC3PARAM),
+            "TLSA" => Ok(QueryType::TLSA),
+            "SMIMEA" => Ok(QueryType::SMIMEA),
+            "OPENPGPKEY" => Ok(QueryType::OPENPGPKEY),
+            "SPF" => Ok(QueryType::SPF),
+            "ANY" => Ok(QueryType::ANY),
+            "URI" => Ok(QueryType::URI),
+            "CAA" => Ok(QueryType::CAA),
+            _ => Err(DnsError::UnknownQueryType(s.to_string())),
+        }
+    }
+}
+
+impl DnsClass {
+    pub fn to_num(&self) -> u16 {
+        match *self {
+            DnsClass::UNKNOWN(x) => x,
+            DnsClass::IN => 1,
+            DnsClass::CH => 3,
+            DnsClass::HS => 4,
+            DnsClass::NONE => 254,
+            DnsClass::ANY => 255,
+        }
+    }
+
+    pub fn from_num(num: u16) -> DnsClass {
+        match num {
+            1 => DnsClass::IN,
+            3 => DnsClass::CH,
+            4 => DnsClass::HS,
+            254 => DnsClass::NONE,
+            255 => DnsClass::ANY,
+            _ => DnsClass::UNKNOWN(num),
+        }
+    }
+}
+
+/// Read a single length-prefixed character string (RFC 1035 section 3.3)
+/// without running past the end of the rdata.
+fn read_character_string<T: PacketBuffer>(buffer: &mut T, data_end: usize) -> Result<String, DnsError> {
+    let len = buffer.read_u8()? as usize;
+    if buffer.pos() + len > data_end {
+        return Err(DnsError::InvalidRecord("Character string exceeds record data length".to_string()));
+    }
+
+    let text = String::from_utf8_lossy(buffer.read_bytes(len)?).to_string();
+
+    Ok(text)
+}
+
+fn write_character_string<T: PacketBuffer>(buffer: &mut T, text: &str) -> Result<(), DnsError> {
+    if text.len() > 255 {
+        return Err(DnsError::InvalidRecord("Character string exceeds 255 bytes".to_string()));
+    }
+
+    buffer.write_u8(text.len() as u8)?;
+    buffer.write_bytes(text.as_bytes())?;
+
+    Ok(())
+}
+
+/// Read the rdata of TXT and SPF records, a sequence of character strings
+/// which are kept apart and byte for byte as they were sent.
+fn read_strings<T: PacketBuffer>(buffer: &mut T, data_end: usize) -> Result<Vec<Vec<u8>>, DnsError> {
+    let mut strings = Vec::new();
+    while buffer.pos() < data_end {
+        let len = buffer.read_u8()? as usize;
+        if buffer.pos() + len > data_end {
+            return Err(DnsError::InvalidRecord("Character string exceeds record data length".to_string()));
+        }
+
+        strings.push(buffer.read_bytes(len)?.to_vec());
+    }
+
+    Ok(strings)
+}
+
+/// The length of rdata made up of a fixed part and a variable one, which
+/// has to fit in the 16 bit length field
+fn rdata_len(fixed: usize, variable: usize) -> Result<u16, DnsError> {
+    fixed
+        .checked_add(variable)
+        .and_then(|len| u16::try_from(len).ok())
+        .ok_or_else(|| DnsError::InvalidRecord(format!("Record data of {} bytes is too long", variable)))
+}
+
+/// Write bytes after their length in a single byte, such as the salt of
+/// NSEC3 and NSEC3PARAM records
+fn write_u8_prefixed<T: PacketBuffer>(buffer: &mut T, bytes: &[u8], field: &str) -> Result<(), DnsError> {
+    let len = u8::try_from(bytes.len())
+        .map_err(|_| DnsError::InvalidRecord(format!("{} of {} bytes exceeds 255 bytes", field, bytes.len())))?;
+
+    buffer.write_u8(len)?;
+    buffer.write_bytes(bytes)?;
+
+    Ok(())
+}
+
+/// Write the character strings of TXT and SPF rdata, each with its own
+/// length. The rdata can't be empty, so no strings at all are written as a
+/// single empty one.
+fn write_strings<T: PacketBuffer>(buffer: &mut T, strings: &[Vec<u8>]) -> Result<(), DnsError> {
+    if strings.is_empty() {
+        buffer.write_u8(0)?;
+    }
+    for string in strings {
+        if string.len() > 255 {
+            return Err(DnsError::InvalidRecord("Character string exceeds 255 bytes".to_string()));
+        }
+
+        buffer.write_u8(string.len() as u8)?;
+        buffer.write_bytes(string)?;
+    }
+
+    Ok(())
+}
+
+/// Standard base64 with padding (RFC 4648)
+fn base64_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | (b[2] as u32);
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[((n >> (18 - 6 * i)) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+
+    out
+}
+
+/// Decoding of standard base64, the counterpart of `base64_encode`. Returns
+/// `None` for input which isn't valid base64.
+#[cfg(any(test, feature = "serde"))]
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let text = text.as_bytes();
+    if !text.len().is_multiple_of(4) {
+        return None;
+    }
+
+    let mut out = Vec::with_capacity(text.len() / 4 * 3);
+    for chunk in text.chunks(4) {
+        let padding = chunk.iter().rev().take_while(|&&c| c == b'=').count();
+        if padding > 2 {
+            return None;
+        }
+
+        let mut n: u32 = 0;
+        for &c in &chunk[..4 - padding] {
+            let value = match c {
+                b'A'..=b'Z' => c - b'A',
+                b'a'..=b'z' => c - b'a' + 26,
+                b'0'..=b'9' => c - b'0' + 52,
+                b'+' => 62,
+                b'/' => 63,
+                _ => return None,
+            };
+            n = (n << 6) | value as u32;
+        }
+        n <<= 6 * padding;
+
+        out.extend_from_slice(&n.to_be_bytes()[1..4 - padding]);
+    }
+
+    // Padding is only allowed at the very end
+    if text[..text.len().saturating_sub(4)].contains(&b'=') {
+        return None;
+    }
+
+    Some(out)
+}
+
+/// Binary rdata is serialized as a base64 string, which keeps JSON output
+/// compact and readable compared to an array of numbers.
+#[cfg(feature = "serde")]
+mod base64_serde {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&super::base64_encode(data))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        super::base64_decode(&text).ok_or_else(|| D::Error::custom("invalid base64"))
+    }
+}
+
+/// The character strings of TXT and SPF records as a list of base64
+/// strings, since nothing says they have to be valid UTF-8.
+#[cfg(feature = "serde")]
+mod base64_list_serde {
+    use serde::{de::Error, ser::SerializeSeq, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(strings: &[Vec<u8>], serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(strings.len()))?;
+        for string in strings {
+            seq.serialize_element(&super::base64_encode(string))?;
+        }
+        seq.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Vec<u8>>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|text| super::base64_decode(text).ok_or_else(|| D::Error::custom("invalid base64")))
+            .collect()
+    }
+}
+
+/// A character string in quotes as it's written in zone files, escaping
+/// quotes and backslashes, and anything unprintable as a decimal `\DDD`
+fn write_quoted(f: &mut fmt::Formatter, string: &[u8]) -> fmt::Result {
+    write!(f, "\"")?;
+    for &b in string {
+        match b {
+            b'"' | b'\\' => write!(f, "\\{}", b as char)?,
+            0x20..=0x7e => write!(f, "{}", b as char)?,
+            _ => write!(f, "\\{:03}", b)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+/// A name as it's written in zone files, with the trailing dot of the root
+fn absolute_name(name: &str) -> String {
+    format!("{}.", name.trim_end_matches('.'))
+}
+
+/// Uppercase hex without separators, as used for digests in zone files
+fn hex_encode(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+/// The salt of NSEC3 records in hex, or a dash when there is none
+fn salt_text(salt: &[u8]) -> String {
+    if salt.is_empty() {
+        "-".to_string()
+    } else {
+        hex_encode(salt)
+    }
+}
+
+/// Base 32 with the extended hex alphabet and no padding (RFC 4648 section
+/// 7), which NSEC3 uses for hashed owner names since it preserves ordering.
+fn base32hex_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUV";
+
+    let mut out = String::with_capacity((data.len() * 8).div_ceil(5));
+    let mut bits: u16 = 0;
+    let mut len = 0;
+    for b in data {
+        bits = (bits << 8) | *b as u16;
+        len += 8;
+        while len >= 5 {
+            len -= 5;
+            out.push(ALPHABET[((bits >> len) & 0x1F) as usize] as char);
+        }
+    }
+    if len > 0 {
+        out.push(ALPHABET[((bits << (5 - len)) & 0x1F) as usize] as char);
+    }
+
+    out
+}
+
+/// A LOC latitude or longitude in degrees, minutes and seconds, picking the
+/// hemisphere from the sign of the offset from 2^31.
+fn loc_angle(raw: u32, positive: char, negative: char) -> String {
+    let offset = raw as i64 - (1 << 31);
+    let hemisphere = if offset < 0 { negative } else { positive };
+
+    let thousandths = offset.abs();
+    let degrees = thousandths / 3_600_000;
+    let minutes = thousandths / 60_000 % 60;
+    let seconds = thousandths % 60_000;
+
+    format!("{} {} {}.{:03} {}", degrees, minutes, seconds / 1000, seconds % 1000, hemisphere)
+}
+
+/// Decode the type bitmap of NSEC and NSEC3 records (RFC 4034 section 4.1.2),
+/// which runs until the end of the rdata. Types are grouped in windows of 256,
+/// each carrying a bitmap of up to 32 bytes.
+fn read_type_bitmap<T: PacketBuffer>(buffer: &mut T, data_end: usize) -> Result<Vec<QueryType>, DnsError> {
+    let mut types = Vec::new();
+
+    while buffer.pos() < data_end {
+        let window = buffer.read_u8()? as u16;
+        let len = buffer.read_u8()? as usize;
+        if len == 0 || len > 32 || buffer.pos() + len > data_end {
+            return Err(DnsError::InvalidRecord(format!("Invalid type bitmap length {}", len)));
+        }
+
+        for i in 0..len {
+            let byte = buffer.read_u8()?;
+            for bit in 0..8 {
+                if byte & (0x80 >> bit) > 0 {
+                    types.push(QueryType::from_num((window << 8) | (i as u16 * 8 + bit)));
+                }
+            }
+        }
+    }
+
+    Ok(types)
+}
+
+/// Encode a type bitmap, leaving out empty windows and trailing zero bytes.
+fn write_type_bitmap<T: PacketBuffer>(buffer: &mut T, types: &[QueryType]) -> Result<(), DnsError> {
+    let mut nums: Vec<u16> = types.iter().map(|qtype| qtype.to_num()).collect();
+    nums.sort_unstable();
+    nums.dedup();
+
+    let mut i = 0;
+    while i < nums.len() {
+        let window = nums[i] >> 8;
+        let mut bitmap = [0u8; 32];
+        let mut len = 0;
+
+        while i < nums.len() && nums[i] >> 8 == window {
+            let low = (nums[i] & 0xFF) as usize;
+            bitmap[low / 8] |= 0x80 >> (low % 8);
+            len = low / 8 + 1;
+            i += 1;
+        }
+
+        buffer.write_u8(window as u8)?;
+        buffer.write_u8(len as u8)?;
+        buffer.write_bytes(&bitmap[..len])?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packets::VectorPacketBuffer;
+
+    /// Write a record to an empty buffer and read it back
+    fn round_trip(record: &DnsRecord) -> DnsRecord {
+        let mut buffer = VectorPacketBuffer::new();
+        record.write(&mut buffer).unwrap();
+        buffer.seek(0).unwrap();
+        DnsRecord::read(&mut buffer).unwrap()
+    }
+
+    #[test]
+    fn aaaa_round_trip() {
+        let record = DnsRecord::AAAA {
+            domain: "google.com".into(),
+            addr: "2a00:1450:4001:82a::200e".parse().unwrap(),
+            class: DnsClass::IN,
+            ttl: 300,
+        };
+
+        assert_eq!(round_trip(&record), record);
+    }
+
+    #[test]
+    fn cname_round_trip() {
+        let record = DnsRecord::CNAME {
+            domain: "www.example.com".into(),
+            host: "example.com".to_string(),
+            class: DnsClass::IN,
+            ttl: 3600,
+        };
+
+        assert_eq!(round_trip(&record), record);
+    }
+
+    #[test]
+    fn txt_chunks_stay_within_rdata() {
+        let txt = DnsRecord::TXT {
+            domain: "example.com".into(),
+            strings: DnsRecord::text_strings(&"x".repeat(300)),
+            class: DnsClass::IN,
+            ttl: 60,
+        };
+        let a = DnsRecord::A {
+            domain: "example.com".into(),
+            addr: Ipv4Addr::new(93, 184, 216, 34),
+            class: DnsClass::IN,
+            ttl: 60,
+        };
+
+        let mut buffer = VectorPacketBuffer::new();
+        txt.write(&mut buffer).unwrap();
+        a.write(&mut buffer).unwrap();
+        buffer.seek(0).unwrap();
+
+        assert_eq!(DnsRecord::read(&mut buffer).unwrap(), txt);
+        assert_eq!(DnsRecord::read(&mut buffer).unwrap(), a);
+    }
+
+    #[test]
+    fn srv_round_trip() {
+        let record = DnsRecord::SRV {
+            domain: "_sip._tcp.example.com".into(),
+            priority: 10,
+            weight: 60,
+            port: 5060,
+            host: "bigbox.example.com".to_string(),
+            class: DnsClass::IN,
+            ttl: 86400,
+        };
+
+        assert_eq!(round_trip(&record), record);
+    }
+
+    #[test]
+    fn ptr_round_trip() {
+        let record = DnsRecord::PTR {
+            domain: "4.3.2.1.in-addr.arpa".into(),
+            host: "host.example.com".to_string(),
+            class: DnsClass::IN,
+            ttl: 3600,
+        };
+
+        assert_eq!(round_trip(&record), record);
+    }
+
+    #[test]
+    fn reverse_name_of_ipv4_address() {
+        let question = DnsQuestion::reverse_from_ipv4(Ipv4Addr::new(1, 2, 3, 4));
+        assert_eq!(question.name, "4.3.2.1.in-addr.arpa");
+        assert_eq!(question.qtype, QueryType::PTR);
+    }
+
+    #[test]
+    fn caa_tags_round_trip() {
+        let caa = |flags: u8, tag: &str, value: &str| DnsRecord::CAA {
+            domain: "example.com".into(),
+            flags,
+            tag: tag.to_string(),
+            value: value.as_bytes().to_vec(),
+            class: DnsClass::IN,
+            ttl: 3600,
+        };
+
+        for record in [
+            caa(0, "issue", "letsencrypt.org"),
+            caa(0, "issuewild", ";"),
+            caa(0, "iodef", "mailto:security@example.com"),
+        ] {
+            assert_eq!(round_trip(&record), record);
+            assert!(!record.is_critical());
+        }
+
+        let critical = caa(128, "tbs", "unknown");
+        assert_eq!(round_trip(&critical), critical);
+        assert!(critical.is_critical());
+    }
+
+    #[test]
+    fn key_tag_of_root_ksk() {
+        // KSK-2017 of the root zone, as published by IANA
+        let public_key = base64_decode(
+            "AwEAAaz/tAm8yTn4Mfeh5eyI96WSVexTBAvkMgJzkKTOiW1vkIbzxeF3+/4RgWOq7HrxRixHlFlExOLAJr5emLvN7SWXgnLh4+B5xQlN\
+            Vz8Og8kvArMtNROxVQuCaSnIDdD5LKyWbRd2n9WGe2R8PzgCmr3EgVLrjyBxWezF0jLHwVN8efS3rCj/EWgvIWgb9tarpVUDK/b58Da+\
+            sqqls3eNbuv7pr+eoZG+SrDK6nWeL3c6H5Apxz7LjVc1uTIdsIXxuOLYA4/ilBmSVIzuDWfdRUfhHdY6+cn8HFRm+2hM8AnXGXws9555\
+            KrUB5qihylGa8subX2Nn6UwNR1AkUTV74bU=",
+        )
+        .unwrap();
+        let record = DnsRecord::DNSKEY {
+            domain: "".into(),
+            flags: 257,
+            protocol: 3,
+            algorithm: 8,
+            public_key,
+            class: DnsClass::IN,
+            ttl: 172800,
+        };
+
+        assert_eq!(record.key_tag(), Some(20326));
+        assert_eq!(round_trip(&record), record);
+    }
+
+    #[test]
+    fn rdata_longer_than_length_field_is_an_error() {
+        let record = DnsRecord::DNSKEY {
+            domain: "example.com".into(),
+            flags: 256,
+            protocol: 3,
+            algorithm: 8,
+            public_key: vec![0; 65532],
+            class: DnsClass::IN,
+            ttl: 3600,
+        };
+
+        let mut buffer = VectorPacketBuffer::new();
+        assert!(matches!(record.write(&mut buffer), Err(DnsError::InvalidRecord(_))));
+    }
+
+    #[test]
+    fn ds_round_trip() {
+        let record = DnsRecord::DS {
+            domain: "com".into(),
+            key_tag: 19718,
+            algorithm: 13,
+            digest_type: 2,
+            digest: vec![0x8a; 32],
+            class: DnsClass::IN,
+            ttl: 86400,
+        };
+
+        assert_eq!(round_trip(&record), record);
+    }
+
+    #[test]
+    fn rrsig_reserializes_byte_for_byte() {
+        // The RRSIG of example.com A as dig +dnssec shows it, with the signer
+        // name in the case it was sent in
+        let mut buffer = VectorPacketBuffer::from_hex(
+            "0765 7861 6d70 6c65 0363 6f6d 0000 2e00
+            0100 0001 2c00 5f00 010d 0200 0001 2c65
+            9200 8065 6922 0009 4307 4578 616d 706c
+            6503 636f 6d00 7ae3 dc51 1b3f 58d9 4299
+            9c1f a32a cb30 974e f44f 057b ad22 15e6
+            38a0 b5bc d86d fef8 e8ce adbf 7352 d516
+            e702 9983 37f7 232d 24db 165e 26fd e3c2
+            d00a 9f87 fa57",
+        )
+        .unwrap();
+        let record = DnsRecord::read(&mut buffer).unwrap();
+
+        match &record {
+            DnsRecord::RRSIG { type_covered, key_tag, signer_name, signature, .. } => {
+                assert_eq!(*type_covered, QueryType::A);
+                assert_eq!(*key_tag, 2371);
+                assert_eq!(signer_name, "Example.com");
+                assert_eq!(signature.len(), 64);
+            }
+            _ => panic!("not an RRSIG record: {:?}", record),
+        }
+
+        let mut written = VectorPacketBuffer::new();
+        let len = record.write(&mut written).unwrap();
+        assert_eq!(written.get_range(0, len).unwrap(), buffer.get_range(0, buffer.buf.len()).unwrap());
+
+        // A signer name pointing back at the owner is refused, not followed
+        let mut buffer = VectorPacketBuffer::new();
+        buffer.write_qname("example.com").unwrap();
+        for field in [QueryType::RRSIG.to_num(), 1, 0, 300, 20, 1, 0x0d02, 0, 300] {
+            buffer.write_u16(field).unwrap();
+        }
+        buffer.write_bytes(&[0; 8]).unwrap();
+        buffer.write_u16(2371).unwrap();
+        buffer.write_bytes(b"\xc0\x00").unwrap();
+        buffer.seek(0).unwrap();
+        assert!(matches!(DnsRecord::read(&mut buffer), Err(DnsError::InvalidPointer(0))));
+    }
+
+    #[test]
+    fn nsec_bitmap_spans_windows() {
+        let record = DnsRecord::NSEC {
+            domain: "alfa.example.com".into(),
+            next_domain: "host.example.com".into(),
+            types: vec![QueryType::A, QueryType::MX, QueryType::RRSIG, QueryType::NSEC, QueryType::CAA],
+            class: DnsClass::IN,
+            ttl: 3600,
+        };
+
+        let mut buffer = VectorPacketBuffer::new();
+        let len = record.write(&mut buffer).unwrap();
+
+        // Window 0 holds A, MX, RRSIG and NSEC, window 1 holds CAA (257)
+        let bitmap = [0, 6, 0x40, 0x01, 0, 0, 0, 0x03, 1, 1, 0x40];
+        assert_eq!(buffer.get_range(len - bitmap.len(), bitmap.len()).unwrap(), &bitmap);
+
+        buffer.seek(0).unwrap();
+        assert_eq!(DnsRecord::read(&mut buffer).unwrap(), record);
+    }
+
+    #[test]
+    fn nsec_covers_and_matches() {
+        let record = DnsRecord::NSEC {
+            domain: "alfa.example.com".into(),
+            next_domain: "host.example.com".into(),
+            types: vec![QueryType::A],
+            class: DnsClass::IN,
+            ttl: 3600,
+        };
+        assert!(record.matches("alfa.example.com"));
+        assert!(!record.matches("b.example.com"));
+        assert!(record.covers("b.example.com"));
+        assert!(record.covers("x.alfa.example.com"));
+        assert!(!record.covers("z.example.com"));
+
+        // The last NSEC of the zone wraps around to the apex
+        let last = DnsRecord::NSEC {
+            domain: "z.example.com".into(),
+            next_domain: "example.com".into(),
+            types: vec![],
+            class: DnsClass::IN,
+            ttl: 3600,
+        };
+        assert!(last.covers("zz.example.com"));
+        assert!(!last.covers("a.example.com"));
+    }
+
+    #[test]
+    fn nsec3_and_nsec3param_round_trip() {
+        let nsec3 = DnsRecord::NSEC3 {
+            domain: "2t7b4g4vsa5smi47k61mv5bv1a22bojr.example.com".into(),
+            hash_algorithm: 1,
+            flags: 1,
+            iterations: 12,
+            salt: vec![0xaa, 0xbb, 0xcc, 0xdd],
+            next_hashed: vec![0x11; 20],
+            types: vec![QueryType::A, QueryType::RRSIG],
+            class: DnsClass::IN,
+            ttl: 3600,
+        };
+        let param = DnsRecord::NSEC3PARAM {
+            domain: "example.com".into(),
+            hash_algorithm: 1,
+            flags: 0,
+            iterations: 12,
+            salt: vec![],
+            class: DnsClass::IN,
+            ttl: 0,
+        };
+
+        assert_eq!(round_trip(&nsec3), nsec3);
+        assert_eq!(round_trip(&param), param);
+
+        // The salt length is a single byte, so 256 bytes can't be written
+        let long_salt = DnsRecord::NSEC3PARAM {
+            domain: "example.com".into(),
+            hash_algorithm: 1,
+            flags: 0,
+            iterations: 12,
+            salt: vec![0xaa; 256],
+            class: DnsClass::IN,
+            ttl: 0,
+        };
+        let mut buffer = VectorPacketBuffer::with_capacity(1024);
+        assert!(matches!(long_salt.write(&mut buffer), Err(DnsError::InvalidRecord(_))));
+
+        let long_salt = DnsRecord::NSEC3 {
+            domain: "2t7b4g4vsa5smi47k61mv5bv1a22bojr.example.com".into(),
+            hash_algorithm: 1,
+            flags: 1,
+            iterations: 12,
+            salt: vec![0xaa; 256],
+            next_hashed: vec![0x11; 20],
+            types: vec![QueryType::A],
+            class: DnsClass::IN,
+            ttl: 3600,
+        };
+        let mut buffer = VectorPacketBuffer::with_capacity(1024);
+        assert!(matches!(long_salt.write(&mut buffer), Err(DnsError::InvalidRecord(_))));
+    }
+
+    #[test]
+    fn chaos_question_keeps_its_class() {
+        let mut question = DnsQuestion::new(Name::from("version.bind"), QueryType::TXT);
+        question.class = DnsClass::CH;
+
+        let mut buffer = VectorPacketBuffer::new();
+        question.write(&mut buffer).unwrap();
+        let end = buffer.pos();
+        assert_eq!(buffer.get_range(end - 4, 4).unwrap(), &[0, 16, 0, 3]);
+
+        buffer.seek(0).unwrap();
+        let mut read = DnsQuestion::new(Name::root(), QueryType::UNKNOWN(0));
+        read.read(&mut buffer).unwrap();
+        assert_eq!(read, question);
+    }
+
+    #[test]
+    fn loc_reserializes_byte_for_byte() {
+        // The example of RFC 1876 section 4:
+        // cambridge-net.kei.com. LOC 42 21 54 N 71 06 18 W -24m 30m
+        let mut buffer = VectorPacketBuffer::from_hex(
+            "0d63 616d 6272 6964 6765 2d6e 6574 036b
+            6569 0363 6f6d 0000 1d00 0100 000e 1000
+            1000 3316 1389 172d d070 be15 f000 988d
+            20",
+        )
+        .unwrap();
+        let record = DnsRecord::read(&mut buffer).unwrap();
+
+        let (latitude, longitude, altitude) = record.loc_position().unwrap();
+        assert!((latitude - 42.365).abs() < 1e-9);
+        assert!((longitude + 71.105).abs() < 1e-9);
+        assert_eq!(altitude, -24.0);
+        assert_eq!(record.loc_precision(), Some((30.0, 10000.0, 10.0)));
+
+        let mut written = VectorPacketBuffer::new();
+        let len = record.write(&mut written).unwrap();
+        assert_eq!(written.get_range(0, len).unwrap(), buffer.get_range(0, buffer.buf.len()).unwrap());
+    }
+
+    #[test]
+    fn uri_target_isnt_split_into_labels() {
+        let record = DnsRecord::URI {
+            domain: "_http._tcp.example.com".into(),
+            priority: 10,
+            weight: 1,
+            target: "https://downloads.example.com/releases/v1.2.3/dnsrust-1.2.3.x86_64.tar.gz?mirror=eu.west.1"
+                .to_string(),
+            class: DnsClass::IN,
+            ttl: 3600,
+        };
+
+        let mut buffer = VectorPacketBuffer::new();
+        let len = record.write(&mut buffer).unwrap();
+
+        // The target is the rest of the rdata, without length prefixes
+        let target = b"https://downloads.example.com/releases/v1.2.3/dnsrust-1.2.3.x86_64.tar.gz?mirror=eu.west.1";
+        assert_eq!(buffer.get_range(len - target.len(), target.len()).unwrap(), target);
+
+        buffer.seek(0).unwrap();
+        assert_eq!(DnsRecord::read(&mut buffer).unwrap(), record);
+    }
+
+    #[test]
+    fn caa_tag_must_be_short_and_alphanumeric() {
+        let caa = |tag: String| DnsRecord::CAA {
+            domain: "example.com".into(),
+            flags: 0,
+            tag,
+            value: b"letsencrypt.org".to_vec(),
+            class: DnsClass::IN,
+            ttl: 3600,
+        };
+
+        for tag in [String::new(), "x".repeat(16), "x".repeat(256), "issue-wild".to_string()] {
+            let mut buffer = VectorPacketBuffer::new();
+            assert!(matches!(caa(tag).write(&mut buffer), Err(DnsError::InvalidRecord(_))));
+        }
+
+        let longest = caa("x".repeat(15));
+        assert_eq!(round_trip(&longest), longest);
+    }
+
+    #[test]
+    fn ipseckey_gateway_forms() {
+        let gateways = [
+            (IpsecGateway::None, 0),
+            (IpsecGateway::V4(Ipv4Addr::new(192, 0, 2, 38)), 1),
+            (IpsecGateway::V6("2001:db8:0:8002::2000:1".parse().unwrap()), 2),
+            (IpsecGateway::Name("mygateway.example.com".to_string()), 3),
+        ];
+
+        for (gateway, gateway_type) in gateways {
+            let record = DnsRecord::IPSECKEY {
+                domain: "x.example.com".into(),
+                precedence: 10,
+                algorithm: 2,
+                gateway,
+                public_key: vec![0x01, 0x03, 0x51, 0x53, 0x79, 0x86, 0xed, 0x35],
+                class: DnsClass::IN,
+                ttl: 7200,
+            };
+
+            // The gateway type follows the precedence, after the 15 byte
+            // owner name and 10 bytes of type, class, ttl and length
+            let mut buffer = VectorPacketBuffer::new();
+            record.write(&mut buffer).unwrap();
+            assert_eq!(buffer.buf[26], gateway_type);
+
+            assert_eq!(round_trip(&record), record);
+        }
+
+        // A gateway name pointing back at the owner is refused, not followed
+        let mut buffer = VectorPacketBuffer::new();
+        buffer.write_qname("x.example.com").unwrap();
+        for field in [QueryType::IPSECKEY.to_num(), 1, 0, 7200, 5] {
+            buffer.write_u16(field).unwrap();
+        }
+        buffer.write_bytes(b"\x0a\x03\x02\xc0\x02").unwrap();
+        buffer.seek(0).unwrap();
+        assert!(matches!(DnsRecord::read(&mut buffer), Err(DnsError::InvalidPointer(2))));
+    }
+
+    #[test]
+    fn dname_target_isnt_compressed() {
+        let record = DnsRecord::DNAME {
+            domain: "example.com".into(),
+            target: "example.net".to_string(),
+            class: DnsClass::IN,
+            ttl: 3600,
+        };
+        assert_eq!(round_trip(&record), record);
+
+        // A target pointing back at the owner is refused, not followed
+        let mut buffer = VectorPacketBuffer::new();
+        buffer.write_qname("example.com").unwrap();
+        for field in [QueryType::DNAME.to_num(), 1, 0, 3600, 6] {
+            buffer.write_u16(field).unwrap();
+        }
+        buffer.write_bytes(b"\x03www\xc0\x00").unwrap();
+        buffer.seek(0).unwrap();
+        assert!(matches!(DnsRecord::read(&mut buffer), Err(DnsError::InvalidPointer(0))));
+    }
+
+    #[test]
+    fn rp_and_afsdb_round_trip() {
+        let rp = DnsRecord::RP {
+            domain: "example.com".into(),
+            mbox: "admin.example.com".to_string(),
+            txt: "info.example.com".to_string(),
+            class: DnsClass::IN,
+            ttl: 3600,
+        };
+        let afsdb = DnsRecord::AFSDB {
+            domain: "example.com".into(),
+            subtype: 1,
+            host: "afsdb.example.com".to_string(),
+            class: DnsClass::IN,
+            ttl: 3600,
+        };
+
+        assert_eq!(round_trip(&rp), rp);
+        assert_eq!(round_trip(&afsdb), afsdb);
+    }
+
+    #[test]
+    fn rp_without_txt_points_at_the_root() {
+        let rp = DnsRecord::RP {
+            domain: "example.com".into(),
+            mbox: "admin.example.com".to_string(),
+            txt: String::new(),
+            class: DnsClass::IN,
+            ttl: 3600,
+        };
+
+        // The rdata ends in the mailbox's terminator and the root's single
+        // zero byte
+        let mut buffer = VectorPacketBuffer::new();
+        let len = rp.write(&mut buffer).unwrap();
+        assert_eq!(buffer.get_range(len - 6, 6).unwrap(), b"\x03com\x00\x00");
+
+        assert_eq!(round_trip(&rp), rp);
+    }
+
+    #[test]
+    fn wks_and_null_fixtures() {
+        // WKS of 192.0.2.1 for TCP with ports 21, 25 and 80
+        let wks = "0178 0000 0b00 0100 000e 1000 10c0 0002
+            0106 0000 0440 0000 0000 0000 80";
+        let mut buffer = VectorPacketBuffer::from_hex(wks).unwrap();
+        let record = DnsRecord::read(&mut buffer).unwrap();
+        match &record {
+            DnsRecord::WKS { addr, protocol, ports, .. } => {
+                assert_eq!(*addr, Ipv4Addr::new(192, 0, 2, 1));
+                assert_eq!(*protocol, 6);
+                assert_eq!(ports, &[21, 25, 80]);
+            }
+            _ => panic!("not a WKS record: {:?}", record),
+        }
+
+        let mut written = VectorPacketBuffer::new();
+        record.write(&mut written).unwrap();
+        assert_eq!(written.to_hex(), buffer.to_hex());
+
+        // NULL with three opaque bytes
+        let mut buffer = VectorPacketBuffer::from_hex("0178 0000 0a00 0100 000e 1000 0361 6263").unwrap();
+        let record = DnsRecord::read(&mut buffer).unwrap();
+        assert!(matches!(&record, DnsRecord::NULL { data, .. } if data == b"abc"));
+
+        let mut written = VectorPacketBuffer::new();
+        record.write(&mut written).unwrap();
+        assert_eq!(written.to_hex(), buffer.to_hex());
+    }
+
+    #[test]
+    fn wks_bitmap_beyond_the_last_port_is_an_error() {
+        // One byte more than it takes to cover port 65535
+        let mut bytes = vec![1, b'x', 0, 0, 11, 0, 1, 0, 0, 0, 60];
+        bytes.extend_from_slice(&(5u16 + 8193).to_be_bytes());
+        bytes.extend_from_slice(&[192, 0, 2, 1, 6]);
+        bytes.resize(bytes.len() + 8193, 0xff);
+
+        let mut buffer = VectorPacketBuffer::with_capacity(bytes.len());
+        buffer.buf.copy_from_slice(&bytes);
+        assert!(matches!(DnsRecord::read(&mut buffer), Err(DnsError::InvalidRecord(_))));
+
+        // Exactly enough for every port is fine
+        let record = DnsRecord::WKS {
+            domain: "x".into(),
+            addr: Ipv4Addr::new(192, 0, 2, 1),
+            protocol: 6,
+            ports: (0..=u16::MAX).collect(),
+            class: DnsClass::IN,
+            ttl: 60,
+        };
+        assert_eq!(round_trip(&record), record);
+    }
+
+    #[test]
+    fn txt_keeps_its_strings_apart() {
+        // "abc" "def" followed by a string which isn't UTF-8
+        let mut buffer = VectorPacketBuffer::from_hex(
+            "0765 7861 6d70 6c65 0363 6f6d 0000 1000
+            0100 000e 1000 0b03 6162 6303 6465 6602
+            ff00",
+        )
+        .unwrap();
+        let record = DnsRecord::read(&mut buffer).unwrap();
+
+        match &record {
+            DnsRecord::TXT { strings, .. } => assert_eq!(strings, &[b"abc".to_vec(), b"def".to_vec(), vec![0xff, 0]]),
+            _ => panic!("not a TXT record: {:?}", record),
+        }
+        assert_eq!(record.text().unwrap(), "abcdef\u{fffd}\0");
+        assert!(record.to_string().ends_with("TXT\t\"abc\" \"def\" \"\\255\\000\""), "{}", record);
+
+        let mut written = VectorPacketBuffer::new();
+        let len = record.write(&mut written).unwrap();
+        assert_eq!(written.get_range(0, len).unwrap(), buffer.get_range(0, buffer.buf.len()).unwrap());
+    }
+
+    #[test]
+    fn spf_round_trip() {
+        let policy = format!("v=spf1 {}-all", "include:_spf.example.com ".repeat(12));
+        let record = DnsRecord::SPF {
+            domain: "example.com".into(),
+            strings: DnsRecord::text_strings(&policy),
+            class: DnsClass::IN,
+            ttl: 3600,
+        };
+
+        let read = round_trip(&record);
+        assert_eq!(read, record);
+        assert_eq!(read.qtype(), QueryType::SPF);
+        assert_eq!(read.text(), Some(policy));
+    }
+
+    #[test]
+    fn every_query_type_maps_both_ways() {
+        for num in 0..=u16::MAX {
+            let qtype = QueryType::from_num(num);
+            assert_eq!(qtype.to_num(), num);
+
+            let name = qtype.to_string();
+            assert_eq!(name.parse::<QueryType>().unwrap(), qtype, "{}", name);
+            assert_eq!(name.to_lowercase().parse::<QueryType>().unwrap(), qtype, "{}", name);
+            assert_eq!(format!("TYPE{}", num).parse::<QueryType>().unwrap(), qtype);
+
+            // Only types we don't know fall back to the generic syntax
+            if !matches!(qtype, QueryType::UNKNOWN(_)) {
+                assert!(!name.starts_with("TYPE"), "{}", name);
+            }
+        }
+
+        assert!("BOGUS".parse::<QueryType>().is_err());
+        assert!("TYPE65536".parse::<QueryType>().is_err());
+    }
+
+    #[test]
+    fn hinfo_round_trip() {
+        let record = DnsRecord::HINFO {
+            domain: "host.example.com".into(),
+            cpu: "INTEL-386".to_string(),
+            os: "UNIX".to_string(),
+            class: DnsClass::IN,
+            ttl: 3600,
+        };
+
+        assert_eq!(round_trip(&record), record);
+    }
+
+    #[test]
+    fn hinfo_os_longer_than_rdata_is_an_error() {
+        // "INTEL" followed by an os claiming 32 bytes where only 4 remain
+        let mut buffer = VectorPacketBuffer::from_hex(
+            "0178 0000 0d00 0100 000e 1000 0b05 494e
+            5445 4c20 554e 4958 0000 0000 0000 0000
+            0000 0000 0000 0000 0000 0000 0000 0000
+            0000 0000 0000",
+        )
+        .unwrap();
+
+        assert!(matches!(DnsRecord::read(&mut buffer), Err(DnsError::InvalidRecord(_))));
+    }
+
+    #[test]
+    fn tlsa_round_trip() {
+        // The port and protocol become the leading labels (RFC 6698 section 3)
+        let name = format!("_{}._{}.{}", 443, "tcp", "example.com");
+        let query = crate::packets::DnsPacket::query(&name, QueryType::TLSA);
+        assert_eq!(query.questions[0].name, "_443._tcp.example.com");
+        assert_eq!(query.questions[0].qtype.to_num(), 52);
+
+        let record = DnsRecord::TLSA {
+            domain: Name::from(name.as_str()),
+            usage: 3,
+            selector: 1,
+            matching_type: 1,
+            cert_data: (0..32).collect(),
+            class: DnsClass::IN,
+            ttl: 3600,
+        };
+
+        let mut buffer = VectorPacketBuffer::new();
+        let len = record.write(&mut buffer).unwrap();
+
+        // The three fields and the association data make up the rdata
+        assert_eq!(buffer.buf[len - 37..len - 35], [0, 35]);
+        assert_eq!(buffer.buf[len - 35..len - 32], [3, 1, 1]);
+        assert_eq!(buffer.buf[len - 32..len], (0..32).collect::<Vec<u8>>()[..]);
+
+        buffer.seek(0).unwrap();
+        assert_eq!(DnsRecord::read(&mut buffer).unwrap(), record);
+    }
+
+    #[test]
+    fn naptr_round_trip() {
+        let record = DnsRecord::NAPTR {
+            domain: "example.com".into(),
+            order: 100,
+            preference: 10,
+            flags: "S".to_string(),
+            services: "SIP+D2U".to_string(),
+            regexp: String::new(),
+            replacement: "_sip._udp.example.com".to_string(),
+            class: DnsClass::IN,
+            ttl: 3600,
+        };
+        assert_eq!(round_trip(&record), record);
+
+        let mut buffer = VectorPacketBuffer::new();
+        let len = record.write(&mut buffer).unwrap();
+        let replacement = b"\x04_sip\x04_udp\x07example\x03com\x00";
+        assert_eq!(buffer.buf[len - replacement.len()..len], replacement[..]);
+
+        // A replacement pointing back at the owner is refused, not followed
+        let mut buffer = VectorPacketBuffer::new();
+        buffer.write_qname("example.com").unwrap();
+        for field in [QueryType::NAPTR.to_num(), 1, 0, 3600, 27, 100, 10] {
+            buffer.write_u16(field).unwrap();
+        }
+        buffer.write_bytes(b"\x01S\x07SIP+D2U\x00\x04_sip\x04_udp\xc0\x00").unwrap();
+        buffer.seek(0).unwrap();
+        assert!(matches!(DnsRecord::read(&mut buffer), Err(DnsError::InvalidPointer(0))));
+    }
+
+    #[test]
+    fn ds_with_a_sha256_digest() {
+        // The SHA-256 digest of dskey.example.com from RFC 4509 section 2.3
+        let digest = VectorPacketBuffer::from_hex(
+            "d4b7d520e7bb5f0f67674a0cceb1e3e0614b93c4f9e99b8383f6a1e4469da50a",
+        )
+        .unwrap()
+        .buf;
+        let record = DnsRecord::DS {
+            domain: "dskey.example.com".into(),
+            key_tag: 60485,
+            algorithm: 5,
+            digest_type: 2,
+            digest: digest.clone(),
+            class: DnsClass::IN,
+            ttl: 86400,
+        };
+        assert_eq!(round_trip(&record), record);
+
+        let mut buffer = VectorPacketBuffer::new();
+        let len = record.write(&mut buffer).unwrap();
+        assert_eq!(buffer.buf[len - 38..len - 32], [0, 36, 0xec, 0x45, 5, 2]);
+        assert_eq!(buffer.buf[len - 32..len], digest[..]);
+
+        // Too short to even hold the fixed fields
+        let mut buffer = VectorPacketBuffer::new();
+        buffer.write_qname("dskey.example.com").unwrap();
+        for field in [QueryType::DS.to_num(), 1, 1, 0x5180, 3] {
+            buffer.write_u16(field).unwrap();
+        }
+        buffer.write_bytes(&[0xec, 0x45, 5]).unwrap();
+        buffer.seek(0).unwrap();
+        assert!(matches!(DnsRecord::read(&mut buffer), Err(DnsError::InvalidRecord(_))));
+    }
+
+    #[test]
+    fn key_tag_of_rfc4034_sample() {
+        // The key of dskey.example.com from RFC 4034 section 5.4
+        let public_key = base64_decode(
+            "AQOeiiR0GOMYkDshWoSKz9XzfwJr1AYtsmx3TGkJaNXVbfi/2pHm822aJ5iI9BMzNXxeYCmZDRD99WYwYqUSdjMmmAphXdvx\
+            egXd/M5+X7OrzKBaMbCVdFLUUh6DhweJBjEVv5f2wwjM9XzcnOf+EPbtG9DMBmADjFDc2w/rljwvFw==",
+        )
+        .unwrap();
+        let record = DnsRecord::DNSKEY {
+            domain: "dskey.example.com".into(),
+            flags: 256,
+            protocol: 3,
+            algorithm: 5,
+            public_key: public_key.clone(),
+            class: DnsClass::IN,
+            ttl: 86400,
+        };
+
+        assert_eq!(record.key_tag(), Some(60485));
+        assert_eq!(round_trip(&record), record);
+
+        let mut buffer = VectorPacketBuffer::new();
+        let len = record.write(&mut buffer).unwrap();
+        let rdata_len = public_key.len() + 4;
+        assert_eq!(buffer.buf[len - rdata_len - 2..len - public_key.len()], [0, rdata_len as u8, 1, 0, 3, 5]);
+
+        // Only DNSKEY records have a key tag
+        let ds = DnsRecord::DS {
+            domain: "dskey.example.com".into(),
+            key_tag: 60485,
+            algorithm: 5,
+            digest_type: 1,
+            digest: vec![0; 20],
+            class: DnsClass::IN,
+            ttl: 86400,
+        };
+        assert_eq!(ds.key_tag(), None);
+    }
+
+    #[test]
+    fn loc_of_a_landmark() {
+        // The Statue of Liberty, 40 41 21.4 N 74 02 40.2 W at 93m
+        let record = DnsRecord::LOC {
+            domain: "liberty.example".into(),
+            version: 0,
+            size: 0x13,
+            horiz_pre: 0x16,
+            vert_pre: 0x13,
+            latitude: (1 << 31) + 146_481_400,
+            longitude: (1 << 31) - 266_560_200,
+            altitude: 10_009_300,
+            class: DnsClass::IN,
+            ttl: 3600,
+        };
+        assert_eq!(round_trip(&record), record);
+
+        let (latitude, longitude, altitude) = record.loc_position().unwrap();
+        assert!((latitude - 40.689278).abs() < 1e-6, "{}", latitude);
+        assert!((longitude + 74.044500).abs() < 1e-6, "{}", longitude);
+        assert_eq!(altitude, 93.0);
+        assert_eq!(record.loc_precision(), Some((10.0, 10000.0, 10.0)));
+
+        assert_eq!(
+            record.to_string(),
+            "liberty.example.\t3600\tIN\tLOC\t40 41 21.400 N 74 2 40.200 W 93.00m 10.00m 10000.00m 10.00m"
+        );
+    }
+}
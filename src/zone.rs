@@ -0,0 +1,207 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use crate::{
+    error::DnsError,
+    name::Name,
+    packets::DnsPacket,
+    record::{DnsClass, DnsRecord, QueryType},
+};
+
+/// The records of a zone we're authoritative for, keyed on owner name and
+/// type like the cache.
+#[derive(Clone, Debug, Default)]
+pub struct Zone {
+    records: HashMap<(Name, QueryType), Vec<DnsRecord>>,
+}
+
+impl Zone {
+    pub fn new() -> Zone {
+        Zone::default()
+    }
+
+    /// Read a zone file from disk, see `parse` for the format.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Zone, DnsError> {
+        Zone::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Parse a simplified zone file, with one record per line in the form
+    /// `name ttl [class] type rdata`. Names are always absolute, there are no
+    /// directives, and everything following a `;` is a comment. Supported
+    /// types are A, AAAA, NS, CNAME, MX and TXT.
+    pub fn parse(text: &str) -> Result<Zone, DnsError> {
+        let mut zone = Zone::new();
+
+        for (i, line) in text.lines().enumerate() {
+            let line = match line.split_once(';') {
+                Some((content, _)) => content,
+                None => line,
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let record = parse_line(line).map_err(|msg| DnsError::InvalidZone(i + 1, msg))?;
+            zone.insert(record);
+        }
+
+        Ok(zone)
+    }
+
+    pub fn insert(&mut self, record: DnsRecord) {
+        let key = (record.domain().clone(), record.qtype());
+        self.records.entry(key).or_default().push(record);
+    }
+
+    /// The records of the given name and type. When the name is an alias,
+    /// its CNAME record is returned instead, as it's the only record an
+    /// alias may have.
+    pub fn lookup(&self, qname: &str, qtype: QueryType) -> Option<&[DnsRecord]> {
+        let qname = Name::from(qname);
+
+        self.records
+            .get(&(qname.clone(), qtype))
+            .or_else(|| self.records.get(&(qname, QueryType::CNAME)))
+            .map(|records| records.as_slice())
+    }
+
+    /// An authoritative answer to a question about the zone, if it has
+    /// matching records.
+    pub fn answer(&self, qname: &str, qtype: QueryType) -> Option<DnsPacket> {
+        let records = self.lookup(qname, qtype)?;
+
+        let mut response = DnsPacket::new();
+        response.header.response = true;
+        response.header.authoritative_answer = true;
+        response.answers = records.to_vec();
+
+        Some(response)
+    }
+}
+
+fn parse_line(line: &str) -> Result<DnsRecord, String> {
+    let mut fields = line.split_whitespace();
+
+    let domain = field(&mut fields, "name")?.to_lowercase().parse::<Name>().map_err(|e| e.to_string())?;
+    let ttl = field(&mut fields, "ttl")?;
+    let ttl = ttl.parse::<u32>().map_err(|_| format!("Invalid ttl {:?}", ttl))?;
+
+    // Only the internet class makes sense here, so it may be left out
+    let mut qtype = field(&mut fields, "type")?;
+    if qtype.eq_ignore_ascii_case("IN") {
+        qtype = field(&mut fields, "type")?;
+    }
+    let qtype = qtype.parse::<QueryType>().map_err(|e| e.to_string())?;
+    let class = DnsClass::IN;
+
+    let name = |host: &str| host.trim_end_matches('.').to_lowercase();
+
+    let record = match qtype {
+        QueryType::A => {
+            let addr = field(&mut fields, "address")?;
+            DnsRecord::A {
+                domain,
+                addr: addr.parse().map_err(|_| format!("Invalid IPv4 address {:?}", addr))?,
+                class,
+                ttl,
+            }
+        }
+        QueryType::AAAA => {
+            let addr = field(&mut fields, "address")?;
+            DnsRecord::AAAA {
+                domain,
+                addr: addr.parse().map_err(|_| format!("Invalid IPv6 address {:?}", addr))?,
+                class,
+                ttl,
+            }
+        }
+        QueryType::NS => DnsRecord::NS {
+            domain,
+            host: name(field(&mut fields, "host")?),
+            class,
+            ttl,
+        },
+        QueryType::CNAME => DnsRecord::CNAME {
+            domain,
+            host: name(field(&mut fields, "host")?),
+            class,
+            ttl,
+        },
+        QueryType::MX => {
+            let priority = field(&mut fields, "priority")?;
+            DnsRecord::MX {
+                domain,
+                priority: priority.parse().map_err(|_| format!("Invalid priority {:?}", priority))?,
+                host: name(field(&mut fields, "host")?),
+                class,
+                ttl,
+            }
+        }
+        QueryType::TXT => DnsRecord::TXT {
+            domain,
+            strings: txt_strings(&fields.collect::<Vec<_>>().join(" "))?,
+            class,
+            ttl,
+        },
+        _ => return Err(format!("Unsupported record type {}", qtype)),
+    };
+
+    Ok(record)
+}
+
+fn field<'a>(fields: &mut impl Iterator<Item = &'a str>, what: &str) -> Result<&'a str, String> {
+    fields.next().ok_or_else(|| format!("Missing {}", what))
+}
+
+/// The character strings of TXT rdata, each one in quotes. Without any
+/// quotes the rest of the line is taken as a single text.
+fn txt_strings(text: &str) -> Result<Vec<Vec<u8>>, String> {
+    if !text.contains('"') {
+        return Ok(DnsRecord::text_strings(text));
+    }
+
+    let strings: Vec<Vec<u8>> = text.split('"').skip(1).step_by(2).map(|s| s.as_bytes().to_vec()).collect();
+    if strings.iter().any(|string| string.len() > 255) {
+        return Err("Character string exceeds 255 bytes".to_string());
+    }
+
+    Ok(strings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ZONE: &str = "; a small test zone
+example.test.     300 IN A     10.0.0.1
+example.test      300 MX 10    mail.example.test.
+www.example.test  60  CNAME    example.test   ; alias
+example.test      60  NS       ns1.example.test
+example.test      60  TXT      \"v=spf1 -all\" \"second\"
+";
+
+    #[test]
+    fn names_resolve_from_a_small_zone() {
+        let zone = Zone::parse(ZONE).unwrap();
+
+        let records = zone.lookup("Example.Test.", QueryType::A).unwrap();
+        assert!(matches!(records, [DnsRecord::A { addr, .. }] if addr.to_string() == "10.0.0.1"));
+
+        // An alias answers for every type with its CNAME
+        assert!(matches!(zone.lookup("www.example.test", QueryType::A).unwrap(), [DnsRecord::CNAME { .. }]));
+        assert!(zone.lookup("nope.test", QueryType::A).is_none());
+
+        let response = zone.answer("example.test", QueryType::MX).unwrap();
+        assert!(response.header.authoritative_answer);
+        assert!(matches!(&response.answers[..], [DnsRecord::MX { priority: 10, host, .. }] if host == "mail.example.test"));
+
+        let txt = zone.lookup("example.test", QueryType::TXT).unwrap();
+        assert!(matches!(txt, [DnsRecord::TXT { strings, .. }] if strings == &[b"v=spf1 -all".to_vec(), b"second".to_vec()]));
+    }
+
+    #[test]
+    fn bad_lines_are_errors() {
+        assert!(matches!(Zone::parse("x 300 IN A nope"), Err(DnsError::InvalidZone(1, _))));
+        assert!(matches!(Zone::parse("; comment\nx 300 IN SOA a b"), Err(DnsError::InvalidZone(2, _))));
+        assert!(matches!(Zone::parse("x 300 IN"), Err(DnsError::InvalidZone(1, _))));
+    }
+}